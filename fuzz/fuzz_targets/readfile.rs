@@ -0,0 +1,34 @@
+//! Fuzz numeric extraction of `readfile`, with `cargo fuzz run readfile`.
+//!
+//! Input is interpreted as whitespace-separated pairs of numeric literals, each pair giving one
+//! value and one group index of a JSON record. Only characters which may appear in JSON numbers
+//! are retained, so records always contain the requested variable and the group column, and
+//! `readfile` must never panic. Sums of squares of successfully read values, as used in
+//! standardisation, must never overflow to infinity.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use uamutations::read_write_file::readfile;
+
+fuzz_target!(|data: &[u8]| {
+    let text: String = String::from_utf8_lossy(data)
+        .chars()
+        .filter(|c| c.is_ascii_digit() || " .eE+-".contains(*c))
+        .collect();
+    let literals: Vec<&str> = text.split_whitespace().collect();
+    let records: Vec<String> = literals
+        .chunks_exact(2)
+        .map(|pair| format!(r#"{{"x": {}, "index": {}}}"#, pair[0], pair[1]))
+        .collect();
+    if records.is_empty() {
+        return;
+    }
+    let json = format!("[{}]", records.join(","));
+
+    let varnames = vec!["x".to_string()];
+    if let Ok(city) = readfile(json.into_bytes(), &varnames, records.len()) {
+        let sum_sq: f64 = city.values.iter().map(|x| x.powi(2)).sum();
+        assert!(sum_sq.is_finite());
+    }
+});
@@ -0,0 +1,260 @@
+//! Optional on-disk caching of parsed city data, to avoid re-parsing large JSON files which are
+//! read repeatedly.
+
+use crate::error::UaError;
+use crate::read_write_file::{readfile, CityMatrix};
+use nalgebra::DMatrix;
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+const MAGIC: &[u8; 4] = b"UACM";
+const VERSION: u32 = 1;
+
+/// Path of the cache file for a given input file and set of variables.
+///
+/// The name of the cache file is a hash of the canonical path of the input file, its modification
+/// time and size, and the requested variables and number of entries. Modifying the input file
+/// therefore changes the path of the cache file, so that stale cache files are never read.
+///
+/// # Errors
+///
+/// Returns `UaError::Io` if the metadata of the input file can not be read.
+pub fn cache_path(
+    path: &Path,
+    varnames: &[String],
+    nentries: usize,
+    cache_dir: &Path,
+) -> Result<PathBuf, UaError> {
+    let canonical = fs::canonicalize(path)?;
+    let meta = fs::metadata(&canonical)?;
+    let mtime = meta
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+
+    let mut hasher = DefaultHasher::new();
+    canonical.hash(&mut hasher);
+    mtime.hash(&mut hasher);
+    meta.len().hash(&mut hasher);
+    varnames.hash(&mut hasher);
+    nentries.hash(&mut hasher);
+
+    Ok(cache_dir.join(format!("{:016x}.uacm", hasher.finish())))
+}
+
+/// Reads a JSON file as for `readfile`, using a binary cache of the parsed data in `cache_dir`.
+///
+/// If a cache file exists for the current version of the input file, the data are read from that
+/// file. Otherwise the input file is parsed, and the result written to the cache. The cache
+/// directory is created if it does not exist. Cache files which can not be read are ignored, and
+/// overwritten with freshly parsed data.
+///
+/// # Arguments
+///
+/// * `path` - Path to the JSON file to be read.
+/// * `varnames` - The names of the variables to be read from the JSON file.
+/// * `nentries` - The number of entries to be read from the JSON file.
+/// * `cache_dir` - Directory in which cache files are stored.
+///
+/// # Errors
+///
+/// Returns errors under the same conditions as `readfile`, or `UaError::Io` if the cache file
+/// can not be written.
+///
+/// # Example
+///
+/// ```
+/// use std::path::Path;
+/// use uamutations::cache::load_city_cached;
+/// use uamutations::read_write_file::readfile;
+/// let path = Path::new("./test_resources/dat1.json");
+/// let varnames = vec!["transport".to_string(), "bike_index".to_string()];
+/// let cache_dir = Path::new("/tmp/uamutations_cache_doctest");
+/// let city = load_city_cached(path, &varnames, 10, cache_dir).unwrap();
+/// assert_eq!(city, readfile("./test_resources/dat1.json", &varnames, 10).unwrap());
+/// ```
+pub fn load_city_cached(
+    path: &Path,
+    varnames: &[String],
+    nentries: usize,
+    cache_dir: &Path,
+) -> Result<CityMatrix, UaError> {
+    let cache_file = cache_path(path, varnames, nentries, cache_dir)?;
+    if let Some(city) = fs::read(&cache_file).ok().and_then(|b| decode(&b)) {
+        return Ok(city);
+    }
+
+    let city = readfile(path.to_path_buf(), varnames, nentries)?;
+    fs::create_dir_all(cache_dir)?;
+    fs::write(&cache_file, encode(&city))?;
+    Ok(city)
+}
+
+/// Serialise a `CityMatrix` to bytes. All numbers are little-endian, and values are stored in
+/// column-major order.
+fn encode(city: &CityMatrix) -> Vec<u8> {
+    let mut buf = Vec::new();
+    buf.extend_from_slice(MAGIC);
+    buf.extend_from_slice(&VERSION.to_le_bytes());
+    buf.extend_from_slice(&(city.values.nrows() as u64).to_le_bytes());
+    buf.extend_from_slice(&(city.values.ncols() as u64).to_le_bytes());
+    buf.extend_from_slice(&(city.varnames.len() as u64).to_le_bytes());
+    for v in &city.varnames {
+        buf.extend_from_slice(&(v.len() as u64).to_le_bytes());
+        buf.extend_from_slice(v.as_bytes());
+    }
+    for x in city.values.iter() {
+        buf.extend_from_slice(&x.to_le_bytes());
+    }
+    buf.extend_from_slice(&(city.groups.len() as u64).to_le_bytes());
+    for g in &city.groups {
+        buf.extend_from_slice(&(*g as u64).to_le_bytes());
+    }
+    buf.extend_from_slice(&(city.ids.len() as u64).to_le_bytes());
+    for id in &city.ids {
+        buf.extend_from_slice(&id.to_le_bytes());
+    }
+    buf
+}
+
+/// Cursor over bytes written by `encode`.
+struct Decoder<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        if self.bytes.len() < n {
+            return None;
+        }
+        let (head, tail) = self.bytes.split_at(n);
+        self.bytes = tail;
+        Some(head)
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        self.take(8)
+            .map(|b| u64::from_le_bytes(b.try_into().unwrap()))
+    }
+
+    fn len(&mut self) -> Option<usize> {
+        self.u64().and_then(|n| usize::try_from(n).ok())
+    }
+}
+
+/// Deserialise bytes written by `encode`, returning `None` if they are not a valid cache file.
+fn decode(bytes: &[u8]) -> Option<CityMatrix> {
+    let mut d = Decoder { bytes };
+    if d.take(4)? != MAGIC || d.take(4)? != VERSION.to_le_bytes() {
+        return None;
+    }
+    let nrows = d.len()?;
+    let ncols = d.len()?;
+
+    let nvars = d.len()?;
+    let mut varnames = Vec::with_capacity(nvars.min(ncols));
+    for _ in 0..nvars {
+        let n = d.len()?;
+        varnames.push(String::from_utf8(d.take(n)?.to_vec()).ok()?);
+    }
+
+    let nvalues = nrows.checked_mul(ncols)?;
+    let raw = d.take(nvalues.checked_mul(8)?)?;
+    let values: Vec<f64> = raw
+        .chunks_exact(8)
+        .map(|b| f64::from_le_bytes(b.try_into().unwrap()))
+        .collect();
+
+    let ngroups = d.len()?;
+    let groups = (0..ngroups)
+        .map(|_| d.len())
+        .collect::<Option<Vec<usize>>>()?;
+    let nids = d.len()?;
+    let ids = (0..nids)
+        .map(|_| d.take(8).map(|b| i64::from_le_bytes(b.try_into().unwrap())))
+        .collect::<Option<Vec<i64>>>()?;
+
+    if !d.bytes.is_empty() {
+        return None;
+    }
+
+    Some(CityMatrix {
+        values: DMatrix::from_vec(nrows, ncols, values),
+        varnames,
+        groups,
+        ids,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_city_cached() {
+        let path = Path::new("./test_resources/dat1.json");
+        let varnames = vec!["transport".to_string(), "bike_index".to_string()];
+        let cache_dir = Path::new("/tmp/uamutations_test_load_city_cached");
+        let _ = fs::remove_dir_all(cache_dir);
+
+        let city1 = load_city_cached(path, &varnames, 20, cache_dir).unwrap();
+        let cache_file = cache_path(path, &varnames, 20, cache_dir).unwrap();
+        assert!(cache_file.exists());
+        assert_eq!(decode(&fs::read(&cache_file).unwrap()), Some(city1.clone()));
+
+        // Second load returns identical data from the cache:
+        let city2 = load_city_cached(path, &varnames, 20, cache_dir).unwrap();
+        assert_eq!(city1, city2);
+
+        // Altering the cache file shows that it is read in preference to the input file:
+        let mut altered = city1.clone();
+        altered.values[(0, 0)] = -1.0;
+        fs::write(&cache_file, encode(&altered)).unwrap();
+        let city3 = load_city_cached(path, &varnames, 20, cache_dir).unwrap();
+        assert_eq!(city3, altered);
+
+        // Corrupt cache files are ignored and overwritten:
+        fs::write(&cache_file, b"not a cache file").unwrap();
+        let city4 = load_city_cached(path, &varnames, 20, cache_dir).unwrap();
+        assert_eq!(city4, city1);
+
+        // Different variables use a different cache file:
+        let varnames2 = vec!["transport".to_string()];
+        assert_ne!(
+            cache_path(path, &varnames2, 20, cache_dir).unwrap(),
+            cache_file
+        );
+
+        let _ = fs::remove_dir_all(cache_dir);
+    }
+
+    #[test]
+    fn test_cache_invalidated_on_modification() {
+        let src = "/tmp/uamutations_test_cache_invalidation.json";
+        let cache_dir = Path::new("/tmp/uamutations_test_cache_invalidation");
+        let _ = fs::remove_dir_all(cache_dir);
+        let varnames = vec!["transport".to_string()];
+
+        fs::write(src, r#"[{"transport": 1.0, "index": 1}]"#).unwrap();
+        let city1 = load_city_cached(Path::new(src), &varnames, 10, cache_dir).unwrap();
+        let cache1 = cache_path(Path::new(src), &varnames, 10, cache_dir).unwrap();
+
+        fs::write(
+            src,
+            r#"[{"transport": 2.0, "index": 1}, {"transport": 3.0, "index": 2}]"#,
+        )
+        .unwrap();
+        let cache2 = cache_path(Path::new(src), &varnames, 10, cache_dir).unwrap();
+        assert_ne!(cache1, cache2);
+        let city2 = load_city_cached(Path::new(src), &varnames, 10, cache_dir).unwrap();
+        assert_eq!(city1.values[(0, 0)], 1.0);
+        assert_eq!(city2.values[(0, 0)], 2.0);
+        assert_eq!(city2.nrows(), 2);
+
+        let _ = fs::remove_dir_all(cache_dir);
+        let _ = fs::remove_file(src);
+    }
+}
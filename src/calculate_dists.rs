@@ -1,8 +1,225 @@
-use nalgebra::DMatrix;
+use nalgebra::storage::RawStorage;
+use nalgebra::{DMatrix, DMatrixView, Dim, Matrix, Matrix1, U1};
+use std::sync::atomic::{self, AtomicBool};
 
+use crate::error::UaError;
+use crate::utils;
+
+/// Indices which sort a vector, and which restore sorted values to their original order, as
+/// returned from `get_ordering_index`.
 pub struct OrderingIndex {
-    index_sort: Vec<usize>,
-    index_reorder: Vec<usize>,
+    /// Indices of the original vector in sorted order.
+    pub index_sort: Vec<usize>,
+    /// Position of each element of the original vector within the sorted vector, and so also the
+    /// zero-based rank of each element.
+    pub index_reorder: Vec<usize>,
+}
+
+/// A strategy for matching entries of one set of values to the closest entries of another, and
+/// calculating the distances between each matched pair.
+///
+/// Implementors can be passed to `uamutate` to replace the default `GreedyMatcher`.
+pub trait Matcher {
+    /// Match each entry of `values1` to an entry of `values2`.
+    ///
+    /// # Arguments
+    ///
+    /// * `values1` - The values to be mutated, with the mutation variable in the first column.
+    /// * `values2` - The values of the mutation target, with the same structure as `values1`.
+    /// * `log_scale` - Whether the first columns of `values1` and `values2` are log-scaled.
+    ///
+    /// # Returns
+    ///
+    /// A matrix with one row for each row of `values1`, and two columns holding absolute and
+    /// relative differences to matched entries of `values2`, as for `calculate_dists`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `UaError::EmptyInput` if `values1` is empty.
+    fn match_dists(
+        &self,
+        values1: &DMatrix<f64>,
+        values2: &DMatrix<f64>,
+        log_scale: &bool,
+    ) -> Result<DMatrix<f64>, UaError>;
+
+    /// Match entries as for `match_dists`, stopping early if `cancel` is set.
+    ///
+    /// The default implementation checks `cancel` only before and after matching. Implementors
+    /// with long-running matching should override this to check `cancel` periodically.
+    ///
+    /// # Errors
+    ///
+    /// Returns `UaError::Cancelled` if `cancel` is set, or otherwise errors under the same
+    /// conditions as `match_dists`.
+    fn match_dists_cancellable(
+        &self,
+        values1: &DMatrix<f64>,
+        values2: &DMatrix<f64>,
+        log_scale: &bool,
+        cancel: Option<&AtomicBool>,
+    ) -> Result<DMatrix<f64>, UaError> {
+        check_cancelled(cancel)?;
+        let dists = self.match_dists(values1, values2, log_scale)?;
+        check_cancelled(cancel)?;
+        Ok(dists)
+    }
+
+    /// Match entries as for `match_dists_cancellable`, treating values within `eps` of one another
+    /// as equal.
+    ///
+    /// The default implementation ignores `eps`. Implementors which order or compare values should
+    /// override this, so that matching is not affected by floating-point jitter smaller than `eps`.
+    ///
+    /// # Errors
+    ///
+    /// Returns errors under the same conditions as `match_dists_cancellable`.
+    fn match_dists_with_tolerance(
+        &self,
+        values1: &DMatrix<f64>,
+        values2: &DMatrix<f64>,
+        log_scale: &bool,
+        eps: f64,
+        cancel: Option<&AtomicBool>,
+    ) -> Result<DMatrix<f64>, UaError> {
+        let _ = eps;
+        self.match_dists_cancellable(values1, values2, log_scale, cancel)
+    }
+
+    /// The full assignment of entries of `values1` to entries of `values2` made by `match_dists`,
+    /// as returned from `assignment_matrix`.
+    ///
+    /// The default implementation recovers the assignment from the distances returned by
+    /// `match_dists`, by pairing entries of `values1` in increasing order of their matched values
+    /// with entries of `values2` in increasing order of their values. This is exact for any
+    /// `Matcher` which matches entries one-to-one on the first column, except that entries of
+    /// `values2` with identical values may be interchanged.
+    ///
+    /// # Errors
+    ///
+    /// Returns errors under the same conditions as `match_dists`.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `values1` and `values2` have different numbers of rows.
+    fn match_assignment(
+        &self,
+        values1: &DMatrix<f64>,
+        values2: &DMatrix<f64>,
+        log_scale: &bool,
+    ) -> Result<Vec<(usize, usize, f64)>, UaError> {
+        assert_eq!(
+            values1.nrows(),
+            values2.nrows(),
+            "values1 and values2 must have the same number of rows."
+        );
+        let dists = self.match_dists(values1, values2, log_scale)?;
+        let scale = |x: f64| if *log_scale { 10f64.powf(x) } else { x };
+        let matched: Vec<f64> = (0..values1.nrows())
+            .map(|i| scale(values1[(i, 0)]) + dists[(i, 0)])
+            .collect();
+        let targets: Vec<f64> = values2.column(0).iter().cloned().collect();
+        let order_matched = get_ordering_index(&matched, false, false).index_reorder;
+        let order_targets = get_ordering_index(&targets, false, false).index_sort;
+
+        Ok(order_matched
+            .iter()
+            .enumerate()
+            .map(|(i, &rank)| (i, order_targets[rank], dists[(i, 0)]))
+            .collect())
+    }
+}
+
+/// Number of entries between successive checks of cancellation flags.
+const CANCEL_CHECK_INTERVAL: usize = 4096;
+
+/// Return `UaError::Cancelled` if `cancel` is set.
+pub(crate) fn check_cancelled(cancel: Option<&AtomicBool>) -> Result<(), UaError> {
+    match cancel {
+        Some(flag) if flag.load(atomic::Ordering::Relaxed) => Err(UaError::Cancelled),
+        _ => Ok(()),
+    }
+}
+
+/// The default `Matcher`, which sequentially and uniquely matches entries starting from the lowest
+/// values of `values1`, as implemented in `calculate_dists`.
+///
+/// Matching maps the sorted values of `values1` on to the sorted values of `values2` rank by rank,
+/// and so is isotonic: mutated values are monotone non-decreasing in the original values, so that
+/// higher original values are never mutated to lower values than lower original values.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct GreedyMatcher;
+
+impl Matcher for GreedyMatcher {
+    fn match_dists(
+        &self,
+        values1: &DMatrix<f64>,
+        values2: &DMatrix<f64>,
+        log_scale: &bool,
+    ) -> Result<DMatrix<f64>, UaError> {
+        calculate_dists(values1, values2, log_scale, 0)
+    }
+
+    fn match_dists_cancellable(
+        &self,
+        values1: &DMatrix<f64>,
+        values2: &DMatrix<f64>,
+        log_scale: &bool,
+        cancel: Option<&AtomicBool>,
+    ) -> Result<DMatrix<f64>, UaError> {
+        calculate_dists_cancellable(values1, values2, log_scale, 0, cancel)
+    }
+
+    fn match_dists_with_tolerance(
+        &self,
+        values1: &DMatrix<f64>,
+        values2: &DMatrix<f64>,
+        log_scale: &bool,
+        eps: f64,
+        cancel: Option<&AtomicBool>,
+    ) -> Result<DMatrix<f64>, UaError> {
+        dists_impl(
+            values1.as_view(),
+            values2.as_view(),
+            log_scale,
+            0,
+            RelativeBase::Source,
+            eps,
+            cancel,
+        )
+    }
+
+    fn match_assignment(
+        &self,
+        values1: &DMatrix<f64>,
+        values2: &DMatrix<f64>,
+        log_scale: &bool,
+    ) -> Result<Vec<(usize, usize, f64)>, UaError> {
+        if values1.is_empty() {
+            return Err(UaError::EmptyInput("values1".to_string()));
+        }
+        assert_eq!(
+            values1.shape(),
+            values2.shape(),
+            "values1 and values2 must have the same dimensions."
+        );
+
+        let values1_ref_var: Vec<f64> = values1.column(0).iter().cloned().collect();
+        let values2_ref_var: Vec<f64> = values2.column(0).iter().cloned().collect();
+        let sorting_order = ordering_with_tolerance(&values1_ref_var, crate::EPS, None)?;
+        let sorting_order2 = ordering_with_tolerance(&values2_ref_var, crate::EPS, None)?;
+
+        let scale = |x: f64| if *log_scale { 10f64.powf(x) } else { x };
+        Ok(sorting_order
+            .index_reorder
+            .iter()
+            .enumerate()
+            .map(|(i, &rank)| {
+                let j = sorting_order2.index_sort[rank];
+                (i, j, scale(values2_ref_var[j]) - scale(values1_ref_var[i]))
+            })
+            .collect())
+    }
 }
 
 /// Calculates a vector of sequential difference between two arrays of f64 values.
@@ -21,17 +238,39 @@ pub struct OrderingIndex {
 /// values used to find closest values of `values2`, and then the original order restored to yield
 /// the final desired matching.
 ///
+/// Relative distances are calculated as `(b - a) / |a|`, for reference values `a` from `values1`
+/// and matched values `b` from `values2`. Dividing by the absolute value ensures that the sign of
+/// relative distances is always the same as the sign of absolute distances, even for negative
+/// reference values such as may arise from standardisation. Relative distances are zero wherever
+/// reference values are (near) zero.
+///
+/// Matching is always done on the first column, while final distances between matched pairs are
+/// calculated on the column specified by `target_dim`. This allows mutations to be expressed in
+/// the units of any variable. Values of the first column within `EPS` of one another are treated
+/// as tied, and matched in their original order, so that matching is not affected by
+/// floating-point jitter. Use `GreedyMatcher::match_dists_with_tolerance` to specify a different
+/// tolerance.
+///
 /// # Arguments
 ///
 /// * `values1` - An Array2 object which provides the reference values against which to sort
-/// `values2`.
+///   `values2`.
 /// * `values2` - An Array2 object which is to be sorted against `values1`.
-/// * `absolute` - A boolean indicating whether to calculate absolute differences.
+/// * `log_scale` - A boolean indicating whether the `target_dim` columns are log-scaled, in which
+///   case they are transformed back to 10^x before calculating distances. This applies only to
+///   the `target_dim` columns, and not to the first columns on which matching is done, so where
+///   `target_dim` is not zero, this must describe the `target_dim` variable rather than the first
+///   variable.
+/// * `target_dim` - Index of the column on which distances are calculated.
+///
+/// # Errors
+///
+/// Returns `UaError::EmptyInput` if `values1` is empty.
 ///
 /// # Panics
 ///
-/// This function will panic if `values1` is empty or if `values1` and `values2` have different
-/// dimensions.
+/// This function will panic if `values1` and `values2` have different dimensions, or if
+/// `target_dim` is not a valid column index.
 ///
 /// # Returns
 ///
@@ -49,7 +288,7 @@ pub struct OrderingIndex {
 /// let values2 = vec![7.0, 9.0, 3.0, 2.0];
 /// let values2 = DMatrix::from_vec(4, 1, values2);
 /// let log_scale = false;
-/// let result = calculate_dists(&values1, &values2, &log_scale);
+/// let result = calculate_dists(&values1, &values2, &log_scale, 0).unwrap();
 /// // The first column of `result` contains the minimal absolute differences. Paired sequences are
 /// // (1, 2), (2, 3), (4, 7), (5, 9), with differences of (1, 1, 3, 4).
 /// let res_col0 = result.column(0).iter().cloned().collect::<Vec<f64>>();
@@ -59,33 +298,221 @@ pub struct OrderingIndex {
 /// let res_col1 = result.column(1).iter().cloned().collect::<Vec<f64>>();
 /// let res1 = vec![1.0, 0.5, 0.75, 0.8];
 /// assert_eq!(res_col1, res1);
+///
+/// // Where targets are below the values to be mutated, both absolute and relative distances are
+/// // negative. Paired sequences here are (4, 1) and (8, 2):
+/// let values1 = DMatrix::from_vec(2, 1, vec![8.0, 4.0]);
+/// let values2 = DMatrix::from_vec(2, 1, vec![1.0, 2.0]);
+/// let result = calculate_dists(&values1, &values2, &log_scale, 0).unwrap();
+/// assert_eq!(result.column(0).iter().cloned().collect::<Vec<f64>>(), vec![-6.0, -3.0]);
+/// assert_eq!(result.column(1).iter().cloned().collect::<Vec<f64>>(), vec![-0.75, -0.75]);
 /// ```
 pub fn calculate_dists(
     values1: &DMatrix<f64>,
     values2: &DMatrix<f64>,
     log_scale: &bool,
-) -> DMatrix<f64> {
-    assert!(!values1.is_empty(), "values1 must not be empty");
+    target_dim: usize,
+) -> Result<DMatrix<f64>, UaError> {
+    calculate_dists_relative_to(
+        values1,
+        values2,
+        log_scale,
+        target_dim,
+        RelativeBase::Source,
+    )
+}
+
+/// Calculates distances as for `calculate_dists`, with relative distances calculated against the
+/// denominator specified by `base`.
+///
+/// # Arguments
+///
+/// * `values1` - The reference values, as for `calculate_dists`.
+/// * `values2` - The values to be sorted against `values1`, as for `calculate_dists`.
+/// * `log_scale` - Whether the `target_dim` columns are log-scaled, as for `calculate_dists`.
+/// * `target_dim` - Index of the column on which distances are calculated.
+/// * `base` - The denominator of relative distances.
+///
+/// # Errors
+///
+/// Returns errors under the same conditions as `calculate_dists`.
+///
+/// # Panics
+///
+/// This function will panic under the same conditions as `calculate_dists`.
+///
+/// # Example
+///
+/// ```
+/// use nalgebra::DMatrix;
+/// use uamutations::calculate_dists::{calculate_dists_relative_to, RelativeBase};
+/// let values1 = DMatrix::from_vec(4, 1, vec![1.0, 2.0, 4.0, 5.0]);
+/// let values2 = DMatrix::from_vec(4, 1, vec![7.0, 9.0, 3.0, 2.0]);
+/// let result =
+///     calculate_dists_relative_to(&values1, &values2, &false, 0, RelativeBase::Target).unwrap();
+/// // Paired sequences are (1, 2), (2, 3), (4, 7), (5, 9), with relative differences of:
+/// let res_col1 = result.column(1).iter().cloned().collect::<Vec<f64>>();
+/// assert_eq!(res_col1, vec![1.0 / 2.0, 1.0 / 3.0, 3.0 / 7.0, 4.0 / 9.0]);
+/// ```
+pub fn calculate_dists_relative_to(
+    values1: &DMatrix<f64>,
+    values2: &DMatrix<f64>,
+    log_scale: &bool,
+    target_dim: usize,
+    base: RelativeBase,
+) -> Result<DMatrix<f64>, UaError> {
+    calculate_dists_view(
+        values1.as_view(),
+        values2.as_view(),
+        log_scale,
+        target_dim,
+        base,
+    )
+}
+
+/// Calculates distances as for `calculate_dists_relative_to`, from views of matrices rather than
+/// owned matrices.
+///
+/// This allows distances to be calculated for parts of larger matrices, such as selected rows or
+/// columns, without first copying them.
+///
+/// # Arguments
+///
+/// * `values1` - View of the reference values, as for `calculate_dists`.
+/// * `values2` - View of the values to be sorted against `values1`, as for `calculate_dists`.
+/// * `log_scale` - Whether the `target_dim` columns are log-scaled, as for `calculate_dists`.
+/// * `target_dim` - Index of the column on which distances are calculated.
+/// * `base` - The denominator of relative distances.
+///
+/// # Errors
+///
+/// Returns errors under the same conditions as `calculate_dists`.
+///
+/// # Panics
+///
+/// This function will panic under the same conditions as `calculate_dists`.
+///
+/// # Example
+///
+/// ```
+/// use nalgebra::DMatrix;
+/// use uamutations::calculate_dists::{calculate_dists, calculate_dists_view, RelativeBase};
+/// let values = DMatrix::from_vec(4, 2, vec![1.0, 2.0, 4.0, 5.0, 7.0, 9.0, 3.0, 2.0]);
+/// let (values1, values2) = (values.columns(0, 1), values.columns(1, 1));
+/// let result = calculate_dists_view(values1, values2, &false, 0, RelativeBase::Source).unwrap();
+/// let owned = calculate_dists(&values1.clone_owned(), &values2.clone_owned(), &false, 0);
+/// assert_eq!(result, owned.unwrap());
+/// ```
+pub fn calculate_dists_view(
+    values1: DMatrixView<'_, f64>,
+    values2: DMatrixView<'_, f64>,
+    log_scale: &bool,
+    target_dim: usize,
+    base: RelativeBase,
+) -> Result<DMatrix<f64>, UaError> {
+    dists_impl(
+        values1,
+        values2,
+        log_scale,
+        target_dim,
+        base,
+        crate::EPS,
+        None,
+    )
+}
+
+/// Calculates distances as for `calculate_dists`, checking `cancel` periodically and returning
+/// early if it is set, such as by another thread when a request is abandoned.
+///
+/// # Arguments
+///
+/// * `values1` - The reference values, as for `calculate_dists`.
+/// * `values2` - The values to be sorted against `values1`, as for `calculate_dists`.
+/// * `log_scale` - Whether the `target_dim` columns are log-scaled, as for `calculate_dists`.
+/// * `target_dim` - Index of the column on which distances are calculated.
+/// * `cancel` - Optional flag which, when set, cancels the calculation.
+///
+/// # Errors
+///
+/// Returns `UaError::Cancelled` if `cancel` is set before the calculation completes, or otherwise
+/// errors under the same conditions as `calculate_dists`.
+///
+/// # Panics
+///
+/// This function will panic under the same conditions as `calculate_dists`.
+///
+/// # Example
+///
+/// ```
+/// use nalgebra::DMatrix;
+/// use std::sync::atomic::AtomicBool;
+/// use uamutations::calculate_dists::calculate_dists_cancellable;
+/// use uamutations::error::UaError;
+/// let values1 = DMatrix::from_vec(4, 1, vec![1.0, 2.0, 4.0, 5.0]);
+/// let values2 = DMatrix::from_vec(4, 1, vec![7.0, 9.0, 3.0, 2.0]);
+/// let cancel = AtomicBool::new(true);
+/// let result = calculate_dists_cancellable(&values1, &values2, &false, 0, Some(&cancel));
+/// assert_eq!(result, Err(UaError::Cancelled));
+/// ```
+pub fn calculate_dists_cancellable(
+    values1: &DMatrix<f64>,
+    values2: &DMatrix<f64>,
+    log_scale: &bool,
+    target_dim: usize,
+    cancel: Option<&AtomicBool>,
+) -> Result<DMatrix<f64>, UaError> {
+    dists_impl(
+        values1.as_view(),
+        values2.as_view(),
+        log_scale,
+        target_dim,
+        RelativeBase::Source,
+        crate::EPS,
+        cancel,
+    )
+}
+
+/// Implementation of `calculate_dists_view`, treating values within `eps` as tied, and checking
+/// `cancel` periodically while sorting and while calculating distances.
+fn dists_impl(
+    values1: DMatrixView<'_, f64>,
+    values2: DMatrixView<'_, f64>,
+    log_scale: &bool,
+    target_dim: usize,
+    base: RelativeBase,
+    eps: f64,
+    cancel: Option<&AtomicBool>,
+) -> Result<DMatrix<f64>, UaError> {
+    if values1.is_empty() {
+        return Err(UaError::EmptyInput("values1".to_string()));
+    }
     assert_eq!(
         values1.shape(),
         values2.shape(),
         "values1 and values2 must have the same dimensions."
     );
+    assert!(
+        target_dim < values1.ncols(),
+        "target_dim must be less than the number of columns of values1"
+    );
 
     let values1_ref_var: Vec<f64> = values1.column(0).iter().cloned().collect();
     let values2_ref_var: Vec<f64> = values2.column(0).iter().cloned().collect();
 
-    let sorting_order = get_ordering_index(&values1_ref_var.to_vec(), false, false);
+    let sorting_order = ordering_with_tolerance(&values1_ref_var, eps, cancel)?;
+    let sorting_order2 = ordering_with_tolerance(&values2_ref_var, eps, cancel)?;
 
-    // Order values1_ref_var by sorting_order.index_sort:
+    // Order target columns of both by sorting orders of reference variables:
     let mut values1_sorted: Vec<f64> = sorting_order
         .index_sort
         .iter()
-        .map(|&i| values1_ref_var[i])
+        .map(|&i| values1[(i, target_dim)])
+        .collect();
+    let mut values2_sorted: Vec<f64> = sorting_order2
+        .index_sort
+        .iter()
+        .map(|&i| values2[(i, target_dim)])
         .collect();
-    // Sort values2_ref_var:
-    let mut values2_sorted = values2_ref_var.clone();
-    values2_sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
 
     if log_scale == &true {
         values1_sorted.iter_mut().for_each(|x| *x = 10f64.powf(*x));
@@ -93,23 +520,16 @@ pub fn calculate_dists(
     }
 
     // Calculate conseqcutive differences between the two vectors:
-    let differences_abs: Vec<f64> = values1_sorted
-        .iter()
-        .zip(values2_sorted.iter())
-        .map(|(&a, &b)| b - a)
-        .collect();
-    let eps = 1.0e-10;
-    let differences_rel: Vec<f64> = values1_sorted
-        .iter()
-        .zip(values2_sorted.iter())
-        .map(|(&a, &b)| {
-            if a <= eps || b <= eps {
-                0.0
-            } else {
-                (b - a) / a
-            }
-        })
-        .collect();
+    let mut differences_abs = Vec::with_capacity(values1_sorted.len());
+    let mut differences_rel = Vec::with_capacity(values1_sorted.len());
+    for (k, (&a, &b)) in values1_sorted.iter().zip(values2_sorted.iter()).enumerate() {
+        if k % CANCEL_CHECK_INTERVAL == 0 {
+            check_cancelled(cancel)?;
+        }
+        let (abs, rel) = pair_distance(a, b, base);
+        differences_abs.push(abs);
+        differences_rel.push(rel);
+    }
     // And re-order those differences according to sorting_order.index_reorder, so they align with
     // the original order of `values1`:
     let differences_abs: Vec<f64> = sorting_order
@@ -123,12 +543,391 @@ pub fn calculate_dists(
         .map(|&i| differences_rel[i])
         .collect();
 
-    DMatrix::from_row_slice(
+    Ok(DMatrix::from_row_slice(
         2,
         differences_abs.len(),
         &[differences_abs, differences_rel].concat(),
     )
-    .transpose()
+    .transpose())
+}
+
+/// Calculates distances by which each entry of `values1` moves a fraction of the way towards the
+/// value of `values2` at the same quantile, without any matching.
+///
+/// The quantile of each entry is its zero-based rank in the first column of `values1`, divided by
+/// one less than the number of entries. Target values are then interpolated at that quantile
+/// between sorted values of the first column of `values2`, as for `stats::quantile`. Where both
+/// have the same number of entries, a `fraction` of 1 gives the same distances as
+/// `calculate_dists`. Distances are calculated on the first column only.
+///
+/// # Arguments
+///
+/// * `values1` - The values to be mutated, with the mutation variable in the first column.
+/// * `values2` - The values of the mutation target, with the same number of columns as `values1`,
+///   but any number of rows.
+/// * `log_scale` - Whether the first columns are log-scaled, in which case they are transformed
+///   back to 10^x before calculating distances.
+/// * `fraction` - The fraction of the distance to the target quantile by which values are moved.
+///
+/// # Errors
+///
+/// Returns `UaError::EmptyInput` if either `values1` or `values2` is empty, or
+/// `UaError::NonFinite` if the first column of either contains `NaN` or infinite values.
+///
+/// # Returns
+///
+/// A matrix with one row for each row of `values1`, and two columns holding absolute and
+/// relative differences, as for `calculate_dists`.
+///
+/// # Example
+///
+/// ```
+/// use nalgebra::DMatrix;
+/// use uamutations::calculate_dists::calculate_dists_toward_quantiles;
+/// let values1 = DMatrix::from_vec(3, 1, vec![4.0, 1.0, 2.0]);
+/// let values2 = DMatrix::from_vec(5, 1, vec![10.0, 2.0, 6.0, 4.0, 8.0]);
+/// // Quantiles of (1, 2, 4) are (0, 0.5, 1), with targets of (2, 6, 10):
+/// let result = calculate_dists_toward_quantiles(&values1, &values2, &false, 0.5).unwrap();
+/// assert_eq!(result.column(0).as_slice(), &[3.0, 0.5, 2.0]);
+/// ```
+pub fn calculate_dists_toward_quantiles(
+    values1: &DMatrix<f64>,
+    values2: &DMatrix<f64>,
+    log_scale: &bool,
+    fraction: f64,
+) -> Result<DMatrix<f64>, UaError> {
+    if values1.is_empty() {
+        return Err(UaError::EmptyInput("values1".to_string()));
+    }
+    if values2.is_empty() {
+        return Err(UaError::EmptyInput("values2".to_string()));
+    }
+    for (values, name) in [(values1, "values1"), (values2, "values2")] {
+        if !values.column(0).iter().all(|x| x.is_finite()) {
+            return Err(UaError::NonFinite(name.to_string()));
+        }
+    }
+
+    let scale = |x: f64| if *log_scale { 10f64.powf(x) } else { x };
+    let values1_ref_var: Vec<f64> = values1.column(0).iter().map(|&x| scale(x)).collect();
+    let mut values2_sorted: Vec<f64> = values2.column(0).iter().map(|&x| scale(x)).collect();
+    values2_sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+    let n1 = values1_ref_var.len();
+    let n2 = values2_sorted.len();
+    let ranks = get_ordering_index(&values1_ref_var, false, false).index_reorder;
+    let mut result = DMatrix::zeros(n1, 2);
+    for (i, &rank) in ranks.iter().enumerate() {
+        // Position in `values2_sorted` of the quantile of this entry:
+        let pos = if n1 > 1 {
+            (rank * (n2 - 1)) as f64 / (n1 - 1) as f64
+        } else {
+            (n2 - 1) as f64 / 2.0
+        };
+        let (lo, hi) = (pos.floor() as usize, pos.ceil() as usize);
+        let target =
+            values2_sorted[lo] + (pos - lo as f64) * (values2_sorted[hi] - values2_sorted[lo]);
+        let (abs, rel) = pair_distance(values1_ref_var[i], target, RelativeBase::Source);
+        result[(i, 0)] = fraction * abs;
+        result[(i, 1)] = fraction * rel;
+    }
+
+    Ok(result)
+}
+
+/// The full assignment of entries of `values1` to entries of `values2` made by `matcher`, as a
+/// sparse list of matched pairs.
+///
+/// # Arguments
+///
+/// * `values1` - The values to be mutated, with the mutation variable in the first column.
+/// * `values2` - The values of the mutation target, with the same structure as `values1`.
+/// * `log_scale` - Whether the first columns are log-scaled, in which case they are transformed
+///   back to 10^x before calculating distances.
+/// * `matcher` - The `Matcher` which matches entries, through `Matcher::match_assignment`.
+///
+/// # Errors
+///
+/// Returns errors under the same conditions as `matcher`.
+///
+/// # Panics
+///
+/// This function will panic under the same conditions as `matcher`.
+///
+/// # Returns
+///
+/// One (row of `values1`, row of `values2`, absolute distance) triple for each row of `values1`,
+/// in the order of rows of `values1`. For matchers which match entries one-to-one, such as
+/// `GreedyMatcher`, each row of `values2` appears exactly once, and distances are identical to
+/// the first column returned from `matcher`.
+///
+/// # Example
+///
+/// ```
+/// use nalgebra::DMatrix;
+/// use uamutations::calculate_dists::{assignment_matrix, GreedyMatcher};
+/// let values1 = DMatrix::from_vec(4, 1, vec![1.0, 2.0, 4.0, 5.0]);
+/// let values2 = DMatrix::from_vec(4, 1, vec![7.0, 9.0, 3.0, 2.0]);
+/// let pairs = assignment_matrix(&values1, &values2, &false, &GreedyMatcher).unwrap();
+/// assert_eq!(pairs, vec![(0, 3, 1.0), (1, 2, 1.0), (2, 0, 3.0), (3, 1, 4.0)]);
+/// ```
+pub fn assignment_matrix(
+    values1: &DMatrix<f64>,
+    values2: &DMatrix<f64>,
+    log_scale: &bool,
+    matcher: &dyn Matcher,
+) -> Result<Vec<(usize, usize, f64)>, UaError> {
+    matcher.match_assignment(values1, values2, log_scale)
+}
+
+/// Matches a fixed set of values to a set of targets which may be extended over time, such as in
+/// interactive tools to which target records are added in batches.
+///
+/// Any number of targets may be held. Prior to matching, targets are resampled to the number of
+/// rows of `values1` with `utils::resample_by_rank`, so that the distribution of their first
+/// column is preserved, and then matched as by `calculate_dists`. Distances are retained until
+/// further targets are added, and only then recalculated.
+///
+/// # Example
+///
+/// ```
+/// use nalgebra::DMatrix;
+/// use uamutations::calculate_dists::IncrementalMatcher;
+/// let values1 = DMatrix::from_vec(4, 1, vec![1.0, 2.0, 4.0, 5.0]);
+/// let mut matcher = IncrementalMatcher::new(values1, false);
+/// matcher.add_targets(DMatrix::from_vec(2, 1, vec![3.0, 9.0]).as_view());
+/// assert_eq!(matcher.dists().unwrap().column(0).as_slice(), &[2.0, 1.0, 5.0, 4.0]);
+/// matcher.add_targets(DMatrix::from_vec(2, 1, vec![2.0, 7.0]).as_view());
+/// assert_eq!(matcher.dists().unwrap().column(0).as_slice(), &[1.0, 1.0, 3.0, 4.0]);
+/// ```
+#[derive(Clone, Debug)]
+pub struct IncrementalMatcher {
+    values1: DMatrix<f64>,
+    targets: DMatrix<f64>,
+    log_scale: bool,
+    dists: Option<DMatrix<f64>>,
+}
+
+impl IncrementalMatcher {
+    /// Create a matcher with no targets.
+    ///
+    /// # Arguments
+    ///
+    /// * `values1` - The values to be mutated, with the mutation variable in the first column.
+    /// * `log_scale` - Whether the first columns of `values1` and all targets are log-scaled, as
+    ///   for `calculate_dists`.
+    pub fn new(values1: DMatrix<f64>, log_scale: bool) -> Self {
+        let targets = DMatrix::zeros(0, values1.ncols());
+        IncrementalMatcher {
+            values1,
+            targets,
+            log_scale,
+            dists: None,
+        }
+    }
+
+    /// Append rows of `new` to the targets, so that distances are recalculated on the next call
+    /// to `dists`.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if `new` has a different number of columns to `values1`.
+    pub fn add_targets(&mut self, new: DMatrixView<'_, f64>) {
+        assert_eq!(
+            new.ncols(),
+            self.values1.ncols(),
+            "new targets must have the same number of columns as values1."
+        );
+        if new.nrows() == 0 {
+            return;
+        }
+        let n = self.targets.nrows();
+        let targets = &self.targets;
+        let combined = DMatrix::from_fn(n + new.nrows(), new.ncols(), |i, j| {
+            if i < n {
+                targets[(i, j)]
+            } else {
+                new[(i - n, j)]
+            }
+        });
+        self.targets = combined;
+        self.dists = None;
+    }
+
+    /// Number of targets added so far.
+    pub fn ntargets(&self) -> usize {
+        self.targets.nrows()
+    }
+
+    /// Absolute and relative distances from each row of `values1` to its matched target, as for
+    /// `calculate_dists`, recalculated only if targets have been added since the last call.
+    ///
+    /// # Errors
+    ///
+    /// Returns `UaError::EmptyInput` if either `values1` or the targets are empty.
+    pub fn dists(&mut self) -> Result<&DMatrix<f64>, UaError> {
+        if self.targets.is_empty() {
+            return Err(UaError::EmptyInput("targets".to_string()));
+        }
+        if self.dists.is_none() {
+            let nrows = self.values1.nrows();
+            if nrows == 0 {
+                return Err(UaError::EmptyInput("values1".to_string()));
+            }
+            let targets = utils::resample_by_rank(&self.targets, nrows);
+            let dists = calculate_dists(&self.values1, &targets, &self.log_scale, 0)?;
+            self.dists = Some(dists);
+        }
+        Ok(self.dists.as_ref().unwrap())
+    }
+}
+
+/// Denominator used to calculate relative distances between matched pairs of values.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RelativeBase {
+    /// Relative distances are `(b - a) / |a|`, for reference values `a` and matched values `b`.
+    /// These are the proportional changes required to mutate each original value into its target,
+    /// so that a relative distance of 1 means that a value has to be doubled.
+    #[default]
+    Source,
+    /// Relative distances are `(b - a) / |b|`. These are the proportions of each target value by
+    /// which the original value falls short of (or exceeds) it, so that a relative distance of 0.5
+    /// means that an original value is only half of its target.
+    Target,
+}
+
+/// Absolute and relative distances between one reference value and the value it is matched to.
+///
+/// This is the function used by `calculate_dists` to calculate distances between each matched
+/// pair, with magnitudes given by `multidim_distance` in one dimension, and so may be used to independently check the results of matching. Values must already
+/// be on the scale on which distances are calculated, so log-scaled values must first be
+/// transformed back to 10^x.
+///
+/// # Arguments
+///
+/// * `a` - The reference value, from `values1`.
+/// * `b` - The matched value, from `values2`.
+/// * `base` - The denominator of relative distances.
+///
+/// # Returns
+///
+/// A tuple of the absolute distance, `b - a`, and the relative distance, `(b - a)` divided by the
+/// absolute value of `a` or `b`, as determined by `base`. Dividing by absolute values ensures that
+/// relative distances always have the same sign as absolute distances. The relative distance is
+/// zero if the denominator is (near) zero.
+///
+/// # Example
+///
+/// ```
+/// use uamutations::calculate_dists::{pair_distance, RelativeBase};
+/// assert_eq!(pair_distance(2.0, 3.0, RelativeBase::Source), (1.0, 0.5));
+/// assert_eq!(pair_distance(-2.0, -3.0, RelativeBase::Source), (-1.0, -0.5));
+/// assert_eq!(pair_distance(0.0, 3.0, RelativeBase::Source), (3.0, 0.0));
+/// assert_eq!(pair_distance(2.0, 4.0, RelativeBase::Target), (2.0, 0.5));
+/// ```
+pub fn pair_distance(a: f64, b: f64, base: RelativeBase) -> (f64, f64) {
+    let dist = multidim_distance(&Matrix1::new(a), &Matrix1::new(b), Metric::Euclidean, None);
+    let abs = dist.copysign(b - a);
+    let denom = match base {
+        RelativeBase::Source => a.abs(),
+        RelativeBase::Target => b.abs(),
+    };
+    let rel = if denom <= crate::EPS {
+        0.0
+    } else {
+        abs / denom
+    };
+    (abs, rel)
+}
+
+/// Metric used to calculate distances between points by `multidim_distance`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Metric {
+    /// Square root of the sum of squared differences in each dimension.
+    #[default]
+    Euclidean,
+    /// Sum of absolute differences in each dimension.
+    Manhattan,
+    /// Largest absolute difference in any dimension.
+    Chebyshev,
+}
+
+/// Distance between two points, `a` and `b`, according to `metric`.
+///
+/// This is the single definition of distance used throughout the crate. Matching by
+/// `calculate_dists` is done on one column only, for which all metrics give the magnitude of the
+/// absolute distance returned by `pair_distance`, which is calculated with this function. Points
+/// may also be whole rows of matrices, so that matching may be checked in the full space of all
+/// variables.
+///
+/// # Arguments
+///
+/// * `a` - Coordinates of the first point, such as a row of `values1`.
+/// * `b` - Coordinates of the second point, such as a row of `values2`, with the same number of
+///   columns as `a`.
+/// * `metric` - The `Metric` used to calculate the distance.
+/// * `weights` - Optional non-negative weight of each dimension, by which each absolute
+///   difference is multiplied. If `None`, all dimensions have a weight of one.
+///
+/// # Panics
+///
+/// This function will panic if `a` and `b` have different numbers of columns, or if `weights`
+/// does not have one weight for each column.
+///
+/// # Example
+///
+/// ```
+/// use nalgebra::DMatrix;
+/// use uamutations::calculate_dists::{multidim_distance, Metric};
+/// let values = DMatrix::from_row_slice(2, 2, &[0.0, 0.0, 3.0, -4.0]);
+/// let (a, b) = (values.row(0), values.row(1));
+/// assert_eq!(multidim_distance(&a, &b, Metric::Euclidean, None), 5.0);
+/// assert_eq!(multidim_distance(&a, &b, Metric::Manhattan, None), 7.0);
+/// assert_eq!(multidim_distance(&a, &b, Metric::Chebyshev, Some(&[2.0, 1.0])), 6.0);
+/// ```
+pub fn multidim_distance<C1, C2, S1, S2>(
+    a: &Matrix<f64, U1, C1, S1>,
+    b: &Matrix<f64, U1, C2, S2>,
+    metric: Metric,
+    weights: Option<&[f64]>,
+) -> f64
+where
+    C1: Dim,
+    C2: Dim,
+    S1: RawStorage<f64, U1, C1>,
+    S2: RawStorage<f64, U1, C2>,
+{
+    assert!(
+        a.ncols() == b.ncols(),
+        "a and b must have the same number of columns"
+    );
+    if let Some(weights) = weights {
+        assert!(
+            weights.len() == a.ncols(),
+            "weights must have same length as a and b"
+        );
+    }
+
+    let diffs = || {
+        a.iter().zip(b.iter()).enumerate().map(|(i, (x, y))| {
+            let w = weights.map_or(1.0, |w| w[i]);
+            w * (y - x).abs()
+        })
+    };
+    match metric {
+        // Scaled by the largest difference, so that single dimensions give exact absolute
+        // differences, and squares can not overflow:
+        Metric::Euclidean => {
+            let max = diffs().fold(0.0, f64::max);
+            if max == 0.0 || !max.is_finite() {
+                max
+            } else {
+                max * diffs().map(|d| (d / max).powi(2)).sum::<f64>().sqrt()
+            }
+        }
+        Metric::Manhattan => diffs().sum(),
+        Metric::Chebyshev => diffs().fold(0.0, f64::max),
+    }
 }
 
 /// Returns a vector of indices that would sort the input vector in ascending or descending order.
@@ -139,6 +938,9 @@ pub fn calculate_dists(
 /// * `desc` - If `true`, sort in descending order; otherwise sort in ascensing order.
 /// * `is_abs` - A boolean indicating whether sorting should be based on absolute values.
 ///
+/// The sort is stable, so tied values retain their original relative order. This makes matching,
+/// and so all results of the mutation algorithm, deterministic.
+///
 /// # Example
 ///
 /// ```
@@ -163,11 +965,7 @@ pub fn get_ordering_index(vals: &[f64], desc: bool, is_abs: bool) -> OrderingInd
     }
 
     let index: Vec<_> = pairs.iter().map(|&(index, _)| index).collect();
-
-    let mut reorder_index = vec![0; index.len()];
-    for (i, &idx) in index.iter().enumerate() {
-        reorder_index[idx] = i;
-    }
+    let reorder_index = invert_permutation(&index);
 
     OrderingIndex {
         index_sort: index,
@@ -175,6 +973,112 @@ pub fn get_ordering_index(vals: &[f64], desc: bool, is_abs: bool) -> OrderingInd
     }
 }
 
+/// Ordering index of `vals` in ascending order, as for `get_ordering_index`, with runs of values
+/// within `eps` of the first value of each run treated as tied, and so kept in their original
+/// order. An `eps` of zero gives the same result as `get_ordering_index`.
+///
+/// If `cancel` is given, values are sorted in blocks which are then merged, checking `cancel`
+/// after every `CANCEL_CHECK_INTERVAL` entries, and returning `UaError::Cancelled` if it is set.
+fn ordering_with_tolerance(
+    vals: &[f64],
+    eps: f64,
+    cancel: Option<&AtomicBool>,
+) -> Result<OrderingIndex, UaError> {
+    let mut index_sort = match cancel {
+        None => get_ordering_index(vals, false, false).index_sort,
+        Some(_) => sort_index_cancellable(vals, cancel)?,
+    };
+    let mut start = 0;
+    while start < index_sort.len() {
+        let first = vals[index_sort[start]];
+        let mut end = start + 1;
+        while end < index_sort.len() && vals[index_sort[end]] - first <= eps {
+            end += 1;
+        }
+        index_sort[start..end].sort_unstable();
+        start = end;
+    }
+    let index_reorder = invert_permutation(&index_sort);
+
+    Ok(OrderingIndex {
+        index_sort,
+        index_reorder,
+    })
+}
+
+/// Indices which stably sort `vals` in ascending order, as for the `index_sort` field returned
+/// from `get_ordering_index`, checking `cancel` periodically.
+///
+/// Blocks of `CANCEL_CHECK_INTERVAL` entries are sorted individually, and then merged pairwise,
+/// with ties taken from the earlier block first so that the sort remains stable.
+fn sort_index_cancellable(
+    vals: &[f64],
+    cancel: Option<&AtomicBool>,
+) -> Result<Vec<usize>, UaError> {
+    let cmp = |a: &usize, b: &usize| vals[*a].partial_cmp(&vals[*b]).unwrap();
+    let n = vals.len();
+    let mut index: Vec<usize> = (0..n).collect();
+    for block in index.chunks_mut(CANCEL_CHECK_INTERVAL) {
+        check_cancelled(cancel)?;
+        block.sort_by(cmp);
+    }
+
+    let mut merged = Vec::with_capacity(n);
+    let mut width = CANCEL_CHECK_INTERVAL;
+    while width < n {
+        merged.clear();
+        for start in (0..n).step_by(2 * width) {
+            let mid = (start + width).min(n);
+            let end = (start + 2 * width).min(n);
+            let (mut i, mut j) = (start, mid);
+            while i < mid || j < end {
+                if merged.len() % CANCEL_CHECK_INTERVAL == 0 {
+                    check_cancelled(cancel)?;
+                }
+                if j == end || (i < mid && cmp(&index[j], &index[i]).is_ge()) {
+                    merged.push(index[i]);
+                    i += 1;
+                } else {
+                    merged.push(index[j]);
+                    j += 1;
+                }
+            }
+        }
+        std::mem::swap(&mut index, &mut merged);
+        width *= 2;
+    }
+
+    Ok(index)
+}
+
+/// Returns the inverse of a permutation, so that `perm[inv[i]] == i` for all `i`.
+///
+/// This converts the `index_sort` field of an `OrderingIndex` into the `index_reorder` field, and
+/// vice-versa.
+///
+/// # Arguments
+///
+/// * `perm` - A permutation of the integers `0..perm.len()`.
+///
+/// # Panics
+///
+/// This function will panic if `perm` contains any values greater than or equal to its length.
+///
+/// # Example
+///
+/// ```
+/// use uamutations::calculate_dists::invert_permutation;
+/// let perm = vec![3, 1, 0, 2];
+/// assert_eq!(invert_permutation(&perm), vec![2, 1, 3, 0]);
+/// ```
+pub fn invert_permutation(perm: &[usize]) -> Vec<usize> {
+    let mut inverse = vec![0; perm.len()];
+    for (i, &idx) in perm.iter().enumerate() {
+        inverse[idx] = i;
+    }
+    inverse
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -205,6 +1109,17 @@ mod tests {
         assert_eq!(oi.index_reorder, expected.index_reorder);
     }
 
+    #[test]
+    fn test_ordering_index_composition() {
+        let vals = vec![0.3, -2.0, 7.5, 1.0, -4.0, 2.2];
+        for (desc, is_abs) in [(false, false), (true, false), (false, true), (true, true)] {
+            let oi = get_ordering_index(&vals, desc, is_abs);
+            let composed: Vec<usize> = oi.index_reorder.iter().map(|&i| oi.index_sort[i]).collect();
+            assert_eq!(composed, (0..vals.len()).collect::<Vec<usize>>());
+            assert_eq!(invert_permutation(&oi.index_reorder), oi.index_sort);
+        }
+    }
+
     #[test]
     fn test_calculate_dists() {
         // Note that 2.0 is closest to 2.0, but is matched to 3.0 because of sequential and unique
@@ -213,7 +1128,7 @@ mod tests {
         let values2 = vec![7.0, 9.0, 3.0, 2.0];
         let values1 = DMatrix::from_vec(4, 1, values1);
         let values2 = DMatrix::from_vec(4, 1, values2);
-        let result = calculate_dists(&values1, &values2, &false);
+        let result = calculate_dists(&values1, &values2, &false, 0).unwrap();
         assert_eq!(result.ncols(), 2, "Result should have 2 columns");
         // First col has absolute differences:
         let res_col0 = result.column(0).iter().cloned().collect::<Vec<f64>>();
@@ -224,4 +1139,386 @@ mod tests {
         let res1 = vec![1.0, 0.5, 0.75, 0.8];
         assert_eq!(res_col1, res1);
     }
+
+    #[test]
+    fn test_calculate_dists_target_dim() {
+        // Second column is ten times the first, but in reverse order for `values2`:
+        let values1 = vec![1.0, 2.0, 4.0, 5.0, 10.0, 20.0, 40.0, 50.0];
+        let values2 = vec![7.0, 9.0, 3.0, 2.0, 20.0, 30.0, 70.0, 90.0];
+        let values1 = DMatrix::from_vec(4, 2, values1);
+        let values2 = DMatrix::from_vec(4, 2, values2);
+
+        // Matching on first column pairs (1, 2), (2, 3), (4, 7), (5, 9), which have second column
+        // values of (10, 90), (20, 70), (40, 20), (50, 30):
+        let result = calculate_dists(&values1, &values2, &false, 1).unwrap();
+        let res_col0 = result.column(0).iter().cloned().collect::<Vec<f64>>();
+        assert_eq!(res_col0, vec![80.0, 50.0, -20.0, -20.0]);
+        let res_col1 = result.column(1).iter().cloned().collect::<Vec<f64>>();
+        assert_eq!(res_col1, vec![8.0, 2.5, -0.5, -0.4]);
+
+        // While distances on first column are unchanged:
+        let result0 = calculate_dists(&values1, &values2, &false, 0).unwrap();
+        let res0 = result0.column(0).iter().cloned().collect::<Vec<f64>>();
+        assert_eq!(res0, vec![1.0, 1.0, 3.0, 4.0]);
+
+        // Log-scaling applies to the `target_dim` column only, with matching unchanged:
+        let mut values1_log = values1.clone();
+        let mut values2_log = values2.clone();
+        values1_log.column_mut(1).apply(|x| *x = x.log10());
+        values2_log.column_mut(1).apply(|x| *x = x.log10());
+        let result_log = calculate_dists(&values1_log, &values2_log, &true, 1).unwrap();
+        for (a, b) in result_log.iter().zip(result.iter()) {
+            assert!((a - b).abs() < 1e-10);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "target_dim must be less than the number of columns of values1")]
+    fn test_calculate_dists_invalid_target_dim() {
+        let values = DMatrix::from_vec(2, 1, vec![1.0, 2.0]);
+        calculate_dists(&values, &values, &false, 1).unwrap();
+    }
+
+    #[test]
+    fn test_calculate_dists_view() {
+        let values = DMatrix::from_vec(
+            6,
+            2,
+            vec![0.0, 1.0, 2.0, 4.0, 5.0, 0.0, 9.0, 7.0, 9.0, 3.0, 2.0, 9.0],
+        );
+        // Rows 1 to 4 of each column, as views into the larger matrix:
+        let values1 = values.view((1, 0), (4, 1));
+        let values2 = values.view((1, 1), (4, 1));
+        for base in [RelativeBase::Source, RelativeBase::Target] {
+            let result = calculate_dists_view(values1, values2, &false, 0, base).unwrap();
+            let owned = calculate_dists_relative_to(
+                &values1.clone_owned(),
+                &values2.clone_owned(),
+                &false,
+                0,
+                base,
+            )
+            .unwrap();
+            assert_eq!(result, owned);
+        }
+        let result = calculate_dists_view(values1, values2, &false, 0, RelativeBase::Source);
+        assert_eq!(result.unwrap().column(0).as_slice(), &[1.0, 1.0, 3.0, 4.0]);
+    }
+
+    #[test]
+    fn test_calculate_dists_empty() {
+        let empty = DMatrix::<f64>::zeros(0, 1);
+        let err = Err(UaError::EmptyInput("values1".to_string()));
+        assert_eq!(calculate_dists(&empty, &empty, &false, 0), err);
+        assert_eq!(GreedyMatcher.match_dists(&empty, &empty, &false), err);
+    }
+
+    #[test]
+    fn test_greedy_matcher_monotone() {
+        let values1 = vec![3.2, -1.0, 5.5, 0.4, 2.2, 9.1, -4.3, 0.4];
+        let values2 = vec![1.0, 8.0, -2.0, 4.5, 0.0, 3.3, 12.0, -7.5];
+        let n = values1.len();
+        let values1 = DMatrix::from_vec(n, 1, values1);
+        let values2 = DMatrix::from_vec(n, 1, values2);
+        let dists = GreedyMatcher
+            .match_dists(&values1, &values2, &false)
+            .unwrap();
+
+        let mut pairs: Vec<(f64, f64)> = (0..n)
+            .map(|i| (values1[(i, 0)], values1[(i, 0)] + dists[(i, 0)]))
+            .collect();
+        pairs.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        for w in pairs.windows(2) {
+            assert!(
+                w[1].1 >= w[0].1,
+                "Mutated values must be monotone in original values"
+            );
+        }
+        // Each target value is used exactly once:
+        let mut mutated: Vec<f64> = pairs.iter().map(|p| p.1).collect();
+        let mut targets: Vec<f64> = values2.iter().cloned().collect();
+        mutated.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        targets.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        for (m, t) in mutated.iter().zip(targets.iter()) {
+            assert!((m - t).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_calculate_dists_negative_reference() {
+        let values1 = DMatrix::from_vec(3, 1, vec![-2.0, 1.0, 0.0]);
+        let values2 = DMatrix::from_vec(3, 1, vec![-1.0, 3.0, -4.0]);
+        // Pairs are (-2, -4), (0, -1), (1, 3):
+        let result = calculate_dists(&values1, &values2, &false, 0).unwrap();
+        let res_abs = result.column(0).iter().cloned().collect::<Vec<f64>>();
+        assert_eq!(res_abs, vec![-2.0, 2.0, -1.0]);
+        // Relative distances retain signs of absolute distances, and are zero for zero reference:
+        let res_rel = result.column(1).iter().cloned().collect::<Vec<f64>>();
+        assert_eq!(res_rel, vec![-1.0, 2.0, 0.0]);
+    }
+
+    #[test]
+    fn test_multidim_distance() {
+        let points = DMatrix::from_row_slice(2, 3, &[1.0, 2.0, 3.0, 2.0, 0.0, 5.0]);
+        let (a, b) = (points.row(0), points.row(1));
+        assert_eq!(multidim_distance(&a, &b, Metric::Euclidean, None), 3.0);
+        assert_eq!(multidim_distance(&a, &b, Metric::Manhattan, None), 5.0);
+        assert_eq!(multidim_distance(&a, &b, Metric::Chebyshev, None), 2.0);
+
+        // Weights multiply differences in each dimension:
+        let w = [4.0, 0.5, 0.0];
+        let euclidean = multidim_distance(&a, &b, Metric::Euclidean, Some(&w));
+        assert!((euclidean - 17f64.sqrt()).abs() < 1e-12);
+        assert_eq!(multidim_distance(&a, &b, Metric::Manhattan, Some(&w)), 5.0);
+        assert_eq!(multidim_distance(&a, &b, Metric::Chebyshev, Some(&w)), 4.0);
+
+        // Distances of `calculate_dists` are those between rows of the `target_dim` columns of
+        // matched entries, in every metric:
+        let values1 = DMatrix::from_vec(4, 2, vec![1.0, 4.0, 2.0, 5.0, 10.0, 40.0, 20.0, 50.0]);
+        let values2 = DMatrix::from_vec(4, 2, vec![3.0, 2.0, 9.0, 7.0, 30.0, 20.0, 90.0, 70.0]);
+        // Entries are matched by rank of the first column, so that 1, 4, 2, 5 are matched to
+        // 2, 7, 3, 9, in rows 1, 3, 0, 2 of `values2`:
+        let matched = [1, 3, 0, 2];
+        for target_dim in 0..2 {
+            let result = calculate_dists(&values1, &values2, &false, target_dim).unwrap();
+            let (col1, col2) = (
+                values1.columns(target_dim, 1),
+                values2.columns(target_dim, 1),
+            );
+            for metric in [Metric::Euclidean, Metric::Manhattan, Metric::Chebyshev] {
+                for (i, &j) in matched.iter().enumerate() {
+                    let dist = multidim_distance(&col1.row(i), &col2.row(j), metric, None);
+                    assert_eq!(dist, result[(i, 0)].abs());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_pair_distance() {
+        let src = RelativeBase::Source;
+        assert_eq!(pair_distance(4.0, 5.0, src), (1.0, 0.25));
+        assert_eq!(pair_distance(4.0, 2.0, src), (-2.0, -0.5));
+        assert_eq!(pair_distance(-4.0, -2.0, src), (2.0, 0.5));
+        assert_eq!(pair_distance(1.0e-12, 2.0, src), (2.0 - 1.0e-12, 0.0));
+
+        // Distances from `calculate_dists` are those of each sorted pair:
+        let values1 = DMatrix::from_vec(4, 1, vec![5.0, -1.0, 2.0, 0.5]);
+        let values2 = DMatrix::from_vec(4, 1, vec![3.0, 8.0, -2.0, 1.0]);
+        let result = calculate_dists(&values1, &values2, &false, 0).unwrap();
+        let pairs = [(5.0, 8.0), (-1.0, -2.0), (2.0, 3.0), (0.5, 1.0)];
+        for (i, &(a, b)) in pairs.iter().enumerate() {
+            assert_eq!((result[(i, 0)], result[(i, 1)]), pair_distance(a, b, src));
+        }
+    }
+
+    #[test]
+    fn test_calculate_dists_relative_base() {
+        let values1 = DMatrix::from_vec(4, 1, vec![1.0, 2.0, 4.0, 5.0]);
+        let values2 = DMatrix::from_vec(4, 1, vec![7.0, 9.0, 3.0, 2.0]);
+        let source =
+            calculate_dists_relative_to(&values1, &values2, &false, 0, RelativeBase::Source)
+                .unwrap();
+        let target =
+            calculate_dists_relative_to(&values1, &values2, &false, 0, RelativeBase::Target)
+                .unwrap();
+
+        // Absolute distances do not depend on the base:
+        assert_eq!(source.column(0), target.column(0));
+        assert_eq!(
+            source,
+            calculate_dists(&values1, &values2, &false, 0).unwrap()
+        );
+
+        // Pairs are (1, 2), (2, 3), (4, 7), (5, 9):
+        let rel_source: Vec<f64> = source.column(1).iter().cloned().collect();
+        let rel_target: Vec<f64> = target.column(1).iter().cloned().collect();
+        assert_eq!(rel_source, vec![1.0, 0.5, 0.75, 0.8]);
+        assert_eq!(rel_target, vec![0.5, 1.0 / 3.0, 3.0 / 7.0, 4.0 / 9.0]);
+        // Relative distances against targets are always smaller for values which increase:
+        assert!(rel_source.iter().zip(&rel_target).all(|(s, t)| t < s));
+
+        // Zero targets give zero relative distances:
+        let values2 = DMatrix::from_vec(4, 1, vec![0.0, 9.0, 3.0, 2.0]);
+        let target =
+            calculate_dists_relative_to(&values1, &values2, &false, 0, RelativeBase::Target)
+                .unwrap();
+        assert_eq!(target[(0, 0)], -1.0);
+        assert_eq!(target[(0, 1)], 0.0);
+    }
+
+    #[test]
+    fn test_calculate_dists_relative_sign() {
+        // Mixture of positive and negative values, with targets both above and below sources:
+        let values1 = DMatrix::from_vec(6, 1, vec![-3.0, 5.0, 0.5, -0.2, 2.0, 8.0]);
+        let values2 = DMatrix::from_vec(6, 1, vec![-6.0, 1.0, 0.1, 4.0, -0.1, 12.0]);
+        for base in [RelativeBase::Source, RelativeBase::Target] {
+            let result = calculate_dists_relative_to(&values1, &values2, &false, 0, base).unwrap();
+            for i in 0..result.nrows() {
+                let (abs, rel) = (result[(i, 0)], result[(i, 1)]);
+                assert!(abs != 0.0 && rel != 0.0);
+                assert_eq!(abs.signum(), rel.signum(), "sign differs for entry {}", i);
+            }
+        }
+    }
+
+    #[test]
+    fn test_incremental_matcher() {
+        let values1 = DMatrix::from_fn(20, 2, |i, j| ((i * 7 + j * 3) % 11) as f64);
+        let values2 = DMatrix::from_fn(30, 2, |i, j| ((i * 5 + j) % 13) as f64 + 0.5);
+
+        let mut matcher = IncrementalMatcher::new(values1.clone(), false);
+        assert_eq!(
+            matcher.dists().unwrap_err(),
+            UaError::EmptyInput("targets".to_string())
+        );
+        matcher.add_targets(values2.rows(0, 10));
+        let partial = matcher.dists().unwrap().clone();
+        matcher.add_targets(values2.rows(10, 20));
+        assert_eq!(matcher.ntargets(), 30);
+        let updated = matcher.dists().unwrap().clone();
+        assert_ne!(partial, updated);
+
+        // Adding targets in batches gives the same result as a fresh run with all targets:
+        let mut fresh = IncrementalMatcher::new(values1.clone(), false);
+        fresh.add_targets(values2.as_view());
+        assert_eq!(fresh.dists().unwrap(), &updated);
+        let resampled = utils::resample_by_rank(&values2, values1.nrows());
+        let expected = calculate_dists(&values1, &resampled, &false, 0).unwrap();
+        assert_eq!(updated, expected);
+    }
+
+    #[test]
+    fn test_calculate_dists_cancellable() {
+        // Enough entries to sort in several blocks, which are then merged:
+        let n = 3 * CANCEL_CHECK_INTERVAL + 1;
+        let values1 = DMatrix::from_fn(n, 1, |i, _| ((i * 7919) % n) as f64);
+        let values2 = DMatrix::from_fn(n, 1, |i, _| ((i * 104_729) % n) as f64 + 0.5);
+        let vals: Vec<f64> = values1.column(0).iter().copied().collect();
+
+        // Unset flags give the same result as `calculate_dists`:
+        let cancel = AtomicBool::new(false);
+        let result = calculate_dists_cancellable(&values1, &values2, &false, 0, Some(&cancel));
+        assert_eq!(result, calculate_dists(&values1, &values2, &false, 0));
+        assert_eq!(
+            sort_index_cancellable(&vals, Some(&cancel)).unwrap(),
+            get_ordering_index(&vals, false, false).index_sort
+        );
+
+        // Set flags stop sorting, and so the whole calculation:
+        cancel.store(true, atomic::Ordering::Relaxed);
+        assert_eq!(
+            sort_index_cancellable(&vals, Some(&cancel)),
+            Err(UaError::Cancelled)
+        );
+        assert_eq!(
+            calculate_dists_cancellable(&values1, &values2, &false, 0, Some(&cancel)),
+            Err(UaError::Cancelled)
+        );
+        assert_eq!(
+            GreedyMatcher.match_dists_cancellable(&values1, &values2, &false, Some(&cancel)),
+            Err(UaError::Cancelled)
+        );
+    }
+
+    #[test]
+    fn test_calculate_dists_tolerance() {
+        // Tied values, and the same values with jitter smaller than `EPS`:
+        let n = 12;
+        let values1 = DMatrix::from_fn(n, 1, |i, _| (i % 3) as f64);
+        let jitter = |i: usize| if i.is_multiple_of(2) { 1.0e-12 } else { -1.0e-12 } * i as f64;
+        let jittered = DMatrix::from_fn(n, 1, |i, _| values1[(i, 0)] + jitter(i));
+        let values2 = DMatrix::from_fn(n, 1, |i, _| i as f64 * 10.0);
+
+        let exact = calculate_dists(&values1, &values2, &false, 0).unwrap();
+        let result = calculate_dists(&jittered, &values2, &false, 0).unwrap();
+        for i in 0..n {
+            assert!(
+                (result[(i, 0)] + jittered[(i, 0)] - exact[(i, 0)] - values1[(i, 0)]).abs() < 1e-9
+            );
+        }
+
+        // Without tolerance, jitter changes the order of tied values, and so the matching:
+        let strict = GreedyMatcher
+            .match_dists_with_tolerance(&jittered, &values2, &false, 0.0, None)
+            .unwrap();
+        assert!((0..n).any(|i| (strict[(i, 0)] - result[(i, 0)]).abs() > 1.0));
+    }
+
+    #[test]
+    fn test_calculate_dists_toward_quantiles_non_finite() {
+        let values1 = DMatrix::from_vec(3, 1, vec![4.0, 1.0, 2.0]);
+        let values2 = DMatrix::from_vec(3, 1, vec![10.0, f64::NAN, 6.0]);
+        assert_eq!(
+            calculate_dists_toward_quantiles(&values1, &values2, &false, 1.0),
+            Err(UaError::NonFinite("values2".to_string()))
+        );
+        let values1 = DMatrix::from_vec(3, 1, vec![4.0, f64::INFINITY, 2.0]);
+        assert_eq!(
+            calculate_dists_toward_quantiles(&values1, &values2, &false, 1.0),
+            Err(UaError::NonFinite("values1".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_assignment_matrix() {
+        let values1 = DMatrix::from_vec(
+            6,
+            2,
+            vec![0.3, 1.2, -0.5, 0.9, 2.0, 0.1, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0],
+        );
+        let values2 = DMatrix::from_vec(
+            6,
+            2,
+            vec![1.5, 0.2, 0.8, -1.0, 0.4, 2.5, 6.0, 5.0, 4.0, 3.0, 2.0, 1.0],
+        );
+        // Matcher which matches the lowest values to the highest targets:
+        struct ReverseMatcher;
+        impl Matcher for ReverseMatcher {
+            fn match_dists(
+                &self,
+                values1: &DMatrix<f64>,
+                values2: &DMatrix<f64>,
+                log_scale: &bool,
+            ) -> Result<DMatrix<f64>, UaError> {
+                let scale = |x: f64| if *log_scale { 10f64.powf(x) } else { x };
+                let ranks1 = get_ordering_index(values1.column(0).as_slice(), false, false);
+                let order2 = get_ordering_index(values2.column(0).as_slice(), true, false);
+                Ok(DMatrix::from_fn(values1.nrows(), 2, |i, j| {
+                    let b = values2[(order2.index_sort[ranks1.index_reorder[i]], 0)];
+                    let (abs, rel) =
+                        pair_distance(scale(values1[(i, 0)]), scale(b), RelativeBase::Source);
+                    if j == 0 {
+                        abs
+                    } else {
+                        rel
+                    }
+                }))
+            }
+        }
+
+        for log_scale in [false, true] {
+            let matchers: [&dyn Matcher; 2] = [&GreedyMatcher, &ReverseMatcher];
+            for matcher in matchers {
+                let pairs = assignment_matrix(&values1, &values2, &log_scale, matcher).unwrap();
+                assert_eq!(pairs.len(), values1.nrows());
+
+                // Valid one-to-one assignment:
+                let sources: Vec<usize> = pairs.iter().map(|p| p.0).collect();
+                assert_eq!(sources, (0..6).collect::<Vec<usize>>());
+                let mut targets: Vec<usize> = pairs.iter().map(|p| p.1).collect();
+                targets.sort();
+                assert_eq!(targets, (0..6).collect::<Vec<usize>>());
+
+                // Distances are those of the matcher, and between the assigned pairs:
+                let dists = matcher.match_dists(&values1, &values2, &log_scale).unwrap();
+                let scale = |x: f64| if log_scale { 10f64.powf(x) } else { x };
+                for (i, j, d) in pairs {
+                    assert_eq!(d, dists[(i, 0)]);
+                    let pair = scale(values2[(j, 0)]) - scale(values1[(i, 0)]);
+                    assert!((d - pair).abs() < 1e-12);
+                }
+            }
+        }
+    }
 }
@@ -0,0 +1,191 @@
+use std::fmt;
+
+/// Errors which may be returned from the mutation algorithm.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum UaError {
+    /// An input contains no records. Holds the name of the input.
+    EmptyInput(String),
+    /// An input contains values which are `NaN` or infinite. Holds the name of the input.
+    NonFinite(String),
+    /// An input has fewer observations than the algorithm requires.
+    InsufficientData {
+        /// Name of the input with too few observations.
+        name: String,
+        /// Number of observations in the input.
+        nobs: usize,
+        /// Minimum number of observations required.
+        min_nobs: usize,
+    },
+    /// A requested variable is not present in an input.
+    MissingVariable {
+        /// Name of the input.
+        name: String,
+        /// Name of the missing variable.
+        varname: String,
+    },
+    /// Columns which must be of equal length are not.
+    LengthMismatch {
+        /// Name of the column with the wrong length.
+        name: String,
+        /// Length of that column.
+        len: usize,
+        /// Length of the first column, which all others must match.
+        expected: usize,
+    },
+    /// An input could not be read or parsed.
+    Parse(String),
+    /// A file could not be opened, or an output could not be created or written. Holds the message
+    /// of the underlying I/O error.
+    Io(String),
+    /// The calculation was cancelled through a cancellation flag before it completed.
+    Cancelled,
+    /// A group number is too large for results to be returned with one row for every group.
+    GroupOutOfRange {
+        /// Name of the input.
+        name: String,
+        /// The largest group number in the input.
+        group: usize,
+        /// Largest group number allowed, `MAX_GROUP`.
+        max_group: usize,
+    },
+}
+
+impl fmt::Display for UaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UaError::EmptyInput(name) => write!(f, "{} contains no records", name),
+            UaError::NonFinite(name) => write!(f, "{} contains NaN or infinite values", name),
+            UaError::InsufficientData {
+                name,
+                nobs,
+                min_nobs,
+            } => write!(
+                f,
+                "{} has {} observations, but at least {} are needed",
+                name, nobs, min_nobs
+            ),
+            UaError::MissingVariable { name, varname } => {
+                write!(f, "{} does not contain variable '{}'", name, varname)
+            }
+            UaError::LengthMismatch {
+                name,
+                len,
+                expected,
+            } => write!(
+                f,
+                "column '{}' has length {}, but should have length {}",
+                name, len, expected
+            ),
+            UaError::Parse(msg) => write!(f, "{}", msg),
+            UaError::Io(msg) => write!(f, "I/O error: {}", msg),
+            UaError::Cancelled => write!(f, "calculation was cancelled"),
+            UaError::GroupOutOfRange {
+                name,
+                group,
+                max_group,
+            } => write!(
+                f,
+                "{} has group number {}, but at most {} are allowed; use `uamutate_map` for \
+                 sparse group numbers",
+                name, group, max_group
+            ),
+        }
+    }
+}
+
+impl std::error::Error for UaError {}
+
+impl From<std::io::Error> for UaError {
+    fn from(err: std::io::Error) -> Self {
+        UaError::Io(err.to_string())
+    }
+}
+
+/// Conditions encountered during a run of the mutation algorithm which do not prevent results from
+/// being calculated, but which may affect their interpretation.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Warning {
+    /// A variable was listed more than once, and only its first occurrence was used.
+    DuplicateVariable {
+        /// Name of the variable.
+        varname: String,
+    },
+    /// A variable has the same value for all entries of an input.
+    ZeroVariance {
+        /// Name of the input.
+        name: String,
+        /// Name of the variable.
+        varname: String,
+    },
+    /// Entries of an input had missing values, and were excluded from calculations.
+    MissingValues {
+        /// Name of the input.
+        name: String,
+        /// Number of entries with missing values.
+        nentries: usize,
+    },
+    /// The mutation target had fewer entries than the data to be mutated, so that entries were
+    /// either dropped or target entries reused, according to `MutationConfig::on_exhaustion`.
+    TargetExhausted {
+        /// Number of entries to be mutated.
+        nobs: usize,
+        /// Number of entries of the mutation target.
+        nobs_target: usize,
+    },
+    /// An extra variable was not present in both inputs, and was dropped from the analysis
+    /// because `MutationConfig::drop_missing_covariates` was set.
+    CovariateDropped {
+        /// Name of the variable.
+        varname: String,
+    },
+    /// The variable to be mutated had the same value for all entries of the mutation target, so
+    /// that all entries were mutated to that value without matching.
+    ConstantTarget {
+        /// Name of the variable.
+        varname: String,
+    },
+    /// Both inputs were identical after excluding entries with missing values, so that all
+    /// mutation distances are zero and matching was skipped.
+    IdenticalInputs,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Warning::DuplicateVariable { varname } => {
+                write!(f, "Variable '{}' was listed more than once", varname)
+            }
+            Warning::ZeroVariance { name, varname } => {
+                write!(
+                    f,
+                    "{} has the same value of '{}' for all entries",
+                    name, varname
+                )
+            }
+            Warning::MissingValues { name, nentries } => write!(
+                f,
+                "{} records of {} skipped due to missing values",
+                nentries, name
+            ),
+            Warning::TargetExhausted { nobs, nobs_target } => write!(
+                f,
+                "{} entries to be mutated, but only {} target entries",
+                nobs, nobs_target
+            ),
+            Warning::CovariateDropped { varname } => write!(
+                f,
+                "Extra variable '{}' is not present in both inputs, and was dropped",
+                varname
+            ),
+            Warning::ConstantTarget { varname } => write!(
+                f,
+                "Target values of '{}' are all identical, so all entries were mutated to that \
+                 value",
+                varname
+            ),
+            Warning::IdenticalInputs => {
+                write!(f, "Both inputs are identical, so no mutation is needed")
+            }
+        }
+    }
+}
@@ -2,86 +2,1034 @@
 //! Analyst](https://urbananalyst.city). The algorithm mutates selected properties for one city to
 //! become more like those of another selected city.
 
+use calculate_dists::Matcher;
+use error::{UaError, Warning};
 use nalgebra::DMatrix;
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::BufReader;
+use std::io::Write;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+pub mod cache;
 pub mod calculate_dists;
+pub mod error;
 pub mod mlr;
 pub mod read_write_file;
+pub mod stats;
 pub mod transform;
 pub mod utils;
 
+/// Minimum number of observations required in each input to calculate mutations.
+pub const MIN_NOBS: usize = 2;
+
+/// Largest group number accepted by `uamutate`, which returns one row for every group up to the
+/// largest. Inputs with larger group numbers, such as census identifiers, may be passed to
+/// `uamutate_map`, which aggregates only those groups which are present.
+pub const MAX_GROUP: usize = 1 << 24;
+
+/// Tolerance within which floating-point values are treated as equal, or as zero. This is used to
+/// detect constant variables, to detect tied values when matching, and to avoid dividing by values
+/// which are only non-zero because of floating-point jitter. The tolerance used by `uamutate` for
+/// the first two of these may be changed with `MutationConfig::eps`.
+pub const EPS: f64 = 1.0e-10;
+
+/// Policy for handling target data with fewer entries than the data to be mutated, in which case
+/// matching would otherwise exhaust all target entries.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Exhaustion {
+    /// Stop matching once all target entries have been used, so that only as many entries are
+    /// mutated as there are target entries.
+    #[default]
+    Stop,
+    /// Return an error.
+    Error,
+    /// Reuse target entries, so that several entries may be matched to the same target entry.
+    /// Target entries are resampled at equally-spaced quantiles of the first variable, so each
+    /// entry is reused a similar number of times.
+    Reuse,
+}
+
+/// Policy for handling entries with missing (`NaN`) values of any variable.
+///
+/// Entries with missing values are never used in regression or matching. The policy determines
+/// only how they affect aggregated results.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NanPolicy {
+    /// Entries with missing values have `NaN` distances, and all aggregated results for any group
+    /// containing such entries are `NaN`. Results have one row for each group in the input data.
+    #[default]
+    Propagate,
+    /// Entries with missing values are dropped from both inputs. Results have one row for each
+    /// group up to the largest group number with any complete entries, and groups with no
+    /// complete entries have results of zero.
+    Skip,
+}
+
+/// Method used to calculate the distance by which each entry is mutated.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum MutationMode {
+    /// Match entries to entries of the mutation target with the `Matcher` passed to `uamutate`.
+    #[default]
+    Match,
+    /// Move each entry a fraction of the way towards the value of the mutation target at the
+    /// same quantile, with `calculate_dists::calculate_dists_toward_quantiles`. This avoids
+    /// matching entirely, and so ignores the `Matcher`. A `fraction` of 1 gives the same results
+    /// as the default `GreedyMatcher`, and smaller fractions give proportionally smaller
+    /// mutations.
+    TowardQuantileMean {
+        /// Fraction of the distance to the target quantile by which entries are moved.
+        fraction: f64,
+    },
+}
+
+/// Options controlling the mutation algorithm.
+#[derive(Clone, Debug, Default)]
+pub struct MutationConfig {
+    /// How to handle target data with fewer entries than the data to be mutated.
+    pub on_exhaustion: Exhaustion,
+    /// Names of variables to be log-transformed. If `None`, variables named in `utils::LOG_VARS`
+    /// are log-transformed.
+    pub log_vars: Option<Vec<String>>,
+    /// Transform applied to aggregated mutation values before they are returned.
+    pub output: transform::OutputTransform,
+    /// Method used to scale both inputs prior to adjustment and matching, applied after any
+    /// log-transformation. The default of `ScaleMode::None` keeps values in their original units,
+    /// so that absolute differences are in the units of the first variable. In that case,
+    /// `mlr::adj_for_beta` combines regression coefficients calculated from both inputs, which
+    /// are only comparable if variables in both inputs are measured on the same scales.
+    pub scale: read_write_file::ScaleMode,
+    /// Fixed statistics used to standardise both inputs, in which case `scale` is ignored. These
+    /// must be calculated from values after any log-transformation, and allow results from
+    /// several cities standardised against one baseline to be compared directly.
+    pub fixed_scaling: Option<read_write_file::Scaling>,
+    /// If true, record the time spent in each phase of the algorithm, and return in the `timings`
+    /// field of `MutationDebug`.
+    pub instrument: bool,
+    /// How to handle entries with missing values.
+    pub nan_policy: NanPolicy,
+    /// Denominator of relative differences. With the default of `RelativeBase::Source`,
+    /// relative differences are those returned by the `Matcher`; otherwise they are recalculated
+    /// from the absolute differences.
+    pub relative_base: calculate_dists::RelativeBase,
+    /// If specified, target values of the first variable which lie within this distance of one
+    /// another are merged with `utils::merge_near_duplicates` prior to matching. The distance is
+    /// on the scale on which values are matched, after any log-transformation and scaling.
+    pub dedup_epsilon: Option<f64>,
+    /// If true, the first variable is not adjusted for its dependence on extra variables with
+    /// `mlr::adj_for_beta`. Mutations then describe differences between the raw distributions of
+    /// the first variable in each input, rather than differences remaining once the effects of the
+    /// extra variables in each input have been accounted for. Extra variables are still read, and
+    /// passed to the `Matcher`, but have no effect on matching with `GreedyMatcher`.
+    pub skip_covariate_adjustment: bool,
+    /// Names of variables for which higher values are worse, and the constants from which values
+    /// of each are subtracted to invert them, so that higher values are better for all variables,
+    /// and positive mutations always represent improvements. If `None`, variables in
+    /// `transform::INVERT_VARS` are inverted. Inversion is applied to raw values immediately after
+    /// reading, before log-transformation of any of `log_vars`, scaling, and adjustment for extra
+    /// variables.
+    pub invert_vars: Option<Vec<(String, f64)>>,
+    /// Map of variable names to numeric values which represent missing values of that variable,
+    /// such as `-999`. These are converted to `NaN` when inputs are read, prior to any
+    /// log-transformation or standardisation, and then handled according to `nan_policy`.
+    pub missing_sentinels: HashMap<String, f64>,
+    /// Non-negative weight of each record of the first input, in the order in which records are
+    /// read, so with one weight for each of the first `nentries` records, or for all records if
+    /// there are fewer. If specified, weights are used in each of the following steps:
+    ///
+    /// 1. Pooled standardisation with `ScaleMode::ZScore`, through
+    ///    `read_write_file::standardise_arrays_weighted`. Other scale modes and `fixed_scaling`
+    ///    are unweighted.
+    /// 2. Regression of the first variable on extra variables of the first input, through
+    ///    `mlr::adj_for_beta_weighted`.
+    /// 3. Aggregation within groups, for which all results are weighted means.
+    ///
+    /// Matching is unweighted. Uniform weights give the same results as no weights.
+    pub weights: Option<Vec<f64>>,
+    /// Method used to calculate mutation distances.
+    pub mode: MutationMode,
+    /// If true, extra variables which are not present in both inputs are dropped with a
+    /// `Warning::CovariateDropped`, and the algorithm proceeds with those present in both.
+    /// Otherwise, all variables must be present in both inputs. The first variable is always
+    /// required.
+    pub drop_missing_covariates: bool,
+    /// Optional flag which may be set, such as by another thread, to cancel a run, which then
+    /// returns `UaError::Cancelled`. The flag is checked between phases of the algorithm, and
+    /// periodically during matching.
+    pub cancel: Option<Arc<AtomicBool>>,
+    /// Tolerance within which values are treated as equal. Values of the first variable within
+    /// this distance of one another are treated as tied when matching, and matched in the order
+    /// in which they were read, so that results are not affected by floating-point jitter smaller
+    /// than this. Variables are also treated as constant if all values lie within this distance.
+    /// The tolerance is on the scale on which values are matched. If `None`, `EPS` is used.
+    pub eps: Option<f64>,
+}
+
+/// Wall-clock time spent in each phase of the mutation algorithm.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Timings {
+    /// Reading both inputs, including any resampling and log-transformation.
+    pub read: Duration,
+    /// Scaling both inputs according to `MutationConfig::scale`.
+    pub standardise: Duration,
+    /// Adjusting for extra variables with `mlr::adj_for_beta`.
+    pub adj_for_beta: Duration,
+    /// Matching entries of both inputs, generally with `calculate_dists::calculate_dists`.
+    pub calculate_dists: Duration,
+    /// Aggregating results within groups.
+    pub aggregate: Duration,
+    /// Total time, including all phases.
+    pub total: Duration,
+}
+
 /// This is the main function, which reads data from two JSON files, calculates absolute and
 /// relative differences between the two sets of data, and writes the results to an output file.
 ///
 /// Some variables have to be log-transformed prior to any analytic routines. The names of these
-/// are defined in utils::log_transform.
+/// are by default those defined in `utils::LOG_VARS`, or may be specified in `config`.
 ///
 /// # Arguments
 ///
-/// * `fname1` - Path to local JSON file with data which are to be mutated.
-/// * `fname2` - Path to local JSON file with data of mutation target towards which first data are
-/// to be mutated.
-/// * `varname` - Name of variable in both `fname1` and `fname2` to be mutated.
-/// * `varextra` - Extra variables to be considered in the mutation.
-/// * `nentries` - The number of entries to be read from the JSON files.
+/// * `reader1` - Reader of data which are to be mutated, generally a
+///   `read_write_file::JsonReader`, or one returned from `read_write_file::reader_for_path`.
+/// * `reader2` - Reader of data of mutation target towards which first data are to be mutated.
+/// * `varnames` - Names of variables in both inputs, the first of which is to be mutated, and any
+///   others are extra variables to be considered in the mutation.
+/// * `nentries` - The number of entries to be read from each input.
+/// * `matcher` - The `Matcher` used to match entries of `reader1` to those of `reader2`; generally
+///   `calculate_dists::GreedyMatcher`.
+/// * `config` - A `MutationConfig` with options controlling the algorithm.
 ///
 /// # Returns
 ///
-/// A vector of length equal to number of distinct groups in the input data 'index' column, with
-/// each value quantifying the mean distance to the nearest points in the target distribution.
+/// A `DMatrix` with one row for each distinct group in the input data 'index' column, and four
+/// columns quantifying mean values within each group:
+/// 1. The original values of the first variable.
+/// 2. The mutated values of the first variable.
+/// 3. The absolute differences between mutated and original values.
+/// 4. The differences relative to the original values.
+///
+/// Both absolute and relative differences are always returned, so callers select whichever is
+/// appropriate. Absolute differences are in the units of the first variable, and so are not
+/// scale-free: they can not be compared between different variables, and will change if input
+/// data are standardised. Relative differences are scale-free, but are only meaningful for
+/// variables measured on a ratio scale with positive values.
 ///
 /// # Process
 ///
-/// 1. Reads the variable specified by `varname` from the files `fname1` and `fname2`.
-/// 2. Calculates the absolute and relative differences between the two sets of data.
-/// 3. Orders the relative differences in descending order.
+/// 1. Reads the variables specified by `varnames` from `reader1` and `reader2`, inverting the
+///    first variable if it is one for which higher values are worse, then log-transforming any
+///    variables which require it.
+/// 2. Adjusts the first variable for its dependence on any other variables.
+/// 3. Matches entries of the first data set to those of the second, and calculates absolute and
+///    relative differences between matched pairs.
+/// 4. Aggregates the results within each group.
 ///
-/// The following seven vectors of equal length are written to the output file:
-/// 1. values: The original values of 'varname' from 'fname1'.
-/// 2. dists: The relative degree by which each should be mutated.
+/// # Errors
+///
+/// Returns `UaError::InsufficientData` if `nentries`, or the number of entries in either input,
+/// is less than `MIN_NOBS`, or if `reader2` has fewer entries than `reader1` and
+/// `config.on_exhaustion` is `Exhaustion::Error`. Returns `UaError::EmptyInput` if either input
+/// contains no records, `UaError::MissingVariable` if either input does not contain one of
+/// `varnames`, `UaError::Io` if either input can not be read, `UaError::Parse` if either input can
+/// not be parsed, and `UaError::GroupOutOfRange` if any group number of `reader1` exceeds
+/// `MAX_GROUP`.
 ///
 /// # Panics
 ///
-/// This function will panic if the input files cannot be read, or if the output file cannot be written.
-
+/// Invalid inputs are reported as errors, so this function only panics if `matcher` panics.
 pub fn uamutate(
-    reader1: BufReader<File>,
-    reader2: BufReader<File>,
+    reader1: &dyn read_write_file::CityReader,
+    reader2: &dyn read_write_file::CityReader,
     varnames: &[String],
     nentries: usize,
-) -> DMatrix<f64> {
-    // Read contents of files:
-    let (mut values1, mut groups1) = read_write_file::readfile(reader1, varnames, nentries);
-    let (mut values2, _groups2) = read_write_file::readfile(reader2, varnames, nentries);
+    matcher: &dyn Matcher,
+    config: &MutationConfig,
+) -> Result<DMatrix<f64>, UaError> {
+    Ok(uamutate_debug(reader1, reader2, varnames, nentries, matcher, config)?.sums)
+}
+
+/// Intermediate results of the mutation algorithm, returned from `uamutate_debug`.
+#[derive(Clone, Debug)]
+pub struct MutationDebug {
+    /// Values to be mutated, after any inversion and log-transformation, but before adjustment
+    /// for extra variables. Entries with missing values are excluded from this and all following
+    /// matrices except `dists`.
+    pub values1: DMatrix<f64>,
+    /// Values to be mutated, after adjustment for extra variables by `mlr::adj_for_beta`. These
+    /// are identical to `values1` if `MutationConfig::skip_covariate_adjustment` is true.
+    pub values1_adjusted: DMatrix<f64>,
+    /// Values of the mutation target, after any inversion and log-transformation.
+    pub values2: DMatrix<f64>,
+    /// Regression coefficients of `values1`, with the intercept first, or empty if
+    /// `MutationConfig::skip_covariate_adjustment` is true.
+    pub beta1: Vec<f64>,
+    /// Regression coefficients of `values2`, with the intercept first, or empty if
+    /// `MutationConfig::skip_covariate_adjustment` is true.
+    pub beta2: Vec<f64>,
+    /// Absolute and relative distances for each entry, prior to aggregation. Rows are in the order
+    /// in which records were read, and so align with identifiers returned from
+    /// `read_write_file::readfile_with_ids`. With `NanPolicy::Propagate`, rows are included for
+    /// entries with missing values, with distances of `NaN`; with `NanPolicy::Skip`, only
+    /// complete entries are included.
+    pub dists: DMatrix<f64>,
+    /// Value of the first variable of the entry of `values2` matched to each row of `dists`, on
+    /// the scale on which distances are calculated, so that `matched_target - value == dist` for
+    /// the absolute distances in the first column of `dists`. Values are after adjustment and any
+    /// inversion by `transform::transform_invert_values_with`, and after reversing any
+    /// log-transformation.
+    pub matched_target: Vec<f64>,
+    /// Group of each row of `dists`.
+    pub groups: Vec<usize>,
+    /// Final aggregated results, as returned from `uamutate`. Row `i` holds results for group
+    /// `i + 1`.
+    pub sums: DMatrix<f64>,
+    /// Time spent in each phase, if `MutationConfig::instrument` is true.
+    pub timings: Option<Timings>,
+    /// Any conditions encountered during the run which may affect interpretation of results.
+    pub warnings: Vec<Warning>,
+}
+
+/// Run the same algorithm as `uamutate`, and return all intermediate results along with the final
+/// aggregated results.
+///
+/// This is intended for validating the algorithm against other implementations, and accepts the
+/// same arguments as `uamutate`.
+///
+/// # Returns
+///
+/// A `MutationDebug` object, the `sums` field of which is identical to the result of `uamutate`.
+///
+/// # Errors
+///
+/// Returns errors under the same conditions as `uamutate`.
+///
+/// # Panics
+///
+/// This function will panic under the same conditions as `uamutate`.
+pub fn uamutate_debug(
+    reader1: &dyn read_write_file::CityReader,
+    reader2: &dyn read_write_file::CityReader,
+    varnames: &[String],
+    nentries: usize,
+    matcher: &dyn Matcher,
+    config: &MutationConfig,
+) -> Result<MutationDebug, UaError> {
+    let start = Instant::now();
+    mutate(reader1, reader2, varnames, nentries, matcher, config, start)
+}
+
+/// Implementation of `uamutate_debug` for inputs read through any `CityReader`, with timings
+/// measured from `start`.
+fn mutate(
+    reader1: &dyn read_write_file::CityReader,
+    reader2: &dyn read_write_file::CityReader,
+    varnames: &[String],
+    nentries: usize,
+    matcher: &dyn Matcher,
+    config: &MutationConfig,
+    start: Instant,
+) -> Result<MutationDebug, UaError> {
+    check_nobs("nentries", nentries)?;
+
+    // Variables listed more than once would be read into duplicate columns:
+    let mut warnings = Vec::new();
+    let (varnames, duplicates) = dedup_varnames(varnames);
+    let varnames = &varnames;
+    warnings.extend(
+        duplicates
+            .into_iter()
+            .map(|varname| Warning::DuplicateVariable { varname }),
+    );
+
+    // Read contents of files, first dropping any extra variables not present in both if requested:
+    let kept_varnames = if config.drop_missing_covariates {
+        let (kept, dropped) = covariates_in_both(reader1, reader2, varnames)?;
+        warnings.extend(
+            dropped
+                .into_iter()
+                .map(|varname| Warning::CovariateDropped { varname }),
+        );
+        kept
+    } else {
+        varnames.to_vec()
+    };
+    let varnames = &kept_varnames;
+    let mut city1 = read_input(reader1, "reader1", varnames, nentries)?;
+    let mut city2 = read_input(reader2, "reader2", varnames, nentries)?;
+    read_write_file::replace_sentinels(&mut city1, &config.missing_sentinels);
+    read_write_file::replace_sentinels(&mut city2, &config.missing_sentinels);
+    if let Some(&group) = city1.groups.iter().max().filter(|&&g| g > MAX_GROUP) {
+        return Err(UaError::GroupOutOfRange {
+            name: "reader1".to_string(),
+            group,
+            max_group: MAX_GROUP,
+        });
+    }
+    let nrows1 = city1.values.nrows();
+    let mut groups1_all = city1.groups;
+    let weights1_all = config.weights.clone();
+    if let Some(weights) = &weights1_all {
+        if weights.len() != nrows1 {
+            return Err(UaError::LengthMismatch {
+                name: "weights".to_string(),
+                len: weights.len(),
+                expected: nrows1,
+            });
+        }
+    }
+
+    let eps = config.eps.unwrap_or(EPS);
+    for (values, name) in [(&city1.values, "reader1"), (&city2.values, "reader2")] {
+        warnings.extend(zero_variance_warnings(values, varnames, name, eps));
+    }
 
-    // Resize to smallest number of rows:
+    // Entries with missing values are excluded from all further calculations:
+    let mut complete1 = complete_rows(&city1.values);
+    let complete2 = complete_rows(&city2.values);
+    for (nrows, ncomplete, name) in [
+        (nrows1, complete1.len(), "reader1"),
+        (city2.values.nrows(), complete2.len(), "reader2"),
+    ] {
+        if ncomplete < nrows {
+            warnings.push(Warning::MissingValues {
+                name: name.to_string(),
+                nentries: nrows - ncomplete,
+            });
+        }
+    }
+    let mut values1 = city1.values.select_rows(&complete1);
+    let mut groups1: Vec<usize> = complete1.iter().map(|&i| groups1_all[i]).collect();
+    let mut weights1: Option<Vec<f64>> = weights1_all
+        .as_ref()
+        .map(|w| complete1.iter().map(|&i| w[i]).collect());
+    let mut values2 = city2.values.select_rows(&complete2);
+    check_nobs("reader1", values1.nrows())?;
+    check_nobs("reader2", values2.nrows())?;
+
+    // Invert values of variables for which higher values are worse, as specified in
+    // `transform::INVERT_VARS` or `config`. This is done on raw values, immediately after reading
+    // and before log-transformation, so variables which are both inverted and listed in `LOG_VARS`
+    // have the logarithm of their inverted values taken:
+    let invert_vars = config.invert_vars.clone().unwrap_or_else(|| {
+        transform::INVERT_VARS
+            .iter()
+            .map(|&(v, c)| (v.to_string(), c))
+            .collect()
+    });
+    transform::transform_invert_values_with(&mut values1, &city1.varnames[0], &invert_vars);
+    transform::transform_invert_values_with(&mut values2, &city2.varnames[0], &invert_vars);
+
+    // Identical inputs, such as the same file passed twice, need no mutation at all:
+    let identical = values1 == values2;
+    if identical {
+        warnings.push(Warning::IdenticalInputs);
+    }
+
+    // Resample target data if there are fewer entries than in `values1` and reuse is allowed, or
+    // otherwise resize to smallest number of rows:
+    if values2.nrows() < values1.nrows() {
+        warnings.push(Warning::TargetExhausted {
+            nobs: values1.nrows(),
+            nobs_target: values2.nrows(),
+        });
+        match config.on_exhaustion {
+            Exhaustion::Stop => {}
+            Exhaustion::Error => {
+                return Err(UaError::InsufficientData {
+                    name: "reader2".to_string(),
+                    nobs: values2.nrows(),
+                    min_nobs: values1.nrows(),
+                });
+            }
+            Exhaustion::Reuse => {
+                values2 = utils::resample_by_rank(&values2, values1.nrows());
+            }
+        }
+    }
     let nentries_actual = values1.nrows().min(values2.nrows());
     if nentries_actual < nentries {
         values1 = utils::resize_matrix(&values1, nentries_actual);
         values2 = utils::resize_matrix(&values2, nentries_actual);
         groups1.truncate(nentries_actual);
+        if let Some(weights) = weights1.as_mut() {
+            weights.truncate(nentries_actual);
+        }
+        if nentries_actual < complete1.len() {
+            complete1.truncate(nentries_actual);
+            groups1_all.truncate(complete1[nentries_actual - 1] + 1);
+        }
     }
 
-    let log_scale = utils::log_transform(&mut values1, varnames);
-    let _log_scale = utils::log_transform(&mut values2, varnames);
+    let log_vars = config
+        .log_vars
+        .clone()
+        .unwrap_or_else(|| utils::LOG_VARS.iter().map(|v| v.to_string()).collect());
+    let log_scale = utils::log_transform_vars(&mut values1, varnames, &log_vars)?;
+    let _log_scale = utils::log_transform_vars(&mut values2, varnames, &log_vars)?;
+    let read = start.elapsed();
+    let cancel = config.cancel.as_deref();
+    calculate_dists::check_cancelled(cancel)?;
 
-    // Adjust `values1` by removing its dependence on varextra, and replacing with the dependnece
-    // of values2 on same variables (but only if `varextra` are specified):
-    if values1.nrows() > 1 {
-        mlr::adj_for_beta(&mut values1, &values2);
+    let phase = Instant::now();
+    match &config.fixed_scaling {
+        Some(scaling) => {
+            read_write_file::apply_fixed_scaling(&mut values1, scaling);
+            read_write_file::apply_fixed_scaling(&mut values2, scaling);
+        }
+        None => match &weights1 {
+            Some(weights) if config.scale == read_write_file::ScaleMode::ZScore => {
+                read_write_file::standardise_arrays_weighted(&mut values1, &mut values2, weights)?;
+            }
+            _ => read_write_file::scale_arrays(&mut values1, &mut values2, config.scale)?,
+        },
     }
+    let standardise = phase.elapsed();
+    let values1_orig = values1.clone();
+    calculate_dists::check_cancelled(cancel)?;
 
-    // Invert values for variables specified in 'lookup_table' of 'transform.rs':
-    transform::transform_invert_values(&mut values1, &varnames[0]);
-    transform::transform_invert_values(&mut values2, &varnames[0]);
+    // Adjust `values1` by removing its dependence on varextra, and replacing with the dependnece
+    // of values2 on same variables (but only if `varextra` are specified). This and the preceding
+    // scaling are equivalent to `preprocess` in the default case:
+    let phase = Instant::now();
+    let (beta1, beta2) = match &weights1 {
+        _ if config.skip_covariate_adjustment => (Vec::new(), Vec::new()),
+        Some(weights) => mlr::adj_for_beta_weighted(&mut values1, &values2, weights)?,
+        None => mlr::adj_for_beta(&mut values1, &values2)?,
+    };
+    let adj_for_beta = phase.elapsed();
+    calculate_dists::check_cancelled(cancel)?;
+    let values1_adjusted = values1.clone();
 
     // Then calculate successive differences between the two sets of values. These are the
     // distances by which `values1` need to be moved in the first dimension only to match the
     // closest equivalent values of `values2`.
-    let dists = calculate_dists::calculate_dists(&values1, &values2, &log_scale);
-    aggregate_to_groups(&values1, &dists, &groups1, &log_scale)
+    let phase = Instant::now();
+    if let Some(epsilon) = config.dedup_epsilon {
+        utils::merge_near_duplicates(&mut values2, epsilon);
+    }
+    // A constant target needs no matching, as all entries would be matched to the same value:
+    let target = values2[(0, 0)];
+    let constant_target = !identical
+        && config.mode == MutationMode::Match
+        && values2.column(0).iter().all(|x| (x - target).abs() <= eps);
+    if constant_target {
+        warnings.push(Warning::ConstantTarget {
+            varname: varnames[0].clone(),
+        });
+    }
+    let mut dists = if identical {
+        DMatrix::zeros(values1.nrows(), 2)
+    } else if constant_target {
+        dists_to_constant(&values1, target, log_scale, config.relative_base)
+    } else {
+        match config.mode {
+            MutationMode::Match => {
+                matcher.match_dists_with_tolerance(&values1, &values2, &log_scale, eps, cancel)?
+            }
+            MutationMode::TowardQuantileMean { fraction } => {
+                calculate_dists::calculate_dists_toward_quantiles(
+                    &values1, &values2, &log_scale, fraction,
+                )?
+            }
+        }
+    };
+    if !identical
+        && !constant_target
+        && config.relative_base != calculate_dists::RelativeBase::Source
+    {
+        for i in 0..dists.nrows() {
+            let a = if log_scale {
+                10f64.powf(values1[(i, 0)])
+            } else {
+                values1[(i, 0)]
+            };
+            let (_, rel) =
+                calculate_dists::pair_distance(a, a + dists[(i, 0)], config.relative_base);
+            dists[(i, 1)] = rel;
+        }
+    }
+    let calculate_dists = phase.elapsed();
+    calculate_dists::check_cancelled(cancel)?;
+
+    // Reinsert entries with missing values as `NaN`, so that they propagate through aggregation:
+    if config.nan_policy == NanPolicy::Propagate && complete1.len() < nrows1 {
+        let nrows = groups1_all.len();
+        let mut values1_all = DMatrix::from_element(nrows, values1.ncols(), f64::NAN);
+        let mut dists_all = DMatrix::from_element(nrows, dists.ncols(), f64::NAN);
+        for (k, &i) in complete1.iter().enumerate() {
+            values1_all.set_row(i, &values1.row(k));
+            dists_all.set_row(i, &dists.row(k));
+        }
+        values1 = values1_all;
+        dists = dists_all;
+        weights1 = weights1_all.map(|mut w| {
+            w.truncate(groups1_all.len());
+            w
+        });
+        groups1 = groups1_all;
+    }
+
+    let matched_target: Vec<f64> = values1
+        .column(0)
+        .iter()
+        .zip(dists.column(0).iter())
+        .map(|(&a, &d)| if log_scale { 10f64.powf(a) } else { a } + d)
+        .collect();
+
+    let phase = Instant::now();
+    let mut sums = aggregate_to_groups(&values1, &dists, &groups1, &log_scale, weights1.as_deref());
+    transform::transform_output(&mut sums, config.output);
+    let aggregate = phase.elapsed();
+
+    let timings = config.instrument.then(|| Timings {
+        read,
+        standardise,
+        adj_for_beta,
+        calculate_dists,
+        aggregate,
+        total: start.elapsed(),
+    });
+
+    Ok(MutationDebug {
+        values1: values1_orig,
+        values1_adjusted,
+        values2,
+        beta1,
+        beta2,
+        dists,
+        matched_target,
+        groups: groups1,
+        sums,
+        timings,
+        warnings,
+    })
+}
+
+/// Run the mutation algorithm in both directions, mutating the first input towards the second, and
+/// the second towards the first.
+///
+/// The two results are generally not negatives of each other. Matching starts from the lowest
+/// values of the data being mutated, adjustment for extra variables uses the regression
+/// coefficients of each input in opposite roles, and results are aggregated within the groups of
+/// whichever input is being mutated.
+///
+/// # Arguments
+///
+/// * `path1` - Path to the first JSON file.
+/// * `path2` - Path to the second JSON file.
+///
+/// All other arguments are as for `uamutate`.
+///
+/// # Returns
+///
+/// A tuple of the results of `uamutate` for the first input mutated towards the second, and for
+/// the second mutated towards the first.
+///
+/// # Errors
+///
+/// Returns `UaError::Io` if either file can not be opened, or otherwise errors under the same
+/// conditions as `uamutate`.
+pub fn uamutate_symmetric(
+    path1: &str,
+    path2: &str,
+    varnames: &[String],
+    nentries: usize,
+    matcher: &dyn Matcher,
+    config: &MutationConfig,
+) -> Result<(DMatrix<f64>, DMatrix<f64>), UaError> {
+    let reader1 = read_write_file::JsonReader::Path(path1.into());
+    let reader2 = read_write_file::JsonReader::Path(path2.into());
+    let forward = uamutate(&reader1, &reader2, varnames, nentries, matcher, config)?;
+    let reverse = uamutate(&reader2, &reader1, varnames, nentries, matcher, config)?;
+
+    Ok((forward, reverse))
+}
+
+/// Run the same algorithm as `uamutate`, and return relative mutations for each group keyed by
+/// group number.
+///
+/// Group numbers are replaced with consecutive numbers with `CityMatrix::densify_groups` before
+/// aggregating, so unlike `uamutate`, they may be arbitrarily large and sparse.
+///
+/// # Arguments
+///
+/// All arguments are as for `uamutate`.
+///
+/// # Returns
+///
+/// A `HashMap` from each group number present in `reader1` to the relative difference between
+/// mutated and original values within that group, as in the fourth column of the `uamutate`
+/// result. Groups with no entries are not included, nor is group 0, the entries of which are
+/// discarded in aggregation.
+///
+/// # Errors
+///
+/// Returns errors under the same conditions as `uamutate`, except that group numbers are not
+/// limited to `MAX_GROUP`.
+///
+/// # Example
+///
+/// ```
+/// use uamutations::read_write_file::JsonReader;
+/// use uamutations::{calculate_dists::GreedyMatcher, uamutate_map, MutationConfig};
+/// let varnames = vec!["bike_index".to_string(), "natural".to_string()];
+/// let mutations = uamutate_map(
+///     &JsonReader::Path("./test_resources/dat1.json".into()),
+///     &JsonReader::Path("./test_resources/dat2.json".into()),
+///     &varnames,
+///     100,
+///     &GreedyMatcher,
+///     &MutationConfig::default(),
+/// )
+/// .unwrap();
+/// assert!(mutations.contains_key(&1));
+/// ```
+pub fn uamutate_map(
+    reader1: &dyn read_write_file::CityReader,
+    reader2: &dyn read_write_file::CityReader,
+    varnames: &[String],
+    nentries: usize,
+    matcher: &dyn Matcher,
+    config: &MutationConfig,
+) -> Result<HashMap<usize, f64>, UaError> {
+    let start = Instant::now();
+    let reader1 = DenseGroupReader {
+        reader: reader1,
+        labels: RefCell::default(),
+    };
+    let debug = mutate(
+        &reader1, reader2, varnames, nentries, matcher, config, start,
+    )?;
+    let labels = reader1.labels.into_inner();
+
+    let mut groups = debug.groups.clone();
+    groups.sort_unstable();
+    groups.dedup();
+    Ok(groups
+        .into_iter()
+        .filter(|&g| g != 0)
+        .map(|g| (labels[g - 1], debug.sums[(g - 1, 3)]))
+        .collect())
+}
+
+/// A `CityReader` which replaces group numbers with consecutive numbers, retaining the original
+/// numbers in `labels`.
+struct DenseGroupReader<'a> {
+    reader: &'a dyn read_write_file::CityReader,
+    labels: RefCell<Vec<usize>>,
+}
+
+impl read_write_file::CityReader for DenseGroupReader<'_> {
+    fn read(
+        &self,
+        varnames: &[String],
+        nentries: usize,
+    ) -> Result<read_write_file::CityMatrix, UaError> {
+        let mut city = self.reader.read(varnames, nentries)?;
+        *self.labels.borrow_mut() = city.densify_groups();
+        Ok(city)
+    }
+
+    fn varnames(&self) -> Result<Vec<String>, UaError> {
+        self.reader.varnames()
+    }
+}
+
+/// Scale two arrays onto common scales, and adjust the first column of `values1` for its
+/// dependence on any other columns, exactly as done by `uamutate` prior to matching.
+///
+/// This allows the statistical core of the algorithm to be combined with other matching
+/// routines. Arrays are modified in-place, and may then be passed to
+/// `calculate_dists::calculate_dists` or any other `Matcher`. Log-transformation, and inversion
+/// by `transform::transform_invert_values_with`, are not applied, and should be applied by the
+/// caller beforehand where needed, inverting before log-transforming, as in `uamutate`. Entries
+/// with missing values should also first be removed.
+///
+/// # Arguments
+///
+/// * `values1` - Values to be mutated, with the variable to be mutated in the first column, and
+///   any extra variables in remaining columns.
+/// * `values2` - Values of the mutation target, with the same columns as `values1`.
+/// * `scale` - The method used to scale both arrays, as for `MutationConfig::scale`.
+///
+/// # Returns
+///
+/// A tuple of the regression coefficients of `values1` and `values2`, each with the intercept
+/// first, as returned from `mlr::adj_for_beta`.
+///
+/// # Errors
+///
+/// Returns `UaError::InsufficientData` if either array has fewer than `MIN_NOBS` rows and `scale`
+/// is not `ScaleMode::None`, or `UaError::EmptyInput` if either array is empty.
+///
+/// # Panics
+///
+/// This function will panic if `values1` and `values2` have different numbers of columns.
+///
+/// # Example
+///
+/// ```
+/// use uamutations::calculate_dists::calculate_dists;
+/// use uamutations::preprocess;
+/// use uamutations::read_write_file::{readfile, ScaleMode};
+/// let varnames = vec!["bike_index".to_string(), "social_index".to_string()];
+/// let mut values1 = readfile("./test_resources/dat1.json", &varnames, 100)
+///     .unwrap()
+///     .values;
+/// let mut values2 = readfile("./test_resources/dat2.json", &varnames, 100)
+///     .unwrap()
+///     .values;
+/// let (beta1, beta2) = preprocess(&mut values1, &mut values2, ScaleMode::ZScore).unwrap();
+/// assert_eq!(beta1.len(), beta2.len());
+/// let dists = calculate_dists(&values1, &values2, &false, 0).unwrap();
+/// assert_eq!(dists.nrows(), 100);
+/// ```
+pub fn preprocess(
+    values1: &mut DMatrix<f64>,
+    values2: &mut DMatrix<f64>,
+    scale: read_write_file::ScaleMode,
+) -> Result<(Vec<f64>, Vec<f64>), UaError> {
+    read_write_file::scale_arrays(values1, values2, scale)?;
+    mlr::adj_for_beta(values1, values2)
+}
+
+/// Absolute and relative distances from the first column of `values1` to a single `target` value,
+/// as calculated by `calculate_dists::pair_distance`.
+fn dists_to_constant(
+    values1: &DMatrix<f64>,
+    target: f64,
+    log_scale: bool,
+    base: calculate_dists::RelativeBase,
+) -> DMatrix<f64> {
+    let scale = |x: f64| if log_scale { 10f64.powf(x) } else { x };
+    let pairs: Vec<(f64, f64)> = values1
+        .column(0)
+        .iter()
+        .map(|&a| calculate_dists::pair_distance(scale(a), scale(target), base))
+        .collect();
+    DMatrix::from_fn(
+        pairs.len(),
+        2,
+        |i, j| {
+            if j == 0 {
+                pairs[i].0
+            } else {
+                pairs[i].1
+            }
+        },
+    )
+}
+
+/// Rename any `EmptyInput` error to refer to the input called `name`.
+fn name_empty_input(e: UaError, name: &str) -> UaError {
+    match e {
+        UaError::EmptyInput(_) => UaError::EmptyInput(name.to_string()),
+        e => e,
+    }
+}
+
+/// Read one input, naming that input in any `EmptyInput` errors.
+fn read_input(
+    reader: &dyn read_write_file::CityReader,
+    name: &str,
+    varnames: &[String],
+    nentries: usize,
+) -> Result<read_write_file::CityMatrix, UaError> {
+    reader
+        .read(varnames, nentries)
+        .map_err(|e| name_empty_input(e, name))
+}
+
+/// Split `varnames` into those present in both inputs, and extra variables which are missing from
+/// either input. The first variable must be present in both.
+fn covariates_in_both(
+    reader1: &dyn read_write_file::CityReader,
+    reader2: &dyn read_write_file::CityReader,
+    varnames: &[String],
+) -> Result<(Vec<String>, Vec<String>), UaError> {
+    let mut available = Vec::new();
+    for (reader, name) in [(reader1, "reader1"), (reader2, "reader2")] {
+        let vars = reader.varnames().map_err(|e| name_empty_input(e, name))?;
+        if !varnames.is_empty() && !vars.contains(&varnames[0]) {
+            return Err(UaError::MissingVariable {
+                name: name.to_string(),
+                varname: varnames[0].clone(),
+            });
+        }
+        available.push(vars);
+    }
+
+    let (kept, dropped) = varnames
+        .iter()
+        .cloned()
+        .partition(|v| available.iter().all(|vars| vars.contains(v)));
+    Ok((kept, dropped))
+}
+
+/// Summary of the two inputs to the mutation algorithm, returned from `validate_inputs`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InputInfo {
+    /// Names of numeric variables present in the first input.
+    pub varnames1: Vec<String>,
+    /// Names of numeric variables present in the second input.
+    pub varnames2: Vec<String>,
+    /// Number of records in the first input.
+    pub nrecords1: usize,
+    /// Number of records in the second input.
+    pub nrecords2: usize,
+    /// Number of records to be read from the first input with missing values of any variable,
+    /// either null or absent.
+    pub nmissing1: usize,
+    /// Number of records to be read from the second input with missing values of any variable,
+    /// either null or absent.
+    pub nmissing2: usize,
+}
+
+/// Check that inputs to `uamutate` are valid, without running the mutation algorithm.
+///
+/// Each input is parsed in full, and every record which would be read by `uamutate` is checked,
+/// so this takes about as long as reading the inputs in `uamutate`, but avoids all further
+/// calculations. Variables listed in the returned `InputInfo` are those of the first record only.
+///
+/// # Arguments
+///
+/// * `reader1` - Reader for local JSON file with data which are to be mutated.
+/// * `reader2` - Reader for local JSON file with data of mutation target.
+/// * `varnames` - Names of variables which must be present in both files.
+/// * `nentries` - The number of entries to be read from the JSON files.
+///
+/// # Returns
+///
+/// An `InputInfo` listing the variables present in each input, and the numbers of records, and of
+/// records with missing values, in each.
+///
+/// # Errors
+///
+/// Returns `UaError::MissingVariable` if any of `varnames` has no numeric values in the first
+/// `nentries` records of either input, or
+/// otherwise errors under the same conditions as `uamutate`, except that no error is returned for
+/// `reader2` having fewer entries than `reader1`.
+///
+/// # Example
+///
+/// ```
+/// use std::fs::File;
+/// use std::io::BufReader;
+/// use uamutations::validate_inputs;
+/// let open = |f: &str| BufReader::new(File::open(f).unwrap());
+/// let varnames = vec!["bike_index".to_string(), "natural".to_string()];
+/// let info = validate_inputs(
+///     open("./test_resources/dat1.json"),
+///     open("./test_resources/dat2.json"),
+///     &varnames,
+///     100,
+/// )
+/// .unwrap();
+/// assert_eq!(info.nrecords1, 1000);
+/// ```
+pub fn validate_inputs(
+    reader1: BufReader<File>,
+    reader2: BufReader<File>,
+    varnames: &[String],
+    nentries: usize,
+) -> Result<InputInfo, UaError> {
+    check_nobs("nentries", nentries)?;
+
+    let mut info = Vec::new();
+    for (reader, name) in [(reader1, "reader1"), (reader2, "reader2")] {
+        let scan =
+            read_write_file::scan_records(reader, varnames, nentries).map_err(|e| match e {
+                UaError::EmptyInput(_) => UaError::EmptyInput(name.to_string()),
+                UaError::MissingVariable { varname, .. } => UaError::MissingVariable {
+                    name: name.to_string(),
+                    varname,
+                },
+                e => e,
+            })?;
+        check_nobs(name, scan.nrecords.min(nentries))?;
+        info.push(scan);
+    }
+    let scan2 = info.pop().unwrap();
+    let scan1 = info.pop().unwrap();
+
+    Ok(InputInfo {
+        varnames1: scan1.varnames,
+        varnames2: scan2.varnames,
+        nrecords1: scan1.nrecords,
+        nrecords2: scan2.nrecords,
+        nmissing1: scan1.nmissing,
+        nmissing2: scan2.nmissing,
+    })
+}
+
+/// Warnings for any columns of `values` which have the same value, to within `eps`, in every row,
+/// ignoring missing values.
+fn zero_variance_warnings(
+    values: &DMatrix<f64>,
+    varnames: &[String],
+    name: &str,
+    eps: f64,
+) -> Vec<Warning> {
+    values
+        .column_iter()
+        .zip(varnames)
+        .filter_map(|(col, varname)| {
+            let mut vals = col.iter().filter(|x| !x.is_nan());
+            let first = vals.next()?;
+            vals.all(|x| (x - first).abs() <= eps)
+                .then(|| Warning::ZeroVariance {
+                    name: name.to_string(),
+                    varname: varname.to_string(),
+                })
+        })
+        .collect()
+}
+
+/// Remove repeated variable names, retaining the first occurrence of each so that the variable to
+/// be mutated remains first.
+///
+/// # Returns
+///
+/// A tuple of the unique variable names, and the names of any which were repeated.
+fn dedup_varnames(varnames: &[String]) -> (Vec<String>, Vec<String>) {
+    let mut unique: Vec<String> = Vec::with_capacity(varnames.len());
+    let mut duplicates: Vec<String> = Vec::new();
+    for v in varnames {
+        if !unique.contains(v) {
+            unique.push(v.clone());
+        } else if !duplicates.contains(v) {
+            duplicates.push(v.clone());
+        }
+    }
+    (unique, duplicates)
+}
+
+/// Indices of all rows of `values` with no missing values.
+fn complete_rows(values: &DMatrix<f64>) -> Vec<usize> {
+    (0..values.nrows())
+        .filter(|&i| values.row(i).iter().all(|x| !x.is_nan()))
+        .collect()
+}
+
+/// Check that an input has at least `MIN_NOBS` observations.
+///
+/// # Arguments
+///
+/// * `name` - Name of the input, used in any error message.
+/// * `nobs` - Number of observations in the input.
+pub(crate) fn check_nobs(name: &str, nobs: usize) -> Result<(), UaError> {
+    if nobs < MIN_NOBS {
+        return Err(UaError::InsufficientData {
+            name: name.to_string(),
+            nobs,
+            min_nobs: MIN_NOBS,
+        });
+    }
+    Ok(())
 }
 
 /// Loop over all columns of the `dists` `DMatrix` object, and aggregate groups for each column.
@@ -89,12 +1037,13 @@ pub fn uamutate(
 /// # Arguments
 ///
 /// * `values1` - The original values used as references for the distances; aggregated versions of
-/// these are also returned.
+///   these are also returned.
 /// * `dists` - A matrix of distances between entries in `values1` and closest values in `values2`.
 /// * `groups` - A vector of same length as `dists`, with 1-based indices of group numbers. There
-/// will generally be far fewer unique groups as there are entries in `dists`.
+///   will generally be far fewer unique groups as there are entries in `dists`.
 /// * `log_scale` - If true, distances are logged before being aggregated, with final values being
-/// 10 ^ mean (log10 (x)).
+///   10 ^ mean (log10 (x)).
+/// * `weights` - Optional weight of each entry, in which case all means are weighted means.
 ///
 /// # Returns
 ///
@@ -110,6 +1059,7 @@ fn aggregate_to_groups(
     dists: &DMatrix<f64>,
     groups: &[usize],
     log_scale: &bool,
+    weights: Option<&[f64]>,
 ) -> DMatrix<f64> {
     assert!(dists.ncols() == 2, "dists must have two columns");
     assert!(
@@ -124,7 +1074,8 @@ fn aggregate_to_groups(
     // Aggregate original values first. These are already log-scaled, so set flag to `false`, and
     // transform back after aggregation:
     let mut values1_first_col: Vec<f64> = values1.column(0).iter().cloned().collect();
-    let mut values1_aggregated = aggregate_to_groups_single_col(&values1_first_col, groups, &false);
+    let mut values1_aggregated =
+        aggregate_to_groups_single_col(&values1_first_col, groups, &false, weights);
     if *log_scale {
         values1_aggregated = values1_aggregated.iter().map(|&x| 10f64.powf(x)).collect();
         values1_first_col = values1_first_col.iter().map(|&x| 10f64.powf(x)).collect();
@@ -141,7 +1092,7 @@ fn aggregate_to_groups(
     // log-scaled are first aggregated in log form, then the aggregate values transformed back to
     // 10^x.
     let values1_transformed_aggregated =
-        aggregate_to_groups_single_col(&values1_transformed, groups, log_scale);
+        aggregate_to_groups_single_col(&values1_transformed, groups, log_scale, weights);
     assert!(
         values1_transformed_aggregated.len() == values1_aggregated.len(),
         "values1_aggregated and values1_transformed_aggregated have different lengths"
@@ -151,13 +1102,13 @@ fn aggregate_to_groups(
     // are far more normal than those of underlying values, and aggregation here is direct. Plus
     // for `dists_abs` and `dists_rel`, values can also be < 0, so log-scaling can't be used in
     // this aggregation anyway.
-    let dists_abs_aggregated = aggregate_to_groups_single_col(&dists_abs, groups, &false);
+    let dists_abs_aggregated = aggregate_to_groups_single_col(&dists_abs, groups, &false, weights);
     assert!(
         dists_abs_aggregated.len() == values1_aggregated.len(),
         "values1_aggregated and dists_abs_aggregated have different lengths"
     );
     let dists_rel: Vec<f64> = dists.column(1).iter().cloned().collect();
-    let dists_rel_aggregated = aggregate_to_groups_single_col(&dists_rel, groups, &false);
+    let dists_rel_aggregated = aggregate_to_groups_single_col(&dists_rel, groups, &false, weights);
     assert!(
         dists_rel_aggregated.len() == values1_aggregated.len(),
         "values1_aggregated and dists_rel_aggregated have different lengths"
@@ -174,58 +1125,535 @@ fn aggregate_to_groups(
     result
 }
 
-/// Aggregate a single column of distances within the groups defined in the original `groups`
-/// vector.
+/// Aggregate distances within groups as for `aggregate_to_groups`, and write each group's results
+/// to `writer` as soon as that group has been aggregated.
+///
+/// This avoids holding all aggregated results in memory prior to writing, and produces output
+/// identical to writing the results of `aggregate_to_groups` with `read_write_file::write_to`.
+/// Entries must be ordered by group, so that each group is complete once the next group is
+/// reached. Groups without any entries are written as for `aggregate_to_groups`.
 ///
 /// # Arguments
 ///
-/// * `dists` - A vector of distances between entries in `values1` and closest values in `values2`.
-/// * `groups` - A vector of same length as `dists`, with 1-based indices of group numbers. There
-/// will generally be far fewer unique groups as there are entries in `dists`.
-/// * `log_scale` - If true, distances are logged before being aggregated, with final values being
-/// 10 ^ mean (log10 (x)).
+/// * `values1` - The original values used as references for the distances.
+/// * `dists` - A matrix of distances between entries in `values1` and closest values in `values2`.
+/// * `groups` - A vector of same length as `dists`, with 1-based indices of group numbers, sorted
+///   in increasing order.
+/// * `log_scale` - If true, the first column of `values1` is log-scaled.
+/// * `writer` - The sink to which the data will be written.
 ///
-/// # Returns
+/// # Errors
 ///
-/// A vector of mean distances within each group to the nearest points in the target distribution.
-fn aggregate_to_groups_single_col(dists: &[f64], groups: &[usize], log_scale: &bool) -> Vec<f64> {
-    let groups_out: Vec<_> = groups.to_vec();
-    let max_group = *groups_out.iter().max().unwrap();
-    let mut counts = vec![0u32; max_group + 1];
-    let mut sums = vec![0f64; max_group + 1];
+/// Returns any error encountered while writing to `writer`.
+///
+/// # Panics
+///
+/// This function will panic under the same conditions as `aggregate_to_groups`, or if `groups`
+/// are not sorted in increasing order.
+pub fn aggregate_and_write<W: Write>(
+    values1: &DMatrix<f64>,
+    dists: &DMatrix<f64>,
+    groups: &[usize],
+    log_scale: &bool,
+    mut writer: W,
+) -> std::io::Result<()> {
+    assert!(dists.ncols() == 2, "dists must have two columns");
+    assert!(
+        dists.nrows() == values1.nrows(),
+        "dists must have same number of rows as values1"
+    );
+    assert!(
+        groups.len() == values1.nrows(),
+        "groups must have same length as values1"
+    );
+    assert!(
+        groups.windows(2).all(|w| w[0] <= w[1]),
+        "groups must be sorted in increasing order"
+    );
 
-    for (i, &group) in groups_out.iter().enumerate() {
-        counts[group] += 1;
-        sums[group] += if *log_scale {
-            dists[i].log10()
+    read_write_file::write_header(&mut writer)?;
+
+    // Groups without entries are aggregated to zero, or 10 ^ 0 for log-scaled values:
+    let empty = if *log_scale { 1.0 } else { 0.0 };
+    // Sums of [original, transformed, absolute, relative] values for current group:
+    let mut sums = [0f64; 4];
+    let mut count = 0usize;
+    let mut current_group = 0;
+
+    let finalise = |sums: &[f64; 4], count: usize| -> [f64; 4] {
+        let n = count as f64;
+        let mut row = [sums[0] / n, sums[1] / n, sums[2] / n, sums[3] / n];
+        if *log_scale {
+            row[0] = 10f64.powf(row[0]);
+            row[1] = 10f64.powf(row[1]);
+        }
+        row
+    };
+
+    for (i, &group) in groups.iter().enumerate() {
+        // Group 0 is discarded, as in `aggregate_to_groups`, because `groups` are 1-based R values:
+        if group == 0 {
+            continue;
+        }
+        if group != current_group {
+            if count > 0 {
+                read_write_file::write_row(&mut writer, &finalise(&sums, count))?;
+            }
+            for _ in (current_group + 1)..group {
+                read_write_file::write_row(&mut writer, &[empty, empty, 0.0, 0.0])?;
+            }
+            current_group = group;
+            sums = [0f64; 4];
+            count = 0;
+        }
+
+        let original = if *log_scale {
+            10f64.powf(values1[(i, 0)])
         } else {
-            dists[i]
+            values1[(i, 0)]
         };
-    }
-
-    // Then convert sums to mean values by dividing by counts:
-    for (sum, count) in sums.iter_mut().zip(&counts) {
-        *sum = if *count != 0 {
-            *sum / *count as f64
+        let transformed = original + dists[(i, 0)];
+        sums[0] += values1[(i, 0)];
+        sums[1] += if *log_scale {
+            transformed.log10()
         } else {
-            0.0
+            transformed
         };
-        if *log_scale {
-            *sum = 10.0f64.powf(*sum);
-        }
+        sums[2] += dists[(i, 0)];
+        sums[3] += dists[(i, 1)];
+        count += 1;
+    }
+    if count > 0 {
+        read_write_file::write_row(&mut writer, &finalise(&sums, count))?;
     }
 
-    // First value of `sums` is junk because `groups` are 1-based R values:
-    sums.remove(0);
-
-    sums
+    Ok(())
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/// Check whether aggregated results from `uamutate` include any non-zero mutations.
+///
+/// Identical input data produce aggregated distances which are all zero. This is correct, but
+/// written output is then hard to distinguish from erroneous results, so this function can be used
+/// to explicitly identify such cases.
+///
+/// # Arguments
+///
+/// * `sums` - Aggregated results returned from `uamutate`.
+///
+/// # Returns
+///
+/// `false` if all absolute distances are (near) zero, otherwise `true`.
+///
+/// # Example
+///
+/// ```
+/// use nalgebra::DMatrix;
+/// use uamutations::mutation_detected;
+/// let sums = DMatrix::from_vec(2, 4, vec![1.0, 2.0, 1.0, 2.0, 0.0, 0.0, 0.0, 0.0]);
+/// assert!(!mutation_detected(&sums));
+/// ```
+pub fn mutation_detected(sums: &DMatrix<f64>) -> bool {
+    assert!(sums.ncols() == 4, "sums must have four columns");
 
-    #[test]
+    sums.column(2).iter().any(|&x| x.abs() > EPS)
+}
+
+/// Select the groups with the largest absolute mutations.
+///
+/// # Arguments
+///
+/// * `groups` - Group number of each value of `sums`.
+/// * `sums` - Aggregated mutation values, such as one column of the result of `uamutate`.
+/// * `n` - Maximum number of groups to return.
+///
+/// # Panics
+///
+/// This function will panic if `groups` and `sums` have different lengths, or if `sums` contains
+/// `NaN` values.
+///
+/// # Returns
+///
+/// Up to `n` pairs of group numbers and mutation values, in descending order of absolute mutation
+/// values. Groups with equal absolute values are ordered by group number.
+///
+/// # Example
+///
+/// ```
+/// use uamutations::top_movers;
+/// let groups = vec![1, 2, 3, 4];
+/// let sums = vec![0.1, -0.5, 0.3, 0.5];
+/// assert_eq!(top_movers(&groups, &sums, 3), vec![(2, -0.5), (4, 0.5), (3, 0.3)]);
+/// ```
+pub fn top_movers(groups: &[usize], sums: &[f64], n: usize) -> Vec<(usize, f64)> {
+    assert_eq!(
+        groups.len(),
+        sums.len(),
+        "groups and sums must have the same length"
+    );
+
+    // Sort by group first, so that the stable sort by absolute values orders ties by group:
+    let mut pairs: Vec<(usize, f64)> = groups.iter().cloned().zip(sums.iter().cloned()).collect();
+    pairs.sort_by_key(|&(g, _)| g);
+    let vals: Vec<f64> = pairs.iter().map(|&(_, v)| v).collect();
+    let order = calculate_dists::get_ordering_index(&vals, true, true);
+
+    order.index_sort.iter().take(n).map(|&i| pairs[i]).collect()
+}
+
+/// Aggregate a single column of distances within groups using an arbitrary statistic.
+///
+/// The distances of each group are collected into a slice, in their original order, and passed
+/// to `f`. Groups are 1-based as for all other aggregation functions, so that entries in group 0
+/// are discarded, and groups without any entries are aggregated to zero without calling `f`.
+///
+/// # Arguments
+///
+/// * `dists` - A vector of distances between entries in `values1` and closest values in `values2`.
+/// * `groups` - A vector of same length as `dists`, with 1-based indices of group numbers.
+/// * `f` - Function aggregating the distances of one group into a single value.
+///
+/// # Returns
+///
+/// A vector with one aggregated value for each group from 1 up to the largest group number.
+///
+/// # Panics
+///
+/// This function will panic if `dists` and `groups` have different lengths.
+///
+/// # Example
+///
+/// ```
+/// use uamutations::aggregate_to_groups_by;
+/// let dists = vec![1.0, 2.0, 3.0, 10.0];
+/// let groups = vec![1, 1, 1, 3];
+/// let max = |x: &[f64]| x.iter().cloned().fold(f64::MIN, f64::max);
+/// let maxima = aggregate_to_groups_by(&dists, &groups, max);
+/// assert_eq!(maxima, vec![3.0, 0.0, 10.0]);
+/// ```
+pub fn aggregate_to_groups_by(
+    dists: &[f64],
+    groups: &[usize],
+    f: impl Fn(&[f64]) -> f64,
+) -> Vec<f64> {
+    assert_eq!(
+        dists.len(),
+        groups.len(),
+        "dists and groups must have the same length"
+    );
+
+    group_members(dists, groups)
+        .iter()
+        .map(|m| if m.is_empty() { 0.0 } else { f(m) })
+        .collect()
+}
+
+/// Distances within each group from 1 up to the largest group number, discarding entries in
+/// group 0.
+fn group_members(dists: &[f64], groups: &[usize]) -> Vec<Vec<f64>> {
+    let max_group = groups.iter().cloned().max().unwrap_or(0);
+    let mut members: Vec<Vec<f64>> = vec![Vec::new(); max_group + 1];
+    for (&d, &group) in dists.iter().zip(groups) {
+        members[group].push(d);
+    }
+
+    // First group is junk because `groups` are 1-based R values:
+    members.remove(0);
+    members
+}
+
+/// Mean, standard deviation, and number of distances within one group, as returned from
+/// `aggregate_group_stats`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GroupStat {
+    /// Mean distance, or zero for groups without entries, as for all other aggregation functions.
+    pub mean: f64,
+    /// Sample standard deviation of distances, or `NaN` for groups with fewer than two entries.
+    pub sd: f64,
+    /// Number of entries in the group.
+    pub count: usize,
+}
+
+/// Aggregate a single column of distances within groups, returning the spread of distances
+/// within each group as well as their means.
+///
+/// # Arguments
+///
+/// * `dists` - A vector of distances between entries in `values1` and closest values in `values2`.
+/// * `groups` - A vector of same length as `dists`, with 1-based indices of group numbers.
+///
+/// # Returns
+///
+/// A vector with one `GroupStat` for each group from 1 up to the largest group number. Entries in
+/// group 0 are discarded.
+///
+/// # Panics
+///
+/// This function will panic if `dists` and `groups` have different lengths.
+///
+/// # Example
+///
+/// ```
+/// use uamutations::aggregate_group_stats;
+/// let dists = vec![1.0, 2.0, 3.0, 10.0];
+/// let groups = vec![1, 1, 1, 3];
+/// let stats = aggregate_group_stats(&dists, &groups);
+/// assert_eq!(stats.len(), 3);
+/// assert_eq!((stats[0].mean, stats[0].sd, stats[0].count), (2.0, 1.0, 3));
+/// assert_eq!(stats[1].count, 0);
+/// ```
+pub fn aggregate_group_stats(dists: &[f64], groups: &[usize]) -> Vec<GroupStat> {
+    assert_eq!(
+        dists.len(),
+        groups.len(),
+        "dists and groups must have the same length"
+    );
+
+    group_members(dists, groups)
+        .iter()
+        .map(|m| {
+            let mut acc = stats::MeanVar::new();
+            m.iter().for_each(|&x| acc.push(x));
+            // Means are sums divided by counts, exactly as for weighted and streamed aggregation:
+            let sum = m.iter().fold(0.0, |sum, x| sum + x);
+            GroupStat {
+                mean: if m.is_empty() {
+                    0.0
+                } else {
+                    sum / m.len() as f64
+                },
+                sd: acc.sd(),
+                count: m.len(),
+            }
+        })
+        .collect()
+}
+
+/// Results of two-level aggregation, as returned from `aggregate_hierarchical`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HierarchicalAggregation {
+    /// Statistics of each group, as returned from `aggregate_group_stats`.
+    pub groups: Vec<GroupStat>,
+    /// Mean of the group means within each parent, from parent 1 up to the largest parent number.
+    /// Parents without any groups with entries are aggregated to zero.
+    pub parents: Vec<f64>,
+}
+
+/// Aggregate a single column of distances first within groups, and then from groups up to the
+/// parents in which they are nested, such as polygons within districts.
+///
+/// # Arguments
+///
+/// * `dists` - A vector of distances between entries in `values1` and closest values in `values2`.
+/// * `groups` - A vector of same length as `dists`, with 1-based indices of group numbers.
+/// * `parent_of_group` - Map from group numbers to 1-based parent numbers. Groups which are not
+///   in the map contribute to group statistics, but not to any parent.
+/// * `weighted` - If true, group means are weighted by their numbers of entries, so that each
+///   parent mean is the mean of all entries in that parent. Otherwise all groups with entries
+///   contribute equally to their parents.
+///
+/// # Returns
+///
+/// A `HierarchicalAggregation` holding statistics of both levels.
+///
+/// # Panics
+///
+/// This function will panic if `dists` and `groups` have different lengths.
+///
+/// # Example
+///
+/// ```
+/// use std::collections::HashMap;
+/// use uamutations::aggregate_hierarchical;
+/// let dists = vec![1.0, 2.0, 3.0, 10.0];
+/// let groups = vec![1, 1, 1, 2];
+/// let parent_of_group = HashMap::from([(1, 1), (2, 1)]);
+/// let result = aggregate_hierarchical(&dists, &groups, &parent_of_group, false);
+/// assert_eq!(result.groups[0].mean, 2.0);
+/// assert_eq!(result.parents, vec![6.0]);
+/// ```
+pub fn aggregate_hierarchical(
+    dists: &[f64],
+    groups: &[usize],
+    parent_of_group: &HashMap<usize, usize>,
+    weighted: bool,
+) -> HierarchicalAggregation {
+    let group_stats = aggregate_group_stats(dists, groups);
+
+    let max_parent = parent_of_group.values().cloned().max().unwrap_or(0);
+    let mut counts = vec![0f64; max_parent + 1];
+    let mut sums = vec![0f64; max_parent + 1];
+    for (i, stat) in group_stats.iter().enumerate() {
+        // Group numbers are 1-based:
+        match parent_of_group.get(&(i + 1)) {
+            Some(&parent) if stat.count > 0 => {
+                let weight = if weighted { stat.count as f64 } else { 1.0 };
+                counts[parent] += weight;
+                sums[parent] += weight * stat.mean;
+            }
+            _ => {}
+        }
+    }
+
+    // First parent is junk because parents are also 1-based:
+    let parents = sums
+        .iter()
+        .zip(&counts)
+        .skip(1)
+        .map(|(sum, count)| if *count != 0.0 { sum / count } else { 0.0 })
+        .collect();
+
+    HierarchicalAggregation {
+        groups: group_stats,
+        parents,
+    }
+}
+
+/// Aggregate distances into bins of the reference values, rather than into groups.
+///
+/// Entries are divided into `nbins` bins of equal numbers of entries (quantile bins), according to
+/// the ranks of `values`. If the number of entries is not divisible by `nbins`, bins differ in size
+/// by at most one entry.
+///
+/// # Arguments
+///
+/// * `values` - Reference values used to define bins, such as original values of the variable
+///   being mutated.
+/// * `dists` - Distances for each entry of `values`.
+/// * `nbins` - Number of bins. If this exceeds the number of entries, each entry is placed in its
+///   own bin.
+///
+/// # Returns
+///
+/// One (bin centre, mean distance) pair for each bin, in increasing order of `values`. Bin centres
+/// are the mean reference values of all entries in each bin.
+///
+/// # Panics
+///
+/// This function will panic if `values` is empty, if `values` and `dists` have different lengths,
+/// or if `nbins` is zero.
+///
+/// # Example
+///
+/// ```
+/// use uamutations::aggregate_by_bins;
+/// let values = vec![4.0, 1.0, 3.0, 2.0];
+/// let dists = vec![0.4, 0.1, 0.3, 0.2];
+/// let bins = aggregate_by_bins(&values, &dists, 2);
+/// assert_eq!(bins.len(), 2);
+/// assert_eq!(bins[0].0, 1.5);
+/// assert_eq!(bins[1].0, 3.5);
+/// ```
+pub fn aggregate_by_bins(values: &[f64], dists: &[f64], nbins: usize) -> Vec<(f64, f64)> {
+    assert!(!values.is_empty(), "values must not be empty");
+    assert_eq!(
+        values.len(),
+        dists.len(),
+        "values and dists must have the same length"
+    );
+    assert!(nbins > 0, "nbins must be positive");
+
+    let n = values.len();
+    let nbins = nbins.min(n);
+    let index_sort = calculate_dists::get_ordering_index(values, false, false).index_sort;
+
+    let mut sums = vec![(0f64, 0f64, 0usize); nbins];
+    for (rank, &i) in index_sort.iter().enumerate() {
+        let bin = &mut sums[rank * nbins / n];
+        bin.0 += values[i];
+        bin.1 += dists[i];
+        bin.2 += 1;
+    }
+
+    sums.iter()
+        .map(|&(v, d, count)| (v / count as f64, d / count as f64))
+        .collect()
+}
+
+/// Aggregate a single column of distances within the groups defined in the original `groups`
+/// vector.
+///
+/// # Arguments
+///
+/// * `dists` - A vector of distances between entries in `values1` and closest values in `values2`.
+/// * `groups` - A vector of same length as `dists`, with 1-based indices of group numbers. There
+///   will generally be far fewer unique groups as there are entries in `dists`.
+/// * `log_scale` - If true, distances are logged before being aggregated, with final values being
+///   10 ^ mean (log10 (x)).
+/// * `weights` - Optional weight of each entry, in which case means are weighted means. Groups
+///   with a total weight of zero are treated as empty.
+///
+/// # Returns
+///
+/// A vector of mean distances within each group to the nearest points in the target distribution.
+fn aggregate_to_groups_single_col(
+    dists: &[f64],
+    groups: &[usize],
+    log_scale: &bool,
+    weights: Option<&[f64]>,
+) -> Vec<f64> {
+    let groups_out: Vec<_> = groups.to_vec();
+    let max_group = *groups_out.iter().max().unwrap();
+    let mut counts = vec![0f64; max_group + 1];
+    let mut sums = vec![0f64; max_group + 1];
+
+    for (i, &group) in groups_out.iter().enumerate() {
+        let weight = weights.map_or(1.0, |w| w[i]);
+        let value = if *log_scale {
+            dists[i].log10()
+        } else {
+            dists[i]
+        };
+        counts[group] += weight;
+        sums[group] += weight * value;
+    }
+
+    // Then convert sums to mean values by dividing by counts:
+    for (sum, count) in sums.iter_mut().zip(&counts) {
+        *sum = if *count != 0.0 { *sum / *count } else { 0.0 };
+        if *log_scale {
+            *sum = 10.0f64.powf(*sum);
+        }
+    }
+
+    // First value of `sums` is junk because `groups` are 1-based R values:
+    sums.remove(0);
+
+    sums
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_abs_diff_eq;
+
+    /// Open a JSON test fixture.
+    fn open(path: &str) -> BufReader<File> {
+        BufReader::new(File::open(path).unwrap())
+    }
+
+    fn json_reader(path: &str) -> read_write_file::JsonReader {
+        read_write_file::JsonReader::Path(path.into())
+    }
+
+    /// Run `uamutate_debug` with the default `GreedyMatcher` on two JSON test fixtures.
+    fn run_fixtures(
+        path1: &str,
+        path2: &str,
+        varnames: &[String],
+        nentries: usize,
+        config: &MutationConfig,
+    ) -> Result<MutationDebug, UaError> {
+        uamutate_debug(
+            &json_reader(path1),
+            &json_reader(path2),
+            varnames,
+            nentries,
+            &calculate_dists::GreedyMatcher,
+            config,
+        )
+    }
+
+    #[test]
     fn test_uamutate() {
         // Define the input parameters for the function
         let filename1 = "./test_resources/dat1.json";
@@ -242,14 +1670,1316 @@ mod tests {
 
         // let sums = uamutate(&mut values1, groups1, &values2);
         let file1 = File::open(filename1).unwrap();
-        let reader1 = BufReader::new(file1);
+        let reader1 = read_write_file::JsonReader::from_reader(BufReader::new(file1)).unwrap();
         let file2 = File::open(filename2).unwrap();
-        let reader2 = BufReader::new(file2);
-        let sums = uamutate(reader1, reader2, &varsall, nentries);
+        let reader2 = read_write_file::JsonReader::from_reader(BufReader::new(file2)).unwrap();
+        let sums = uamutate(
+            &reader1,
+            &reader2,
+            &varsall,
+            nentries,
+            &calculate_dists::GreedyMatcher,
+            &MutationConfig::default(),
+        )
+        .unwrap();
 
         assert!(!sums.is_empty());
     }
 
+    /// Matcher which matches every entry to itself, so all distances are zero.
+    struct IdentityMatcher;
+
+    impl Matcher for IdentityMatcher {
+        fn match_dists(
+            &self,
+            values1: &DMatrix<f64>,
+            _values2: &DMatrix<f64>,
+            _log_scale: &bool,
+        ) -> Result<DMatrix<f64>, UaError> {
+            Ok(DMatrix::zeros(values1.nrows(), 2))
+        }
+    }
+
+    #[test]
+    fn test_uamutate_matchers() {
+        let varsall = vec![
+            "bike_index".to_string(),
+            "natural".to_string(),
+            "social_index".to_string(),
+        ];
+        let nentries = 10;
+
+        let greedy = uamutate(
+            &json_reader("./test_resources/dat1.json"),
+            &json_reader("./test_resources/dat2.json"),
+            &varsall,
+            nentries,
+            &calculate_dists::GreedyMatcher,
+            &MutationConfig::default(),
+        )
+        .unwrap();
+        let identity = uamutate(
+            &json_reader("./test_resources/dat1.json"),
+            &json_reader("./test_resources/dat2.json"),
+            &varsall,
+            nentries,
+            &IdentityMatcher,
+            &MutationConfig::default(),
+        )
+        .unwrap();
+
+        // Original values are unaffected by the choice of matcher:
+        assert_eq!(greedy.column(0), identity.column(0));
+        // Identity matching leaves values unchanged, with all distances zero:
+        assert_eq!(identity.column(0), identity.column(1));
+        assert!(identity.column(2).iter().all(|&x| x == 0.0));
+        assert!(identity.column(3).iter().all(|&x| x == 0.0));
+        // Whereas greedy matching to a different city mutates values:
+        assert!(greedy.column(2).iter().any(|&x| x != 0.0));
+    }
+
+    #[test]
+    fn test_uamutate_absolute() {
+        let varsall = vec!["transport".to_string(), "natural".to_string()];
+        let sums = uamutate(
+            &json_reader("./test_resources/dat1.json"),
+            &json_reader("./test_resources/dat2.json"),
+            &varsall,
+            100,
+            &calculate_dists::GreedyMatcher,
+            &MutationConfig::default(),
+        )
+        .unwrap();
+        // Absolute differences are mutated minus original values:
+        for i in 0..sums.nrows() {
+            assert_abs_diff_eq!(sums[(i, 2)], sums[(i, 1)] - sums[(i, 0)], epsilon = 1e-10);
+        }
+    }
+
+    #[test]
+    fn test_uamutate_scale() {
+        let varsall = vec!["transport".to_string(), "bike_index".to_string()];
+        let run = |scale: read_write_file::ScaleMode| {
+            let config = MutationConfig {
+                scale,
+                ..MutationConfig::default()
+            };
+            run_fixtures(
+                "./test_resources/dat1.json",
+                "./test_resources/dat2.json",
+                &varsall,
+                100,
+                &config,
+            )
+            .unwrap()
+        };
+        let raw = run(read_write_file::ScaleMode::None);
+        let scaled = run(read_write_file::ScaleMode::ZScore);
+
+        // Without scaling, original values are retained in their own units:
+        let city1 =
+            read_write_file::readfile(open("./test_resources/dat1.json"), &varsall, 100).unwrap();
+        // Entries are reduced to the number of complete entries of `dat2.json`, which has some
+        // missing values:
+        let nrows = raw.values1.nrows();
+        assert_eq!(raw.values1, city1.values.rows(0, nrows));
+        assert_ne!(scaled.values1, city1.values.rows(0, nrows));
+        let (mean, _) = utils::mean_sd_dmat(&raw.values1).unwrap();
+        assert!(mean > 10.0);
+
+        // Scaling to pooled z-scores does not change the order of matched pairs, so the sign of
+        // all mutations is unchanged:
+        for (a, b) in raw
+            .dists
+            .column(0)
+            .iter()
+            .zip(scaled.dists.column(0).iter())
+        {
+            assert_eq!(a.signum(), b.signum());
+        }
+    }
+
+    #[test]
+    fn test_uamutate_timings() {
+        let varsall = vec!["bike_index".to_string(), "natural".to_string()];
+        let run = |instrument: bool| {
+            let config = MutationConfig {
+                instrument,
+                ..MutationConfig::default()
+            };
+            run_fixtures(
+                "./test_resources/dat1.json",
+                "./test_resources/dat2.json",
+                &varsall,
+                1000,
+                &config,
+            )
+            .unwrap()
+        };
+        assert!(run(false).timings.is_none());
+
+        let timings = run(true).timings.unwrap();
+        let phases = [
+            timings.read,
+            timings.standardise,
+            timings.adj_for_beta,
+            timings.calculate_dists,
+            timings.aggregate,
+        ];
+        assert!(timings.read > Duration::ZERO);
+        assert!(timings.calculate_dists > Duration::ZERO);
+        let sum: Duration = phases.iter().sum();
+        // Phases exclude only a few cheap steps such as copying and inverting values:
+        assert!(sum <= timings.total);
+        assert!(sum.as_secs_f64() > 0.5 * timings.total.as_secs_f64());
+    }
+
+    #[test]
+    fn test_uamutate_nan_policy() {
+        let varsall = vec!["transport".to_string(), "bike_index".to_string()];
+        let run = |nan_policy: NanPolicy| {
+            let config = MutationConfig {
+                nan_policy,
+                ..MutationConfig::default()
+            };
+            run_fixtures(
+                "./test_resources/dat_nan.json",
+                "./test_resources/dat2.json",
+                &varsall,
+                8,
+                &config,
+            )
+            .unwrap()
+        };
+
+        // Groups 2 and 4 contain entries with missing values, and group 4 has no complete
+        // entries:
+        let propagate = run(NanPolicy::Propagate);
+        assert_eq!(propagate.values1.nrows(), 5);
+        assert_eq!(propagate.dists.nrows(), 8);
+        let nan_rows: Vec<bool> = propagate
+            .dists
+            .column(0)
+            .iter()
+            .map(|x| x.is_nan())
+            .collect();
+        assert_eq!(
+            nan_rows,
+            vec![false, false, true, false, false, false, true, true]
+        );
+        assert_eq!(propagate.sums.nrows(), 4);
+        for (i, has_nan) in [false, true, false, true].iter().enumerate() {
+            assert!(propagate.sums.row(i).iter().all(|x| x.is_nan() == *has_nan));
+        }
+
+        let skip = run(NanPolicy::Skip);
+        assert_eq!(skip.dists.nrows(), 5);
+        assert_eq!(skip.groups, vec![1, 1, 2, 3, 3]);
+        assert_eq!(skip.sums.nrows(), 3);
+        assert!(skip.sums.iter().all(|x| x.is_finite()));
+        // Complete groups are identical under both policies:
+        assert_eq!(skip.sums.row(0), propagate.sums.row(0));
+        assert_eq!(skip.sums.row(2), propagate.sums.row(2));
+    }
+
+    #[test]
+    fn test_uamutate_nan_output_transform() {
+        let varsall = vec!["transport".to_string(), "bike_index".to_string()];
+        let run = |output: transform::OutputTransform| {
+            let config = MutationConfig {
+                output,
+                ..MutationConfig::default()
+            };
+            run_fixtures(
+                "./test_resources/dat_nan.json",
+                "./test_resources/dat2.json",
+                &varsall,
+                8,
+                &config,
+            )
+            .unwrap()
+            .sums
+        };
+
+        let raw = run(transform::OutputTransform::Raw);
+        for output in [
+            transform::OutputTransform::Rank,
+            transform::OutputTransform::NormalScore,
+        ] {
+            let sums = run(output);
+            assert_eq!(sums.nrows(), raw.nrows());
+            for i in 0..sums.nrows() {
+                for j in 2..sums.ncols() {
+                    assert_eq!(sums[(i, j)].is_nan(), raw[(i, j)].is_nan());
+                }
+            }
+            // Groups 1 and 3 are complete, and retain the order of their raw values:
+            for j in 2..sums.ncols() {
+                assert_eq!(
+                    sums[(0, j)] < sums[(2, j)],
+                    raw[(0, j)] < raw[(2, j)],
+                    "{:?}",
+                    output
+                );
+            }
+        }
+        let rank = run(transform::OutputTransform::Rank);
+        for j in 2..rank.ncols() {
+            let mut finite: Vec<f64> = rank
+                .column(j)
+                .iter()
+                .cloned()
+                .filter(|x| !x.is_nan())
+                .collect();
+            finite.sort_by(|a, b| a.total_cmp(b));
+            assert_eq!(finite, vec![0.0, 100.0]);
+        }
+    }
+
+    #[test]
+    fn test_uamutate_warnings() {
+        let varsall = vec!["transport".to_string(), "bike_index".to_string()];
+        let run = |file1: &str, file2: &str| {
+            run_fixtures(file1, file2, &varsall, 8, &MutationConfig::default())
+                .unwrap()
+                .warnings
+        };
+
+        let warnings = run("./test_resources/dat1.json", "./test_resources/dat2.json");
+        assert!(warnings.is_empty());
+
+        let warnings = run(
+            "./test_resources/dat_const.json",
+            "./test_resources/dat2.json",
+        );
+        assert_eq!(
+            warnings,
+            vec![Warning::ZeroVariance {
+                name: "reader1".to_string(),
+                varname: "bike_index".to_string()
+            }]
+        );
+
+        let warnings = run(
+            "./test_resources/dat_nan.json",
+            "./test_resources/dat_const.json",
+        );
+        assert!(warnings.contains(&Warning::MissingValues {
+            name: "reader1".to_string(),
+            nentries: 3
+        }));
+        assert!(warnings.contains(&Warning::TargetExhausted {
+            nobs: 5,
+            nobs_target: 4
+        }));
+        assert_eq!(
+            warnings[1].to_string(),
+            "3 records of reader1 skipped due to missing values"
+        );
+    }
+
+    #[test]
+    fn test_top_movers() {
+        let groups = vec![5, 3, 1, 4, 2];
+        let sums = vec![-0.2, 0.9, 0.2, -1.5, 0.0];
+        let movers = top_movers(&groups, &sums, 10);
+        assert_eq!(
+            movers,
+            vec![(4, -1.5), (3, 0.9), (1, 0.2), (5, -0.2), (2, 0.0)]
+        );
+
+        assert_eq!(top_movers(&groups, &sums, 2), vec![(4, -1.5), (3, 0.9)]);
+        assert!(top_movers(&groups, &sums, 0).is_empty());
+    }
+
+    #[test]
+    fn test_eps_jitter() {
+        // Constant variables with sub-EPS jitter are still detected as constant:
+        let jitter = |i: usize| (i % 3) as f64 * 1.0e-12;
+        let values = DMatrix::from_fn(6, 2, |i, j| if j == 0 { 2.0 + jitter(i) } else { i as f64 });
+        let varnames = vec!["a".to_string(), "b".to_string()];
+        let warnings = zero_variance_warnings(&values, &varnames, "reader1", EPS);
+        assert_eq!(
+            warnings,
+            vec![Warning::ZeroVariance {
+                name: "reader1".to_string(),
+                varname: "a".to_string()
+            }]
+        );
+
+        // Matching of perturbed data gives the same distances, and no spurious relative distances
+        // from references which are only non-zero through jitter:
+        let values1 = DMatrix::from_vec(4, 1, vec![0.0, 1.0, 2.0, 3.0]);
+        let values2 = DMatrix::from_vec(4, 1, vec![0.5, 1.5, 2.5, 3.5]);
+        let perturbed = DMatrix::from_fn(4, 1, |i, _| values1[(i, 0)] + jitter(i + 1));
+        let dists = calculate_dists::calculate_dists(&values1, &values2, &false, 0).unwrap();
+        let dists_perturbed =
+            calculate_dists::calculate_dists(&perturbed, &values2, &false, 0).unwrap();
+        for (a, b) in dists.iter().zip(dists_perturbed.iter()) {
+            assert_abs_diff_eq!(a, b, epsilon = EPS);
+        }
+        assert!(!mutation_detected(&DMatrix::from_fn(3, 4, |i, _| jitter(
+            i
+        ))));
+    }
+
+    #[test]
+    fn test_aggregate_by_bins() {
+        // Ramp of distances increasing with values, in shuffled order:
+        let n = 103;
+        let values: Vec<f64> = (0..n).map(|i| ((i * 37) % n) as f64).collect();
+        let dists: Vec<f64> = values.iter().map(|v| 0.5 * v + 1.0).collect();
+
+        let bins = aggregate_by_bins(&values, &dists, 10);
+        assert_eq!(bins.len(), 10);
+        for w in bins.windows(2) {
+            assert!(w[1].0 > w[0].0);
+            assert!(w[1].1 > w[0].1);
+        }
+        // Linear ramp means that bin means are the ramp applied to bin centres:
+        for (centre, mean) in &bins {
+            assert_abs_diff_eq!(*mean, 0.5 * centre + 1.0, epsilon = 1e-10);
+        }
+
+        // More bins than entries gives one bin per entry:
+        let bins = aggregate_by_bins(&values[..3], &dists[..3], 10);
+        assert_eq!(bins.len(), 3);
+    }
+
+    #[test]
+    fn test_aggregate_group_stats() {
+        let dists = vec![1.0, 2.0, 3.0, 10.0, 4.0, 5.0];
+        let groups = vec![1, 1, 1, 3, 3, 0];
+        let stats = aggregate_group_stats(&dists, &groups);
+        assert_eq!(stats.len(), 3);
+
+        // Group 1 has deviations of (-1, 0, 1) from its mean of 2, so variance (1 + 0 + 1) / 2:
+        assert_eq!(stats[0].count, 3);
+        assert_abs_diff_eq!(stats[0].mean, 2.0, epsilon = 1e-12);
+        assert_abs_diff_eq!(stats[0].sd, 1.0, epsilon = 1e-12);
+        assert_eq!(stats[1].count, 0);
+        assert_eq!(stats[1].mean, 0.0);
+        // Group 3 has deviations of (3, -3) from its mean of 7, so variance (9 + 9) / 1:
+        assert_eq!(stats[2].count, 2);
+        assert_abs_diff_eq!(stats[2].mean, 7.0, epsilon = 1e-12);
+        assert_abs_diff_eq!(stats[2].sd, 18f64.sqrt(), epsilon = 1e-12);
+
+        let means: Vec<f64> = stats.iter().map(|s| s.mean).collect();
+        assert_eq!(
+            means,
+            aggregate_to_groups_single_col(&dists, &groups, &false, None)
+        );
+    }
+
+    #[test]
+    fn test_aggregate_hierarchical() {
+        // Two polygons (groups 1 and 2) roll up into district 1, with an empty district 2, and
+        // group 3 not in any district:
+        let dists = vec![1.0, 2.0, 3.0, 10.0, 100.0];
+        let groups = vec![1, 1, 1, 2, 3];
+        let parent_of_group = HashMap::from([(1, 1), (2, 1), (4, 2)]);
+
+        let result = aggregate_hierarchical(&dists, &groups, &parent_of_group, false);
+        let means: Vec<(f64, usize)> = result.groups.iter().map(|g| (g.mean, g.count)).collect();
+        assert_eq!(means, vec![(2.0, 3), (10.0, 1), (100.0, 1)]);
+        // Unweighted mean of group means of 2 and 10, and zero for the empty district:
+        assert_eq!(result.parents, vec![6.0, 0.0]);
+
+        // Weighted by counts of 3 and 1, giving the mean of all four entries:
+        let result = aggregate_hierarchical(&dists, &groups, &parent_of_group, true);
+        assert_eq!(result.parents, vec![4.0, 0.0]);
+    }
+
+    #[test]
+    fn test_aggregate_to_groups_by() {
+        let dists = vec![1.0, 2.0, 3.0, 100.0, -50.0, 5.0, 7.0, 9.0];
+        let groups = vec![1, 1, 1, 1, 1, 3, 3, 0];
+
+        // Trimmed mean, dropping the lowest and highest values of groups with more than 2 values:
+        let trimmed_mean = |x: &[f64]| {
+            let mut x = x.to_vec();
+            x.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            let x = if x.len() > 2 {
+                &x[1..x.len() - 1]
+            } else {
+                &x[..]
+            };
+            x.iter().sum::<f64>() / x.len() as f64
+        };
+        let result = aggregate_to_groups_by(&dists, &groups, trimmed_mean);
+        assert_eq!(result, vec![2.0, 0.0, 6.0]);
+
+        // Plain means match the built-in aggregation, including empty groups and group 0:
+        let mean = |x: &[f64]| x.iter().sum::<f64>() / x.len() as f64;
+        assert_eq!(
+            aggregate_to_groups_by(&dists, &groups, mean),
+            aggregate_to_groups_single_col(&dists, &groups, &false, None)
+        );
+    }
+
+    #[test]
+    fn test_uamutate_matched_target() {
+        let varsall = vec!["transport".to_string(), "bike_index".to_string()];
+        let debug = run_fixtures(
+            "./test_resources/dat1.json",
+            "./test_resources/dat2.json",
+            &varsall,
+            100,
+            &MutationConfig::default(),
+        )
+        .unwrap();
+
+        // 'transport' is neither inverted nor log-transformed, so matching is on adjusted values:
+        assert_eq!(debug.matched_target.len(), debug.dists.nrows());
+        for (i, target) in debug.matched_target.iter().enumerate() {
+            let value = debug.values1_adjusted[(i, 0)];
+            assert_abs_diff_eq!(target - value, debug.dists[(i, 0)], epsilon = 1e-10);
+        }
+        // Each target entry is matched exactly once:
+        let mut matched = debug.matched_target.clone();
+        let mut targets: Vec<f64> = debug.values2.column(0).iter().cloned().collect();
+        matched.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        targets.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        for (m, t) in matched.iter().zip(targets.iter()) {
+            assert_abs_diff_eq!(m, t, epsilon = 1e-10);
+        }
+    }
+
+    #[test]
+    fn test_uamutate_skip_covariate_adjustment() {
+        let varsall = vec![
+            "bike_index".to_string(),
+            "natural".to_string(),
+            "social_index".to_string(),
+        ];
+        let run = |vars: &[String], skip_covariate_adjustment| {
+            let config = MutationConfig {
+                skip_covariate_adjustment,
+                ..Default::default()
+            };
+            run_fixtures(
+                "./test_resources/dat1.json",
+                "./test_resources/dat2.json",
+                vars,
+                100,
+                &config,
+            )
+            .unwrap()
+        };
+
+        let adjusted = run(&varsall, false);
+        let unadjusted = run(&varsall, true);
+        assert!(unadjusted.beta1.is_empty() && unadjusted.beta2.is_empty());
+        assert_eq!(unadjusted.values1, unadjusted.values1_adjusted);
+        assert_ne!(adjusted.sums, unadjusted.sums);
+
+        // Without adjustment, results are those of the first variable alone:
+        let single = run(&varsall[..1], false);
+        assert_eq!(unadjusted.sums, single.sums);
+    }
+
+    #[test]
+    fn test_uamutate_drop_missing_covariates() {
+        let varsall = vec![
+            "bike_index".to_string(),
+            "natural".to_string(),
+            "social_index".to_string(),
+        ];
+        let run = |varnames: &[String], drop_missing_covariates| {
+            let config = MutationConfig {
+                drop_missing_covariates,
+                ..Default::default()
+            };
+            run_fixtures(
+                "./test_resources/dat1.json",
+                "./test_resources/dat_no_social.json",
+                varnames,
+                100,
+                &config,
+            )
+        };
+
+        let dropped = run(&varsall, true).unwrap();
+        assert_eq!(
+            dropped.warnings,
+            vec![Warning::CovariateDropped {
+                varname: "social_index".to_string(),
+            }]
+        );
+        let expected = run(&varsall[..2], false).unwrap();
+        assert_eq!(dropped.sums, expected.sums);
+        assert_eq!(dropped.beta1, expected.beta1);
+    }
+
+    #[test]
+    fn test_preprocess() {
+        let varnames = vec!["transport".to_string(), "social_index".to_string()];
+        let config = MutationConfig {
+            scale: read_write_file::ScaleMode::ZScore,
+            ..Default::default()
+        };
+        let debug = run_fixtures(
+            "./test_resources/dat1.json",
+            "./test_resources/dat2.json",
+            &varnames,
+            100,
+            &config,
+        )
+        .unwrap();
+
+        let read = |f: &str| read_write_file::readfile(f, &varnames, 100).unwrap().values;
+        // Entries with missing values are excluded, and both inputs reduced to the same size:
+        let values2 = read("./test_resources/dat2.json");
+        let mut values2 = values2.select_rows(&complete_rows(&values2));
+        let mut values1 = read("./test_resources/dat1.json")
+            .rows(0, values2.nrows())
+            .into_owned();
+        let (beta1, beta2) = preprocess(&mut values1, &mut values2, config.scale).unwrap();
+        assert_eq!(beta1, debug.beta1);
+        assert_eq!(beta2, debug.beta2);
+        assert_eq!(values1, debug.values1_adjusted);
+
+        let dists = calculate_dists::calculate_dists(&values1, &values2, &false, 0).unwrap();
+        assert_abs_diff_eq!(dists, debug.dists, epsilon = 1e-12);
+    }
+
+    #[test]
+    fn test_uamutate_constant_target() {
+        let varnames = vec!["bike_index".to_string()];
+        let run = |relative_base| {
+            let config = MutationConfig {
+                relative_base,
+                ..Default::default()
+            };
+            run_fixtures(
+                "./test_resources/dat1.json",
+                "./test_resources/dat_const.json",
+                &varnames,
+                4,
+                &config,
+            )
+            .unwrap()
+        };
+
+        for base in [
+            calculate_dists::RelativeBase::Source,
+            calculate_dists::RelativeBase::Target,
+        ] {
+            let debug = run(base);
+            assert!(debug.warnings.contains(&Warning::ConstantTarget {
+                varname: "bike_index".to_string()
+            }));
+            // All entries are mutated to the same target value, with finite distances:
+            let target = debug.matched_target[0];
+            for &x in &debug.matched_target {
+                assert_abs_diff_eq!(x, target, epsilon = 1e-12);
+            }
+            assert!(debug.dists.iter().all(|x| x.is_finite()));
+            for i in 0..debug.dists.nrows() {
+                let a = debug.matched_target[i] - debug.dists[(i, 0)];
+                let (_, rel) = calculate_dists::pair_distance(a, target, base);
+                assert_abs_diff_eq!(debug.dists[(i, 1)], rel, epsilon = 1e-12);
+            }
+        }
+    }
+
+    #[test]
+    fn test_uamutate_eps() {
+        // Tied values of the first variable, and the same values with jitter smaller than `EPS`:
+        let json = |jitter: f64| {
+            let records: Vec<serde_json::Value> = (0..60)
+                .map(|i| {
+                    let noise = if i % 2 == 0 { jitter } else { -jitter } * i as f64;
+                    serde_json::json!({"transport": (i % 4) as f64 + noise, "index": i / 5 + 1})
+                })
+                .collect();
+            read_write_file::JsonReader::Bytes(serde_json::to_vec(&records).unwrap())
+        };
+        let reader2 = read_write_file::reader_for_path("./test_resources/dat2.json").unwrap();
+        let varnames = vec!["transport".to_string()];
+        let run = |reader1: &read_write_file::JsonReader, eps: Option<f64>| {
+            let config = MutationConfig {
+                eps,
+                log_vars: Some(Vec::new()),
+                ..MutationConfig::default()
+            };
+            mutate(
+                reader1,
+                reader2.as_ref(),
+                &varnames,
+                60,
+                &calculate_dists::GreedyMatcher,
+                &config,
+                Instant::now(),
+            )
+            .unwrap()
+            .matched_target
+        };
+
+        let exact = run(&json(0.0), None);
+        let jittered = run(&json(1.0e-13), None);
+        for (a, b) in exact.iter().zip(&jittered) {
+            assert_abs_diff_eq!(a, b, epsilon = 1e-9);
+        }
+        // Without tolerance, jitter changes the order of tied values, and so the matching:
+        let strict = run(&json(1.0e-13), Some(0.0));
+        assert!(exact.iter().zip(&strict).any(|(a, b)| (a - b).abs() > 1e-6));
+    }
+
+    #[test]
+    fn test_uamutate_readers() {
+        let varnames = vec!["bike_index".to_string(), "natural".to_string()];
+        let config = MutationConfig {
+            drop_missing_covariates: true,
+            ..Default::default()
+        };
+        let run = |reader1: &dyn read_write_file::CityReader,
+                   reader2: &dyn read_write_file::CityReader| {
+            uamutate(
+                reader1,
+                reader2,
+                &varnames,
+                100,
+                &calculate_dists::GreedyMatcher,
+                &config,
+            )
+        };
+        let expected = run(
+            &json_reader("./test_resources/dat1.json"),
+            &json_reader("./test_resources/dat2.json"),
+        )
+        .unwrap();
+
+        // Inputs from any reader give identical results:
+        let reader1 = read_write_file::reader_for_path("./test_resources/dat1.json").unwrap();
+        let reader2 =
+            read_write_file::JsonReader::from_reader(open("./test_resources/dat2.json")).unwrap();
+        assert_eq!(run(reader1.as_ref(), &reader2).unwrap(), expected);
+
+        let empty = read_write_file::JsonReader::Bytes(b"[]".to_vec());
+        assert_eq!(
+            run(&empty, &reader2),
+            Err(UaError::EmptyInput("reader1".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_uamutate_cancelled() {
+        let varnames = vec!["bike_index".to_string(), "natural".to_string()];
+        let config = MutationConfig {
+            cancel: Some(Arc::new(AtomicBool::new(true))),
+            ..Default::default()
+        };
+        let result = uamutate(
+            &json_reader("./test_resources/dat1.json"),
+            &json_reader("./test_resources/dat2.json"),
+            &varnames,
+            100,
+            &calculate_dists::GreedyMatcher,
+            &config,
+        );
+        assert_eq!(result, Err(UaError::Cancelled));
+        assert_eq!(UaError::Cancelled.to_string(), "calculation was cancelled");
+    }
+
+    #[test]
+    fn test_uamutate_weights() {
+        let varsall = vec![
+            "bike_index".to_string(),
+            "natural".to_string(),
+            "social_index".to_string(),
+        ];
+        let run = |weights| {
+            let config = MutationConfig {
+                scale: read_write_file::ScaleMode::ZScore,
+                weights,
+                ..Default::default()
+            };
+            run_fixtures(
+                "./test_resources/dat1.json",
+                "./test_resources/dat2.json",
+                &varsall,
+                100,
+                &config,
+            )
+        };
+
+        let unweighted = run(None).unwrap();
+        let uniform = run(Some(vec![2.0; 100])).unwrap();
+        assert_abs_diff_eq!(uniform.sums, unweighted.sums, epsilon = 1e-10);
+        for (a, b) in uniform.beta1.iter().zip(&unweighted.beta1) {
+            assert_abs_diff_eq!(a, b, epsilon = 1e-10);
+        }
+
+        let varying: Vec<f64> = (0..100).map(|i| 1.0 + (i % 4) as f64).collect();
+        let weighted = run(Some(varying)).unwrap();
+        assert_eq!(weighted.sums.shape(), unweighted.sums.shape());
+        assert_ne!(weighted.beta1, unweighted.beta1);
+        assert_ne!(weighted.values1, unweighted.values1);
+        let max_diff = (&weighted.sums - &unweighted.sums).abs().max();
+        assert!(max_diff > 1e-6);
+
+        assert_eq!(
+            run(Some(vec![1.0; 99])).unwrap_err(),
+            UaError::LengthMismatch {
+                name: "weights".to_string(),
+                len: 99,
+                expected: 100
+            }
+        );
+    }
+
+    #[test]
+    fn test_uamutate_toward_quantile_mean() {
+        let varsall = vec!["bike_index".to_string(), "natural".to_string()];
+        let run = |mode| {
+            let config = MutationConfig {
+                mode,
+                ..Default::default()
+            };
+            uamutate(
+                &json_reader("./test_resources/dat1.json"),
+                &json_reader("./test_resources/dat2.json"),
+                &varsall,
+                100,
+                &calculate_dists::GreedyMatcher,
+                &config,
+            )
+            .unwrap()
+        };
+
+        let matched = run(MutationMode::Match);
+        let none = run(MutationMode::TowardQuantileMean { fraction: 0.0 });
+        assert!(none.columns(2, 2).iter().all(|&x| x == 0.0));
+        assert_eq!(none.column(0), none.column(1));
+        assert!(!mutation_detected(&none));
+
+        let full = run(MutationMode::TowardQuantileMean { fraction: 1.0 });
+        assert_abs_diff_eq!(full, matched, epsilon = 1e-12);
+
+        let half = run(MutationMode::TowardQuantileMean { fraction: 0.5 });
+        assert_abs_diff_eq!(
+            half.column(2) * 2.0,
+            matched.column(2).clone_owned(),
+            epsilon = 1e-12
+        );
+    }
+
+    #[test]
+    fn test_uamutate_identical_inputs() {
+        let varsall = vec![
+            "bike_index".to_string(),
+            "natural".to_string(),
+            "social_index".to_string(),
+        ];
+        let result = run_fixtures(
+            "./test_resources/dat1.json",
+            "./test_resources/dat1.json",
+            &varsall,
+            100,
+            &MutationConfig::default(),
+        )
+        .unwrap();
+
+        assert!(result.warnings.contains(&Warning::IdenticalInputs));
+        assert!(result.dists.iter().all(|&d| d == 0.0));
+        for i in 0..result.sums.nrows() {
+            assert_eq!(result.sums[(i, 1)], result.sums[(i, 0)]);
+            assert_eq!(result.sums[(i, 2)], 0.0);
+            assert_eq!(result.sums[(i, 3)], 0.0);
+        }
+        assert!(!mutation_detected(&result.sums));
+    }
+
+    #[test]
+    fn test_uamutate_invert_vars() {
+        let varsall = vec!["transport".to_string()];
+        let run = |invert_vars| {
+            let config = MutationConfig {
+                invert_vars,
+                ..Default::default()
+            };
+            run_fixtures(
+                "./test_resources/dat1.json",
+                "./test_resources/dat2.json",
+                &varsall,
+                100,
+                &config,
+            )
+            .unwrap()
+        };
+
+        let raw = run(None);
+        let inverted = run(Some(vec![("transport".to_string(), 100.0)]));
+
+        // Inversion reverses the order of values in both inputs, so the same entries are matched,
+        // but with absolute mutations of opposite sign:
+        let total = |d: &MutationDebug| d.dists.column(0).sum();
+        assert!(total(&raw).abs() > 1e-6);
+        assert_abs_diff_eq!(total(&inverted), -total(&raw), epsilon = 1e-8);
+        for (a, b) in raw
+            .sums
+            .column(2)
+            .iter()
+            .zip(inverted.sums.column(2).iter())
+        {
+            assert!(a * b <= 0.0);
+        }
+    }
+
+    #[test]
+    fn test_uamutate_invert_before_log() {
+        let varsall = vec!["transport".to_string()];
+        let config = MutationConfig {
+            invert_vars: Some(vec![("transport".to_string(), 100.0)]),
+            log_vars: Some(varsall.clone()),
+            ..Default::default()
+        };
+        let debug = run_fixtures(
+            "./test_resources/dat1.json",
+            "./test_resources/dat2.json",
+            &varsall,
+            100,
+            &config,
+        )
+        .unwrap();
+
+        // Values are inverted first, and the inverted values then log-transformed:
+        let city =
+            read_write_file::readfile(open("./test_resources/dat1.json"), &varsall, 100).unwrap();
+        for (x, raw) in debug
+            .values1
+            .column(0)
+            .iter()
+            .zip(city.values.column(0).iter())
+        {
+            assert_abs_diff_eq!(*x, (100.0 - raw).log10(), epsilon = 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_uamutate_relative_base() {
+        let varsall = vec!["transport".to_string(), "bike_index".to_string()];
+        let run = |relative_base| {
+            let config = MutationConfig {
+                relative_base,
+                ..Default::default()
+            };
+            run_fixtures(
+                "./test_resources/dat1.json",
+                "./test_resources/dat2.json",
+                &varsall,
+                100,
+                &config,
+            )
+            .unwrap()
+        };
+        let source = run(calculate_dists::RelativeBase::Source);
+        let target = run(calculate_dists::RelativeBase::Target);
+
+        assert_eq!(source.dists.column(0), target.dists.column(0));
+        assert_ne!(source.dists.column(1), target.dists.column(1));
+        for (i, &b) in target.matched_target.iter().enumerate() {
+            let a = target.values1_adjusted[(i, 0)];
+            assert_abs_diff_eq!(source.dists[(i, 1)] * a.abs(), b - a, epsilon = 1e-10);
+            assert_abs_diff_eq!(target.dists[(i, 1)] * b.abs(), b - a, epsilon = 1e-10);
+        }
+    }
+
+    #[test]
+    fn test_uamutate_symmetric() {
+        let varsall = vec!["bike_index".to_string(), "natural".to_string()];
+        let (file1, file2) = ("./test_resources/dat1.json", "./test_resources/dat2.json");
+        let run = |f1: &str, f2: &str| {
+            uamutate_symmetric(
+                f1,
+                f2,
+                &varsall,
+                100,
+                &calculate_dists::GreedyMatcher,
+                &MutationConfig::default(),
+            )
+        };
+        let (forward, reverse) = run(file1, file2).unwrap();
+        assert_eq!(forward.ncols(), 4);
+        assert_eq!(reverse.ncols(), 4);
+        assert!(mutation_detected(&forward) && mutation_detected(&reverse));
+
+        let (reverse2, forward2) = run(file2, file1).unwrap();
+        assert_eq!(forward, forward2);
+        assert_eq!(reverse, reverse2);
+
+        let result = run(file1, "./test_resources/nonexistent.json");
+        assert!(matches!(result, Err(UaError::Io(_))));
+    }
+
+    #[test]
+    fn test_uamutate_duplicate_varnames() {
+        // As in 'main.rs', the mutation variable is repeated in the extra variables:
+        let varname = "bike_index".to_string();
+        let varextra = vec!["natural".to_string(), "bike_index".to_string()];
+        let varsall = [vec![varname], varextra].concat();
+        assert_eq!(
+            dedup_varnames(&varsall),
+            (
+                vec!["bike_index".to_string(), "natural".to_string()],
+                vec!["bike_index".to_string()]
+            )
+        );
+
+        let run = |vars: &[String]| {
+            run_fixtures(
+                "./test_resources/dat1.json",
+                "./test_resources/dat2.json",
+                vars,
+                100,
+                &MutationConfig::default(),
+            )
+            .unwrap()
+        };
+        let debug = run(&varsall);
+        let debug_unique = run(&varsall[..2]);
+        assert_eq!(debug.values1.ncols(), 2);
+        assert_eq!(debug.sums, debug_unique.sums);
+        assert_eq!(
+            debug.warnings,
+            vec![Warning::DuplicateVariable {
+                varname: "bike_index".to_string()
+            }]
+        );
+    }
+
+    #[test]
+    fn test_uamutate_debug() {
+        let varsall = vec![
+            "bike_index".to_string(),
+            "natural".to_string(),
+            "social_index".to_string(),
+        ];
+        let matcher = calculate_dists::GreedyMatcher;
+        let sums = uamutate(
+            &json_reader("./test_resources/dat1.json"),
+            &json_reader("./test_resources/dat2.json"),
+            &varsall,
+            100,
+            &matcher,
+            &MutationConfig::default(),
+        )
+        .unwrap();
+        let debug = uamutate_debug(
+            &json_reader("./test_resources/dat1.json"),
+            &json_reader("./test_resources/dat2.json"),
+            &varsall,
+            100,
+            &matcher,
+            &MutationConfig::default(),
+        )
+        .unwrap();
+
+        assert_eq!(debug.sums, sums);
+        assert_eq!(debug.beta1.len(), varsall.len());
+        assert_eq!(debug.beta2.len(), varsall.len());
+        assert_eq!(debug.dists.nrows(), debug.values1.nrows());
+        // Adjustment only changes first column:
+        assert_ne!(debug.values1.column(0), debug.values1_adjusted.column(0));
+        assert_eq!(debug.values1.column(1), debug.values1_adjusted.column(1));
+        // And retains the original mean and SD of that column:
+        let (mean0, sd0) = utils::mean_sd_dmat(&debug.values1).unwrap();
+        let (mean1, sd1) = utils::mean_sd_dmat(&debug.values1_adjusted).unwrap();
+        assert_abs_diff_eq!(mean0, mean1, epsilon = 1e-10);
+        assert_abs_diff_eq!(sd0, sd1, epsilon = 1e-10);
+    }
+
+    #[test]
+    fn test_uamutate_map() {
+        let varsall = vec!["bike_index".to_string(), "natural".to_string()];
+        let debug = run_fixtures(
+            "./test_resources/dat1.json",
+            "./test_resources/dat2.json",
+            &varsall,
+            100,
+            &MutationConfig::default(),
+        )
+        .unwrap();
+        let mutations = uamutate_map(
+            &json_reader("./test_resources/dat1.json"),
+            &json_reader("./test_resources/dat2.json"),
+            &varsall,
+            100,
+            &calculate_dists::GreedyMatcher,
+            &MutationConfig::default(),
+        )
+        .unwrap();
+
+        let mut groups = debug.groups.clone();
+        groups.sort();
+        groups.dedup();
+        assert_eq!(mutations.len(), groups.len());
+        for g in groups {
+            assert_eq!(mutations[&g], debug.sums[(g - 1, 3)]);
+        }
+
+        // Entries in group 0 are discarded:
+        let mutations = uamutate_map(
+            &json_reader("./test_resources/dat_group0.json"),
+            &json_reader("./test_resources/dat2.json"),
+            &varsall,
+            100,
+            &calculate_dists::GreedyMatcher,
+            &MutationConfig::default(),
+        )
+        .unwrap();
+        let mut keys: Vec<usize> = mutations.keys().cloned().collect();
+        keys.sort();
+        assert_eq!(keys, (1..=8).collect::<Vec<usize>>());
+    }
+
+    #[test]
+    fn test_uamutate_large_group_ids() {
+        let varsall = vec!["bike_index".to_string(), "natural".to_string()];
+        let result = uamutate(
+            &json_reader("./test_resources/dat_large_groups.json"),
+            &json_reader("./test_resources/dat2.json"),
+            &varsall,
+            100,
+            &calculate_dists::GreedyMatcher,
+            &MutationConfig::default(),
+        );
+        assert_eq!(
+            result.unwrap_err(),
+            UaError::GroupOutOfRange {
+                name: "reader1".to_string(),
+                group: usize::MAX,
+                max_group: MAX_GROUP,
+            }
+        );
+
+        // Sparse group numbers give the same results as consecutive group numbers:
+        let mutations = uamutate_map(
+            &json_reader("./test_resources/dat_large_groups.json"),
+            &json_reader("./test_resources/dat2.json"),
+            &varsall,
+            100,
+            &calculate_dists::GreedyMatcher,
+            &MutationConfig::default(),
+        )
+        .unwrap();
+        let mut json: serde_json::Value =
+            serde_json::from_reader(open("./test_resources/dat_large_groups.json")).unwrap();
+        let labels: Vec<usize> = (0..7)
+            .map(|k| 9007199254740993 + 2 * k)
+            .chain([usize::MAX])
+            .collect();
+        for record in json.as_array_mut().unwrap() {
+            let group = record["index"].as_u64().unwrap() as usize;
+            let dense = labels.iter().position(|&g| g == group).unwrap() + 1;
+            record["index"] = serde_json::json!(dense);
+        }
+        let fname = "/tmp/test_uamutate_large_group_ids.json";
+        std::fs::write(fname, json.to_string()).unwrap();
+        let dense = run_fixtures(
+            fname,
+            "./test_resources/dat2.json",
+            &varsall,
+            100,
+            &MutationConfig::default(),
+        )
+        .unwrap();
+        assert_eq!(mutations.len(), labels.len());
+        for (k, label) in labels.iter().enumerate() {
+            assert_eq!(mutations[label], dense.sums[(k, 3)]);
+        }
+    }
+
+    #[test]
+    fn test_mutation_detected_identical_inputs() {
+        let varsall = vec!["bike_index".to_string(), "natural".to_string()];
+        let sums = uamutate(
+            &json_reader("./test_resources/dat1.json"),
+            &json_reader("./test_resources/dat1.json"),
+            &varsall,
+            10,
+            &calculate_dists::GreedyMatcher,
+            &MutationConfig::default(),
+        )
+        .unwrap();
+        assert!(!mutation_detected(&sums));
+
+        let sums = uamutate(
+            &json_reader("./test_resources/dat1.json"),
+            &json_reader("./test_resources/dat2.json"),
+            &varsall,
+            10,
+            &calculate_dists::GreedyMatcher,
+            &MutationConfig::default(),
+        )
+        .unwrap();
+        assert!(mutation_detected(&sums));
+    }
+
+    #[test]
+    fn test_uamutate_single_observation() {
+        let varsall = vec!["bike_index".to_string(), "natural".to_string()];
+        let result = uamutate(
+            &json_reader("./test_resources/dat1.json"),
+            &json_reader("./test_resources/dat2.json"),
+            &varsall,
+            1,
+            &calculate_dists::GreedyMatcher,
+            &MutationConfig::default(),
+        );
+        assert_eq!(
+            result.unwrap_err(),
+            UaError::InsufficientData {
+                name: "nentries".to_string(),
+                nobs: 1,
+                min_nobs: MIN_NOBS
+            }
+        );
+    }
+
+    #[test]
+    fn test_uamutate_empty_input() {
+        let varsall = vec!["bike_index".to_string(), "natural".to_string()];
+        let result = uamutate(
+            &json_reader("./test_resources/dat1.json"),
+            &json_reader("./test_resources/empty.json"),
+            &varsall,
+            10,
+            &calculate_dists::GreedyMatcher,
+            &MutationConfig::default(),
+        );
+        assert_eq!(
+            result.unwrap_err(),
+            UaError::EmptyInput("reader2".to_string())
+        );
+    }
+
+    #[test]
+    fn test_validate_inputs() {
+        let varsall = vec!["transport".to_string(), "bike_index".to_string()];
+        let info = validate_inputs(
+            open("./test_resources/dat1.json"),
+            open("./test_resources/dat2.json"),
+            &varsall,
+            100,
+        )
+        .unwrap();
+        for var in &varsall {
+            assert!(info.varnames1.contains(var));
+            assert!(info.varnames2.contains(var));
+        }
+        assert_eq!(info.nrecords1, 1000);
+        assert_eq!(info.nrecords2, 1000);
+
+        let varsall = vec!["transport".to_string(), "cell_id".to_string()];
+        let result = validate_inputs(
+            open("./test_resources/dat_ids.json"),
+            open("./test_resources/dat2.json"),
+            &varsall,
+            100,
+        );
+        assert_eq!(
+            result.unwrap_err(),
+            UaError::MissingVariable {
+                name: "reader2".to_string(),
+                varname: "cell_id".to_string()
+            }
+        );
+
+        // Values which are null or absent in later records are counted as missing:
+        let varsall = vec!["transport".to_string(), "social_index".to_string()];
+        let info = validate_inputs(
+            open("./test_resources/dat_nan.json"),
+            open("./test_resources/dat_partial.json"),
+            &["transport".to_string(), "bike_index".to_string()],
+            100,
+        )
+        .unwrap();
+        assert_eq!((info.nmissing1, info.nmissing2), (3, 1));
+        let info = validate_inputs(
+            open("./test_resources/dat1.json"),
+            open("./test_resources/dat_partial.json"),
+            &varsall,
+            100,
+        )
+        .unwrap();
+        assert_eq!((info.nmissing1, info.nmissing2), (0, 2));
+    }
+
+    #[test]
+    fn test_uamutate_exhaustion() {
+        let varsall = vec!["transport".to_string(), "bike_index".to_string()];
+        let run = |on_exhaustion: Exhaustion| {
+            // Target data have only 4 entries:
+            uamutate(
+                &json_reader("./test_resources/dat1.json"),
+                &json_reader("./test_resources/dat_ids.json"),
+                &varsall,
+                10,
+                &calculate_dists::GreedyMatcher,
+                &MutationConfig {
+                    on_exhaustion,
+                    ..Default::default()
+                },
+            )
+        };
+
+        // Only first 4 entries of 'dat1' are mutated, which are in 4 groups:
+        let stop = run(Exhaustion::Stop).unwrap();
+        assert_eq!(stop.nrows(), 4);
+
+        let err = run(Exhaustion::Error).unwrap_err();
+        assert_eq!(
+            err,
+            UaError::InsufficientData {
+                name: "reader2".to_string(),
+                nobs: 4,
+                min_nobs: 10
+            }
+        );
+
+        // All 10 entries are mutated by reusing targets:
+        let reuse = run(Exhaustion::Reuse).unwrap();
+        assert_eq!(reuse.nrows(), 10);
+        assert!(reuse.iter().all(|x| x.is_finite()));
+    }
+
+    #[test]
+    fn test_aggregate_and_write() {
+        let values1 = DMatrix::from_vec(5, 1, vec![0.5, 1.0, 0.2, 0.3, 0.8]);
+        let dists = DMatrix::from_vec(
+            5,
+            2,
+            vec![0.1, -0.2, 0.3, 0.05, 0.4, 0.2, -0.2, 1.5, 0.17, 0.5],
+        );
+        // Groups are sorted, with group 3 empty:
+        let groups = vec![1, 1, 2, 4, 4];
+
+        for log_scale in [false, true] {
+            let sums = aggregate_to_groups(&values1, &dists, &groups, &log_scale, None);
+            let mut batch: Vec<u8> = Vec::new();
+            read_write_file::write_to(&mut batch, &sums).unwrap();
+
+            let mut streamed: Vec<u8> = Vec::new();
+            aggregate_and_write(&values1, &dists, &groups, &log_scale, &mut streamed).unwrap();
+
+            assert_eq!(
+                String::from_utf8(streamed).unwrap(),
+                String::from_utf8(batch).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "groups must be sorted in increasing order")]
+    fn test_aggregate_and_write_unsorted_groups() {
+        let values1 = DMatrix::from_vec(2, 1, vec![1.0, 2.0]);
+        let dists = DMatrix::from_vec(2, 2, vec![1.0, 2.0, 3.0, 4.0]);
+        let groups = vec![2, 1];
+        let _ = aggregate_and_write(&values1, &dists, &groups, &false, std::io::sink());
+    }
+
     #[test]
     #[should_panic(expected = "dists must have two columns")]
     fn test_aggregate_to_groups_invalid_dists_columns() {
@@ -257,7 +2987,7 @@ mod tests {
         let dists = DMatrix::from_vec(1, 1, vec![1.0]);
         let groups = vec![1];
         let log_scale = false;
-        aggregate_to_groups(&values1, &dists, &groups, &log_scale);
+        aggregate_to_groups(&values1, &dists, &groups, &log_scale, None);
     }
 
     #[test]
@@ -267,7 +2997,7 @@ mod tests {
         let dists = DMatrix::from_vec(2, 2, vec![1.0, 2.0, 3.0, 4.0]);
         let groups = vec![1, 2];
         let log_scale = false;
-        aggregate_to_groups(&values1, &dists, &groups, &log_scale);
+        aggregate_to_groups(&values1, &dists, &groups, &log_scale, None);
     }
 
     #[test]
@@ -277,6 +3007,6 @@ mod tests {
         let dists = DMatrix::from_vec(1, 2, vec![1.0, 2.0]);
         let groups = vec![1, 2];
         let log_scale = false;
-        aggregate_to_groups(&values1, &dists, &groups, &log_scale);
+        aggregate_to_groups(&values1, &dists, &groups, &log_scale, None);
     }
 }
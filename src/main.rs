@@ -6,9 +6,7 @@ use std::fs::File;
 use std::io::BufReader;
 
 extern crate uamutations;
-pub mod mlr;
-pub mod read_write_file;
-pub mod utils;
+use uamutations::read_write_file;
 
 const NENTRIES: usize = 10000;
 // const NENTRIES: usize = 1000;
@@ -41,11 +39,19 @@ fn main() {
     let varsall = [varsall, varextra].concat();
 
     let file1 = File::open(FNAME1).unwrap();
-    let reader1 = BufReader::new(file1);
+    let reader1 = read_write_file::JsonReader::from_reader(BufReader::new(file1)).unwrap();
     let file2 = File::open(FNAME2).unwrap();
-    let reader2 = BufReader::new(file2);
-
-    let sums = uamutations::uamutate(reader1, reader2, &varsall, NENTRIES);
-
-    read_write_file::write_file(&sums, OUTFILENAME);
+    let reader2 = read_write_file::JsonReader::from_reader(BufReader::new(file2)).unwrap();
+
+    let sums = uamutations::uamutate(
+        &reader1,
+        &reader2,
+        &varsall,
+        NENTRIES,
+        &uamutations::calculate_dists::GreedyMatcher,
+        &uamutations::MutationConfig::default(),
+    )
+    .unwrap();
+
+    read_write_file::write_file_named(&sums, OUTFILENAME, VARNAME).unwrap();
 }
@@ -1,5 +1,8 @@
 use nalgebra::{DMatrix, DVector, SVD};
+use serde_json::Value;
 
+use crate::error::UaError;
+use crate::stats;
 use crate::utils;
 
 /// Calculates beta coefficients (slopes) of a multiple linear regression of dimensions [1.., _] of
@@ -9,13 +12,15 @@ use crate::utils;
 ///
 /// * `data` - An nalgebra::DMatrix object of [observations, variables].
 ///
-/// # Panics
+/// # Errors
 ///
-/// This function will panic if `data` is empty.
+/// Returns `UaError::EmptyInput` if `data` is empty.
 ///
 /// # Returns
 ///
-/// Vector of f64 values of multiple linear regression coefficients, one for each variable.
+/// Vector of f64 values of multiple linear regression coefficients, one for each variable. The
+/// first value is the intercept. Where `data` has only two variables, coefficients are those of a
+/// simple linear regression.
 ///
 /// # Example
 ///
@@ -28,7 +33,7 @@ use crate::utils;
 ///     2.1, 3.2, 4.1, 5.2, 5.9
 /// ];
 /// let data_2 = DMatrix::from_vec(5, 2, data_2);
-/// let result_2 = mlr_beta(&data_2);
+/// let result_2 = mlr_beta(&data_2).unwrap();
 /// println!("Result with 2 variables: {:?}", result_2);
 ///
 /// // Example with 3 variables
@@ -38,15 +43,17 @@ use crate::utils;
 ///     3.0, 4.1, 4.9, 6.0, 7.1,
 /// ];
 /// let data_3 = DMatrix::from_vec(5, 3, data_3);
-/// let result_3 = mlr_beta(&data_3);
+/// let result_3 = mlr_beta(&data_3).unwrap();
 /// println!("Result with 3 variables: {:?}", result_3);
 /// ```
-pub fn mlr_beta(data: &DMatrix<f64>) -> Vec<f64> {
-    assert!(!data.is_empty(), "values1 must not be empty");
-    assert!(
-        data.nrows() > 0 && data.ncols() > 0,
-        "Data must have at least one row and one column"
-    );
+pub fn mlr_beta(data: &DMatrix<f64>) -> Result<Vec<f64>, UaError> {
+    if data.is_empty() {
+        return Err(UaError::EmptyInput("data".to_string()));
+    }
+
+    if data.ncols() == 2 {
+        return Ok(simple_regression(data));
+    }
 
     let mut data_clone = data.clone();
     // Take first col as target_var:
@@ -58,7 +65,105 @@ pub fn mlr_beta(data: &DMatrix<f64>) -> Vec<f64> {
     let svd = SVD::new(data_clone, true, true);
     let b = svd.solve(&target_var, 0.0).unwrap();
 
-    b.iter().cloned().collect()
+    Ok(b.iter().cloned().collect())
+}
+
+/// Calculates regression coefficients of the first column of `data` against all other columns,
+/// as for `mlr_beta`, but by weighted least squares with one weight for each observation.
+///
+/// Weights need not sum to one. Uniform weights give the same coefficients as `mlr_beta`.
+///
+/// # Arguments
+///
+/// * `data` - An nalgebra::DMatrix object of [observations, variables].
+/// * `weights` - Non-negative weight of each observation, with the same length as the number of
+///   rows of `data`.
+///
+/// # Errors
+///
+/// Returns `UaError::EmptyInput` if `data` is empty.
+///
+/// # Panics
+///
+/// This function will panic if `weights` has a different length to the number of rows of `data`,
+/// or if any weights are negative.
+///
+/// # Returns
+///
+/// Vector of regression coefficients, one for each variable, with the intercept first.
+///
+/// # Example
+///
+/// ```
+/// use nalgebra::DMatrix;
+/// use uamutations::mlr::mlr_beta_weighted;
+/// // The last observation lies off the line y = 2x, and is given zero weight:
+/// let data = DMatrix::from_vec(4, 2, vec![2.0, 4.0, 6.0, 0.0, 1.0, 2.0, 3.0, 4.0]);
+/// let beta = mlr_beta_weighted(&data, &[1.0, 1.0, 1.0, 0.0]).unwrap();
+/// assert!(beta[0].abs() < 1e-12 && (beta[1] - 2.0).abs() < 1e-12);
+/// ```
+pub fn mlr_beta_weighted(data: &DMatrix<f64>, weights: &[f64]) -> Result<Vec<f64>, UaError> {
+    if data.is_empty() {
+        return Err(UaError::EmptyInput("data".to_string()));
+    }
+    assert_eq!(
+        weights.len(),
+        data.nrows(),
+        "weights must have same length as number of rows of data"
+    );
+    assert!(
+        weights.iter().all(|&w| w >= 0.0),
+        "weights must not be negative"
+    );
+
+    if data.ncols() == 2 {
+        let (y, x) = (data.column(0), data.column(1));
+        let sum_w: f64 = weights.iter().sum();
+        let wmean = |v: &[f64]| v.iter().zip(weights).map(|(a, w)| a * w).sum::<f64>() / sum_w;
+        let xmean = wmean(x.as_slice());
+        let ymean = wmean(y.as_slice());
+        let sxy: f64 = (0..data.nrows())
+            .map(|i| weights[i] * (x[i] - xmean) * (y[i] - ymean))
+            .sum();
+        let sxx: f64 = (0..data.nrows())
+            .map(|i| weights[i] * (x[i] - xmean).powi(2))
+            .sum();
+        let slope = if sxx > 0.0 { sxy / sxx } else { 0.0 };
+        return Ok(vec![ymean - slope * xmean, slope]);
+    }
+
+    // Weighted least squares is ordinary least squares with each row scaled by sqrt(weight):
+    let sqrt_w: Vec<f64> = weights.iter().map(|w| w.sqrt()).collect();
+    let design = DMatrix::from_fn(data.nrows(), data.ncols(), |i, j| {
+        if j == 0 {
+            sqrt_w[i]
+        } else {
+            data[(i, j)] * sqrt_w[i]
+        }
+    });
+    let target_var = DVector::from_fn(data.nrows(), |i, _| data[(i, 0)] * sqrt_w[i]);
+    let svd = SVD::new(design, true, true);
+    let b = svd.solve(&target_var, 0.0).unwrap();
+
+    Ok(b.iter().cloned().collect())
+}
+
+/// Intercept and slope of a simple linear regression of the first column of `data` against the
+/// second. If the second column has no variance, the slope is zero.
+fn simple_regression(data: &DMatrix<f64>) -> Vec<f64> {
+    let y = data.column(0);
+    let x = data.column(1);
+    let xmean = x.mean();
+    let ymean = y.mean();
+    let sxy: f64 = x
+        .iter()
+        .zip(y.iter())
+        .map(|(&xi, &yi)| (xi - xmean) * (yi - ymean))
+        .sum();
+    let sxx: f64 = x.iter().map(|&xi| (xi - xmean).powi(2)).sum();
+    let slope = if sxx > 0.0 { sxy / sxx } else { 0.0 };
+
+    vec![ymean - slope * xmean, slope]
 }
 
 /// Adjusts the first row of `values1` based on the multi-linear regression coefficients of the
@@ -71,51 +176,545 @@ pub fn mlr_beta(data: &DMatrix<f64>) -> Vec<f64> {
 /// # Arguments
 ///
 /// * `values1` - A 2D array where the first row is the variable to be adjusted and the remaining
-/// rows are the other variables.
+///   rows are the other variables.
 /// * `values2` - A 2D array with the same structure as `values1`, used to calculate the MLR
-/// coefficients for adjustment.
+///   coefficients for adjustment.
 ///
-/// * Example
-/// let mut v1 = array![[1.0, 2.0, 3.0, 4.0, 5.0], [2.1, 3.2, 4.1, 5.2, 5.9]];
+/// # Returns
+///
+/// The regression coefficients of `values1` and `values2`, as returned from `mlr_beta`.
+///
+/// # Errors
+///
+/// Returns `UaError::EmptyInput` if either `values1` or `values2` is empty.
+///
+/// # Example
+///
+/// ```
+/// use nalgebra::DMatrix;
+/// use uamutations::mlr::adj_for_beta;
+/// let v1 = vec![1.0, 2.0, 3.0, 4.0, 5.0, 2.1, 3.2, 4.1, 5.2, 5.9];
+/// let mut v1 = DMatrix::from_vec(5, 2, v1);
 /// let v1_orig = v1.clone();
-/// let v2 = array![[1.0, 2.0, 3.0, 4.0, 5.0], [3.1, 4.3, 5.3, 6.5, 7.3]];
-/// adj_for_beta(&mut v1, &v2);
+/// let v2 = vec![1.0, 2.0, 3.0, 4.0, 5.0, 3.1, 4.3, 5.3, 6.5, 7.3];
+/// let v2 = DMatrix::from_vec(5, 2, v2);
+/// adj_for_beta(&mut v1, &v2).unwrap();
 /// assert_ne!(v1, v1_orig, "v1 should differ from v1_orig");
 /// assert_eq!(
-///     v1.slice(s![1.., ..]),
-///     v1_orig.slice(s![1.., ..]),
-///     "Only the first row of v1 should be different"
+///     v1.column(1),
+///     v1_orig.column(1),
+///     "Only the first column of v1 should be different"
 /// );
-pub fn adj_for_beta(values1: &mut DMatrix<f64>, values2: &DMatrix<f64>) {
-    let (mean1, sd1) = utils::mean_sd_dmat(values1);
-
+/// ```
+pub fn adj_for_beta(
+    values1: &mut DMatrix<f64>,
+    values2: &DMatrix<f64>,
+) -> Result<(Vec<f64>, Vec<f64>), UaError> {
     // Calculate MLR regression coefficients between first variables and all others:
-    let mut beta1 = mlr_beta(values1);
-    beta1[0] = 0.0;
-    let mut beta2 = mlr_beta(values2);
-    beta2[0] = 0.0;
+    let beta1 = mlr_beta(values1).map_err(|_| UaError::EmptyInput("values1".to_string()))?;
+    let beta2 = mlr_beta(values2).map_err(|_| UaError::EmptyInput("values2".to_string()))?;
+    adjust_first_column(values1, values2, &beta1, &beta2)?;
+
+    Ok((beta1, beta2))
+}
 
-    let b1 = DMatrix::from_fn(values1.nrows(), values1.ncols(), |_, r| beta1[r]);
-    let b2 = DMatrix::from_fn(values1.nrows(), values1.ncols(), |_, r| beta2[r]);
+/// Adjusts the first column of `values1` as for `adj_for_beta`, but using robust regression
+/// coefficients from `mlr_beta_robust`, so that adjustments are less affected by outliers.
+///
+/// # Arguments
+///
+/// * `values1` - A 2D array where the first column is the variable to be adjusted and the
+///   remaining columns are the other variables.
+/// * `values2` - A 2D array with the same structure as `values1`.
+///
+/// # Returns
+///
+/// The robust regression coefficients of `values1` and `values2`.
+///
+/// # Errors
+///
+/// Returns `UaError::EmptyInput` if either `values1` or `values2` is empty.
+pub fn adj_for_beta_robust(
+    values1: &mut DMatrix<f64>,
+    values2: &DMatrix<f64>,
+) -> Result<(Vec<f64>, Vec<f64>), UaError> {
+    let beta1 = mlr_beta_robust(values1).map_err(|_| UaError::EmptyInput("values1".to_string()))?;
+    let beta2 = mlr_beta_robust(values2).map_err(|_| UaError::EmptyInput("values2".to_string()))?;
+    adjust_first_column(values1, values2, &beta1, &beta2)?;
 
-    let product1 = values1.component_mul(&b1);
-    let product2 = values2.component_mul(&b2);
+    Ok((beta1, beta2))
+}
 
-    let sum1: DVector<f64> = product1.column_sum();
-    let sum2: DVector<f64> = product2.column_sum();
-    // Adjust so that mean effect of sum2 - sum1 is zero:
-    let sum_sum1: f64 = sum1.iter().sum();
-    let mean_sum1 = sum_sum1 / sum1.len() as f64;
-    let sum_sum2: f64 = sum2.iter().sum();
-    let mean_sum2 = sum_sum2 / sum2.len() as f64;
-    let adjusted_sum2: DVector<f64> = sum2.map(|x| x + mean_sum1 - mean_sum2);
-    let first_column = values1.column(0).clone_owned() + adjusted_sum2 - sum1.clone();
+/// Adjusts the first column of `values1` as for `adj_for_beta`, but with regression coefficients
+/// of `values1` estimated by weighted least squares with `mlr_beta_weighted`.
+///
+/// Weights apply only to the regression of `values1`. Coefficients of `values2` are unweighted,
+/// as are the mean and standard deviation to which adjusted values are rescaled.
+///
+/// # Arguments
+///
+/// * `values1` - A 2D array where the first column is the variable to be adjusted and the
+///   remaining columns are the other variables.
+/// * `values2` - A 2D array with the same structure as `values1`.
+/// * `weights1` - Weight of each observation (row) of `values1`.
+///
+/// # Returns
+///
+/// The regression coefficients of `values1` and `values2`.
+///
+/// # Errors
+///
+/// Returns `UaError::EmptyInput` if either `values1` or `values2` is empty.
+///
+/// # Panics
+///
+/// This function will panic under the same conditions as `mlr_beta_weighted`.
+pub fn adj_for_beta_weighted(
+    values1: &mut DMatrix<f64>,
+    values2: &DMatrix<f64>,
+    weights1: &[f64],
+) -> Result<(Vec<f64>, Vec<f64>), UaError> {
+    let beta1 = mlr_beta_weighted(values1, weights1)
+        .map_err(|_| UaError::EmptyInput("values1".to_string()))?;
+    let beta2 = mlr_beta(values2).map_err(|_| UaError::EmptyInput("values2".to_string()))?;
+    adjust_first_column(values1, values2, &beta1, &beta2)?;
 
-    // Then finally adjust values to have same (mean, sd) as original values:
-    let (mean2, sd2) = utils::mean_sd_column(&first_column);
-    let first_column = first_column.map(|x| ((x - mean2) / sd2) * sd1 + mean1);
+    Ok((beta1, beta2))
+}
 
-    values1.set_column(0, &first_column);
+/// Calculates regression coefficients of the first column of `data` against all other columns,
+/// as for `mlr_beta`, but using estimators which are robust to outliers.
+///
+/// With a single covariate, this is the Theil-Sen estimator, for which the slope is the median of
+/// slopes between all pairs of observations, and the intercept is the median residual. This
+/// tolerates up to around 29% of observations being outliers, but takes time quadratic in the
+/// number of observations. With several covariates, coefficients are estimated by iteratively
+/// reweighted least squares with Huber weights, starting from the ordinary least squares
+/// solution. This down-weights observations with large residuals, but is not robust to outliers
+/// in the covariates themselves (high-leverage points).
+///
+/// # Arguments
+///
+/// * `data` - An nalgebra::DMatrix object of [observations, variables].
+///
+/// # Errors
+///
+/// Returns `UaError::EmptyInput` if `data` is empty.
+///
+/// # Returns
+///
+/// Vector of regression coefficients, one for each variable, with the intercept first.
+pub fn mlr_beta_robust(data: &DMatrix<f64>) -> Result<Vec<f64>, UaError> {
+    if data.is_empty() {
+        return Err(UaError::EmptyInput("data".to_string()));
+    }
+
+    let y: Vec<f64> = data.column(0).iter().cloned().collect();
+    match data.ncols() {
+        1 => Ok(vec![stats::quantile(&y, 0.5)]),
+        2 => Ok(theil_sen(data)),
+        _ => huber_irls(data, 20),
+    }
+}
+
+/// Theil-Sen estimates of the intercept and slope of the first column of `data` against the
+/// second.
+fn theil_sen(data: &DMatrix<f64>) -> Vec<f64> {
+    let (y, x) = (data.column(0), data.column(1));
+    let mut slopes = Vec::new();
+    for i in 0..data.nrows() {
+        for j in (i + 1)..data.nrows() {
+            if x[j] != x[i] {
+                slopes.push((y[j] - y[i]) / (x[j] - x[i]));
+            }
+        }
+    }
+    let slope = if slopes.is_empty() {
+        0.0
+    } else {
+        stats::quantile(&slopes, 0.5)
+    };
+    let residuals: Vec<f64> = y
+        .iter()
+        .zip(x.iter())
+        .map(|(yi, xi)| yi - slope * xi)
+        .collect();
+
+    vec![stats::quantile(&residuals, 0.5), slope]
+}
+
+/// Regression coefficients of the first column of `data` against all others, estimated by
+/// iteratively reweighted least squares with Huber weights.
+fn huber_irls(data: &DMatrix<f64>, max_iter: usize) -> Result<Vec<f64>, UaError> {
+    // Tuning constant for 95% efficiency under normal errors:
+    let k = 1.345;
+    let y = data.column(0).clone_owned();
+    let mut design = data.clone();
+    design.set_column(0, &DVector::from_element(data.nrows(), 1.0));
+
+    let mut beta = mlr_beta(data)?;
+    for _ in 0..max_iter {
+        let residuals: Vec<f64> = (&y - &design * DVector::from_vec(beta.clone()))
+            .iter()
+            .cloned()
+            .collect();
+        // Robust scale from the median absolute deviation:
+        let abs_resid: Vec<f64> = residuals.iter().map(|r| r.abs()).collect();
+        let scale = stats::quantile(&abs_resid, 0.5) / 0.6745;
+        if scale <= f64::EPSILON {
+            break;
+        }
+        let sqrt_w: Vec<f64> = residuals
+            .iter()
+            .map(|r| (k * scale / r.abs()).min(1.0).sqrt())
+            .collect();
+        let weighted_design = DMatrix::from_fn(design.nrows(), design.ncols(), |i, j| {
+            design[(i, j)] * sqrt_w[i]
+        });
+        let weighted_y = DVector::from_fn(y.len(), |i, _| y[i] * sqrt_w[i]);
+        let svd = SVD::new(weighted_design, true, true);
+        let beta_new: Vec<f64> = svd
+            .solve(&weighted_y, 0.0)
+            .unwrap()
+            .iter()
+            .cloned()
+            .collect();
+        let change: f64 = beta_new
+            .iter()
+            .zip(beta.iter())
+            .map(|(a, b)| (a - b).abs())
+            .sum();
+        beta = beta_new;
+        if change < 1e-10 {
+            break;
+        }
+    }
+
+    Ok(beta)
+}
+
+/// Replace the first column of `values1` with values adjusted for the covariates of `values2`
+/// given the regression coefficients of both, retaining the original mean and standard deviation.
+fn adjust_first_column(
+    values1: &mut DMatrix<f64>,
+    values2: &DMatrix<f64>,
+    beta1: &[f64],
+    beta2: &[f64],
+) -> Result<(), UaError> {
+    let model = adjustment_model(values1, values2, beta1, beta2, &[])?;
+    apply_adjustment(values1, &model)
+}
+
+/// Values of the first column of `values` adjusted for the covariates of `target_covariates`,
+/// prior to rescaling.
+fn unscaled_adjustment(
+    values: &DMatrix<f64>,
+    target_covariates: &DMatrix<f64>,
+    beta1: &[f64],
+    beta2: &[f64],
+    offset: f64,
+) -> DVector<f64> {
+    let covariates1 = values.columns(1, values.ncols() - 1);
+    DVector::from_fn(values.nrows(), |i, _| {
+        let x1: Vec<f64> = covariates1.row(i).iter().cloned().collect();
+        let x2: Vec<f64> = target_covariates.row(i).iter().cloned().collect();
+        adjust_observation(values[(i, 0)], &x1, &x2, &beta1[1..], &beta2[1..]) + offset
+    })
+}
+
+/// Calculate all parameters of the adjustment applied by `adjust_first_column`.
+fn adjustment_model(
+    values1: &DMatrix<f64>,
+    values2: &DMatrix<f64>,
+    beta1: &[f64],
+    beta2: &[f64],
+    varnames: &[String],
+) -> Result<AdjustmentModel, UaError> {
+    let (mean, sd) = utils::mean_sd_dmat(values1)?;
+
+    // Adjust so that mean effect of covariates in `values2` relative to `values1` is zero:
+    let covariate_mean = |values: &DMatrix<f64>, beta: &[f64]| {
+        let cov_means: Vec<f64> = (1..values.ncols())
+            .map(|j| values.column(j).mean())
+            .collect();
+        linear_effect(&cov_means, &beta[1..])
+    };
+    let offset = covariate_mean(values1, beta1) - covariate_mean(values2, beta2);
+    let target_covariates = values2
+        .view((0, 1), (values1.nrows(), values2.ncols() - 1))
+        .clone_owned();
+
+    // Statistics of adjusted values, used to rescale them to the original (mean, sd):
+    let adjusted = unscaled_adjustment(values1, &target_covariates, beta1, beta2, offset);
+    let (adjusted_mean, adjusted_sd) = utils::mean_sd_column(&adjusted)?;
+
+    Ok(AdjustmentModel {
+        varnames: varnames.to_vec(),
+        beta1: beta1.to_vec(),
+        beta2: beta2.to_vec(),
+        target_covariates,
+        offset,
+        adjusted_mean,
+        adjusted_sd,
+        mean,
+        sd,
+    })
+}
+
+/// The adjustment applied by `adj_for_beta`, fitted with `fit_adjustment`, which may be stored and
+/// applied to other data with `apply_adjustment` without refitting.
+///
+/// The adjustment of each observation depends on the covariates of the corresponding row of the
+/// mutation target, so that models may only be applied to data with the same number of rows as
+/// those to which they were fitted.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AdjustmentModel {
+    /// Names of variables, the first of which is adjusted, and the remainder of which are
+    /// covariates.
+    pub varnames: Vec<String>,
+    /// Regression coefficients of the data to be adjusted, with the intercept first.
+    pub beta1: Vec<f64>,
+    /// Regression coefficients of the mutation target, with the intercept first.
+    pub beta2: Vec<f64>,
+    /// Covariates of the mutation target, with one row for each observation.
+    pub target_covariates: DMatrix<f64>,
+    /// Difference in mean linear effects of covariates, added to all adjusted values.
+    pub offset: f64,
+    /// Mean of adjusted values prior to rescaling.
+    pub adjusted_mean: f64,
+    /// Standard deviation of adjusted values prior to rescaling.
+    pub adjusted_sd: f64,
+    /// Mean to which adjusted values are rescaled, being that of the original values.
+    pub mean: f64,
+    /// Standard deviation to which adjusted values are rescaled, being that of the original
+    /// values.
+    pub sd: f64,
+}
+
+impl AdjustmentModel {
+    /// Serialise the model to a JSON string, which may be read back with `from_json`.
+    pub fn to_json(&self) -> String {
+        let rows: Vec<Vec<f64>> = self
+            .target_covariates
+            .row_iter()
+            .map(|row| row.iter().cloned().collect())
+            .collect();
+        serde_json::json!({
+            "varnames": self.varnames,
+            "beta1": self.beta1,
+            "beta2": self.beta2,
+            "target_covariates": rows,
+            "offset": self.offset,
+            "adjusted_mean": self.adjusted_mean,
+            "adjusted_sd": self.adjusted_sd,
+            "mean": self.mean,
+            "sd": self.sd,
+        })
+        .to_string()
+    }
+
+    /// Read a model from a JSON string written by `to_json`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `UaError::Parse` if `text` is not valid JSON, or if any field is missing or of the
+    /// wrong type.
+    pub fn from_json(text: &str) -> Result<Self, UaError> {
+        let value: Value = serde_json::from_str(text)
+            .map_err(|e| UaError::Parse(format!("Unable to parse adjustment model: {}", e)))?;
+        let field = |name: &str| {
+            value
+                .get(name)
+                .ok_or_else(|| UaError::Parse(format!("Adjustment model has no field '{}'", name)))
+        };
+        let invalid = |name: &str| UaError::Parse(format!("Invalid field '{}'", name));
+        let number = |name: &str| field(name)?.as_f64().ok_or_else(|| invalid(name));
+        let numbers = |v: &Value, name: &str| -> Result<Vec<f64>, UaError> {
+            v.as_array()
+                .ok_or_else(|| invalid(name))?
+                .iter()
+                .map(|x| x.as_f64().ok_or_else(|| invalid(name)))
+                .collect()
+        };
+
+        let varnames = field("varnames")?
+            .as_array()
+            .ok_or_else(|| invalid("varnames"))?
+            .iter()
+            .map(|v| {
+                v.as_str()
+                    .map(String::from)
+                    .ok_or_else(|| invalid("varnames"))
+            })
+            .collect::<Result<Vec<String>, UaError>>()?;
+        let rows = field("target_covariates")?
+            .as_array()
+            .ok_or_else(|| invalid("target_covariates"))?
+            .iter()
+            .map(|row| numbers(row, "target_covariates"))
+            .collect::<Result<Vec<Vec<f64>>, UaError>>()?;
+        let beta1 = numbers(field("beta1")?, "beta1")?;
+        let ncovariates = beta1.len().saturating_sub(1);
+        if let Some(row) = rows.iter().find(|row| row.len() != ncovariates) {
+            return Err(UaError::LengthMismatch {
+                name: "target_covariates".to_string(),
+                len: row.len(),
+                expected: ncovariates,
+            });
+        }
+
+        Ok(AdjustmentModel {
+            varnames,
+            beta2: numbers(field("beta2")?, "beta2")?,
+            beta1,
+            target_covariates: DMatrix::from_row_iterator(
+                rows.len(),
+                ncovariates,
+                rows.into_iter().flatten(),
+            ),
+            offset: number("offset")?,
+            adjusted_mean: number("adjusted_mean")?,
+            adjusted_sd: number("adjusted_sd")?,
+            mean: number("mean")?,
+            sd: number("sd")?,
+        })
+    }
+}
+
+/// Fit the adjustment applied by `adj_for_beta`, without modifying `values1`.
+///
+/// # Arguments
+///
+/// * `values1` - A 2D array where the first column is the variable to be adjusted and the
+///   remaining columns are the other variables.
+/// * `values2` - A 2D array with the same structure as `values1`.
+/// * `varnames` - Names of the columns of `values1`, stored with the model.
+///
+/// # Returns
+///
+/// An `AdjustmentModel` which, when passed to `apply_adjustment` along with `values1`, gives
+/// exactly the same result as `adj_for_beta`.
+///
+/// # Errors
+///
+/// Returns `UaError::EmptyInput` if either `values1` or `values2` is empty.
+///
+/// # Example
+///
+/// ```
+/// use nalgebra::DMatrix;
+/// use uamutations::mlr::{adj_for_beta, apply_adjustment, fit_adjustment, AdjustmentModel};
+/// let v1 = DMatrix::from_vec(5, 2, vec![1.0, 2.0, 3.0, 4.0, 5.0, 2.1, 3.2, 4.1, 5.2, 5.9]);
+/// let v2 = DMatrix::from_vec(5, 2, vec![1.0, 2.0, 3.0, 4.0, 5.0, 3.1, 4.3, 5.3, 6.5, 7.3]);
+/// let varnames = vec!["bike_index".to_string(), "natural".to_string()];
+/// let model = fit_adjustment(&v1, &v2, &varnames).unwrap();
+///
+/// // Models may be stored, and later applied to give the same result as `adj_for_beta`:
+/// let model = AdjustmentModel::from_json(&model.to_json()).unwrap();
+/// let mut applied = v1.clone();
+/// apply_adjustment(&mut applied, &model).unwrap();
+/// let mut adjusted = v1.clone();
+/// adj_for_beta(&mut adjusted, &v2).unwrap();
+/// assert_eq!(applied, adjusted);
+/// ```
+pub fn fit_adjustment(
+    values1: &DMatrix<f64>,
+    values2: &DMatrix<f64>,
+    varnames: &[String],
+) -> Result<AdjustmentModel, UaError> {
+    let beta1 = mlr_beta(values1).map_err(|_| UaError::EmptyInput("values1".to_string()))?;
+    let beta2 = mlr_beta(values2).map_err(|_| UaError::EmptyInput("values2".to_string()))?;
+    adjustment_model(values1, values2, &beta1, &beta2, varnames)
+}
+
+/// Replace the first column of `values` with values adjusted by a fitted `AdjustmentModel`.
+///
+/// # Arguments
+///
+/// * `values` - A 2D array with the same structure as that to which `model` was fitted.
+/// * `model` - An `AdjustmentModel` returned from `fit_adjustment`.
+///
+/// # Errors
+///
+/// Returns `UaError::LengthMismatch` if `values` does not have the same number of rows as the
+/// target covariates of `model`.
+///
+/// # Panics
+///
+/// This function will panic if `values` does not have one more column than the number of
+/// covariates of `model`.
+pub fn apply_adjustment(values: &mut DMatrix<f64>, model: &AdjustmentModel) -> Result<(), UaError> {
+    if values.nrows() != model.target_covariates.nrows() {
+        return Err(UaError::LengthMismatch {
+            name: "values".to_string(),
+            len: values.nrows(),
+            expected: model.target_covariates.nrows(),
+        });
+    }
+    assert_eq!(
+        values.ncols(),
+        model.target_covariates.ncols() + 1,
+        "values must have one column more than the number of covariates."
+    );
+
+    let adjusted = unscaled_adjustment(
+        values,
+        &model.target_covariates,
+        &model.beta1,
+        &model.beta2,
+        model.offset,
+    );
+    let adjusted =
+        adjusted.map(|x| ((x - model.adjusted_mean) / model.adjusted_sd) * model.sd + model.mean);
+    values.set_column(0, &adjusted);
+
+    Ok(())
+}
+
+/// Adjusts a single observation by removing the linear effects of its own covariates, and adding
+/// the linear effects of the covariates of the corresponding observation of the mutation target:
+///
+/// `value - sum_j(beta1[j] * covariates1[j]) + sum_j(beta2[j] * covariates2[j])`
+///
+/// This is the adjustment applied to each observation in `adj_for_beta`, prior to centering and
+/// rescaling.
+///
+/// # Arguments
+///
+/// * `value` - Value of the variable to be adjusted.
+/// * `covariates1` - Values of covariates of the observation being adjusted.
+/// * `covariates2` - Values of the same covariates of the corresponding target observation.
+/// * `beta1` - Regression coefficients of `covariates1`, excluding the intercept.
+/// * `beta2` - Regression coefficients of `covariates2`, excluding the intercept.
+///
+/// # Panics
+///
+/// This function will panic in debug builds if the covariates and coefficients do not all have
+/// the same length.
+///
+/// # Example
+///
+/// ```
+/// use uamutations::mlr::adjust_observation;
+/// let adjusted = adjust_observation(10.0, &[1.0, 2.0], &[3.0, 1.0], &[0.5, 1.0], &[1.0, 2.0]);
+/// // 10 - (0.5 + 2) + (3 + 2):
+/// assert_eq!(adjusted, 12.5);
+/// ```
+pub fn adjust_observation(
+    value: f64,
+    covariates1: &[f64],
+    covariates2: &[f64],
+    beta1: &[f64],
+    beta2: &[f64],
+) -> f64 {
+    debug_assert_eq!(covariates1.len(), beta1.len());
+    debug_assert_eq!(covariates2.len(), beta2.len());
+    value - linear_effect(covariates1, beta1) + linear_effect(covariates2, beta2)
+}
+
+/// Sum of products of covariates and their regression coefficients.
+fn linear_effect(covariates: &[f64], beta: &[f64]) -> f64 {
+    covariates.iter().zip(beta).map(|(x, b)| x * b).sum()
 }
 
 #[cfg(test)]
@@ -135,7 +734,7 @@ mod tests {
         assert!(data_2[(0, 1)] == 2.1 && data_2[(1, 1)] == 3.2 && data_2[(2, 1)] == 4.1);
         assert_eq!(data_2.nrows(), 5);
         assert_eq!(data_2.ncols(), 2);
-        let result_2 = mlr_beta(&data_2);
+        let result_2 = mlr_beta(&data_2).unwrap();
         assert_eq!(result_2.len(), 2);
     }
 
@@ -155,7 +754,7 @@ mod tests {
         assert!(data_3[(0, 1)] == 2.1 && data_3[(1, 1)] == 3.2 && data_3[(2, 1)] == 4.1);
         assert_eq!(data_3.nrows(), 5);
         assert_eq!(data_3.ncols(), 3);
-        let result_3 = mlr_beta(&data_3);
+        let result_3 = mlr_beta(&data_3).unwrap();
         assert_eq!(result_3.len(), 3);
     }
 
@@ -166,7 +765,7 @@ mod tests {
         let v1_orig = v1.clone();
         let v2 = vec![1.0, 2.0, 3.0, 4.0, 5.0, 3.1, 4.3, 5.3, 6.5, 7.3];
         let v2 = DMatrix::from_vec(5, 2, v2);
-        adj_for_beta(&mut v1, &v2);
+        adj_for_beta(&mut v1, &v2).unwrap();
         assert_ne!(
             v1, v1_orig,
             "v1 should be different from v1_orig after adj_for_beta"
@@ -179,9 +778,216 @@ mod tests {
     }
 
     #[test]
-    #[should_panic(expected = "values1 must not be empty")]
+    fn test_adj_for_beta_single_covariate() {
+        // First columns are targets; second are covariates:
+        let x1 = [1.0, 2.0, 3.0, 4.0, 5.0];
+        let y1 = [2.0, 4.0, 5.0, 4.0, 5.0];
+        let x2 = [2.0, 4.0, 6.0, 8.0, 10.0];
+        let y2 = [1.0, 3.0, 2.0, 5.0, 4.0];
+        let mut v1 = DMatrix::from_vec(5, 2, [y1, x1].concat());
+        let v2 = DMatrix::from_vec(5, 2, [y2, x2].concat());
+
+        let (beta1, beta2) = adj_for_beta(&mut v1, &v2).unwrap();
+        // Hand-computed coefficients of simple regressions:
+        let tol = 1e-12;
+        assert!((beta1[0] - 2.2).abs() < tol && (beta1[1] - 0.6).abs() < tol);
+        assert!((beta2[0] - 0.6).abs() < tol && (beta2[1] - 0.4).abs() < tol);
+        assert!(
+            (mlr_beta(&DMatrix::from_vec(5, 2, [y1, [1.0; 5]].concat())).unwrap()[1]).abs() < tol,
+            "Slope against constant covariate should be zero"
+        );
+
+        // y1 - 0.6 * (x1 - 3) + 0.4 * (x2 - 6):
+        let expected = [1.6, 3.8, 5.0, 4.2, 5.4];
+        // Rescaled to mean and SD of y1:
+        let (mean1, sd1) = (4.0, 1.5f64.sqrt());
+        let mean_e = expected.iter().sum::<f64>() / 5.0;
+        let sd_e = (expected.iter().map(|e| (e - mean_e).powi(2)).sum::<f64>() / 4.0).sqrt();
+        for (i, e) in expected.iter().enumerate() {
+            let e = (e - mean_e) / sd_e * sd1 + mean1;
+            assert!((v1[(i, 0)] - e).abs() < 1e-10);
+        }
+        assert_eq!(v1.column(1).as_slice(), &x1);
+    }
+
+    #[test]
+    fn test_adjust_observation() {
+        // No covariates leaves values unchanged:
+        assert_eq!(adjust_observation(2.5, &[], &[], &[], &[]), 2.5);
+        // 4 - (0.5 * 2 - 1 * 3) + (0.25 * 4 + 2 * 1) = 4 + 2 + 3:
+        let adjusted =
+            adjust_observation(4.0, &[2.0, 3.0], &[4.0, 1.0], &[0.5, -1.0], &[0.25, 2.0]);
+        assert_eq!(adjusted, 9.0);
+        // Identical covariates and coefficients cancel:
+        assert_eq!(
+            adjust_observation(1.5, &[2.0, 3.0], &[2.0, 3.0], &[0.5, -1.0], &[0.5, -1.0]),
+            1.5
+        );
+    }
+
+    #[test]
+    fn test_adj_for_beta_robust() {
+        // Targets depend linearly on covariates, with small deterministic noise:
+        let n = 40;
+        let x: Vec<f64> = (0..n).map(|i| i as f64 / 4.0).collect();
+        let noise = |i: usize| ((i * 7) % 5) as f64 / 10.0 - 0.2;
+        let y1: Vec<f64> = x
+            .iter()
+            .enumerate()
+            .map(|(i, xi)| 2.0 * xi + noise(i))
+            .collect();
+        let y2: Vec<f64> = x
+            .iter()
+            .enumerate()
+            .map(|(i, xi)| 0.5 * xi + noise(i + 2))
+            .collect();
+        let v2 = DMatrix::from_vec(n, 2, [y2, x.clone()].concat());
+
+        // Inject a single outlier at the highest covariate value:
+        let mut y1_outlier = y1.clone();
+        y1_outlier[n - 1] -= 200.0;
+
+        let beta_clean =
+            mlr_beta_robust(&DMatrix::from_vec(n, 2, [y1.clone(), x.clone()].concat())).unwrap();
+        assert!((beta_clean[1] - 2.0).abs() < 0.1);
+
+        let adjust = |y: &[f64], robust: bool| {
+            let mut v1 = DMatrix::from_vec(n, 2, [y.to_vec(), x.clone()].concat());
+            let (beta1, _) = if robust {
+                adj_for_beta_robust(&mut v1, &v2).unwrap()
+            } else {
+                adj_for_beta(&mut v1, &v2).unwrap()
+            };
+            (beta1[1], v1)
+        };
+        let (slope_ols, _) = adjust(&y1_outlier, false);
+        let (slope_robust, _) = adjust(&y1_outlier, true);
+        assert!((slope_robust - 2.0).abs() < 0.1);
+        assert!((slope_ols - 2.0).abs() > 1.0);
+
+        // Ranks of adjusted values of all observations other than the outlier are compared with
+        // those from data without the outlier:
+        let rank_error = |robust: bool| {
+            let (_, clean) = adjust(&y1, robust);
+            let (_, outlier) = adjust(&y1_outlier, robust);
+            let ranks = |v: &DMatrix<f64>| {
+                let col: Vec<f64> = v.column(0).iter().take(n - 1).cloned().collect();
+                crate::calculate_dists::get_ordering_index(&col, false, false).index_reorder
+            };
+            ranks(&clean)
+                .iter()
+                .zip(ranks(&outlier).iter())
+                .map(|(a, b)| (*a as f64 - *b as f64).abs())
+                .sum::<f64>()
+        };
+        assert!(rank_error(true) < rank_error(false));
+    }
+
+    #[test]
+    fn test_mlr_beta_robust_multiple() {
+        let n = 30;
+        let x1: Vec<f64> = (0..n).map(|i| i as f64).collect();
+        let x2: Vec<f64> = (0..n).map(|i| ((i * 11) % 7) as f64).collect();
+        let mut y: Vec<f64> = (0..n).map(|i| 1.0 + 2.0 * x1[i] - 3.0 * x2[i]).collect();
+        y[5] += 100.0;
+        let data = DMatrix::from_vec(n, 3, [y, x1, x2].concat());
+
+        let ols = mlr_beta(&data).unwrap();
+        let robust = mlr_beta_robust(&data).unwrap();
+        let err = |b: &[f64]| (b[1] - 2.0).abs() + (b[2] + 3.0).abs();
+        assert!(err(&robust) < err(&ols));
+        assert!(err(&robust) < 0.05);
+    }
+
+    #[test]
+    fn test_mlr_beta_weighted() {
+        let n = 30;
+        let x1: Vec<f64> = (0..n).map(|i| i as f64).collect();
+        let x2: Vec<f64> = (0..n).map(|i| ((i * 11) % 7) as f64).collect();
+        let y: Vec<f64> = (0..n)
+            .map(|i| 1.0 + 2.0 * x1[i] - 3.0 * x2[i] + ((i * 7) % 5) as f64 / 10.0)
+            .collect();
+        let uniform = vec![2.5; n];
+        for data in [
+            DMatrix::from_vec(n, 2, [y.clone(), x1.clone()].concat()),
+            DMatrix::from_vec(n, 3, [y.clone(), x1.clone(), x2.clone()].concat()),
+        ] {
+            let ols = mlr_beta(&data).unwrap();
+            let wls = mlr_beta_weighted(&data, &uniform).unwrap();
+            for (a, b) in ols.iter().zip(wls.iter()) {
+                assert!((a - b).abs() < 1e-10);
+            }
+
+            // Zero weights are equivalent to dropping observations:
+            let mut weights = vec![1.0; n];
+            weights[..10].iter_mut().for_each(|w| *w = 0.0);
+            let wls = mlr_beta_weighted(&data, &weights).unwrap();
+            let dropped = mlr_beta(&data.rows(10, n - 10).clone_owned()).unwrap();
+            for (a, b) in dropped.iter().zip(wls.iter()) {
+                assert!((a - b).abs() < 1e-10);
+            }
+        }
+    }
+
+    #[test]
+    fn test_fit_apply_adjustment() {
+        let n = 30;
+        let values1 = DMatrix::from_fn(n, 3, |i, j| {
+            let x = i as f64;
+            match j {
+                0 => 1.0 + 0.5 * x + (x * 1.3).sin(),
+                1 => x + (x * 0.7).cos(),
+                _ => (x * 0.4).sin() * 3.0,
+            }
+        });
+        let values2 = values1.map(|x| 2.0 * x + 0.3 * x.sin());
+        let varnames: Vec<String> = ["transport", "natural", "social_index"]
+            .iter()
+            .map(|v| v.to_string())
+            .collect();
+
+        let model = fit_adjustment(&values1, &values2, &varnames).unwrap();
+        let mut applied = values1.clone();
+        apply_adjustment(&mut applied, &model).unwrap();
+        let mut adjusted = values1.clone();
+        let (beta1, beta2) = adj_for_beta(&mut adjusted, &values2).unwrap();
+        assert_eq!(applied, adjusted);
+        assert_eq!((model.beta1.clone(), model.beta2.clone()), (beta1, beta2));
+
+        let restored = AdjustmentModel::from_json(&model.to_json()).unwrap();
+        assert_eq!(restored, model);
+        assert!(matches!(
+            AdjustmentModel::from_json(r#"{"varnames": []}"#),
+            Err(UaError::Parse(_))
+        ));
+
+        let mut short = values1.rows(0, n - 1).clone_owned();
+        assert_eq!(
+            apply_adjustment(&mut short, &model),
+            Err(UaError::LengthMismatch {
+                name: "values".to_string(),
+                len: n - 1,
+                expected: n,
+            })
+        );
+    }
+
+    #[test]
     fn test_mlr_beta_empty_data() {
         let empty_data = DMatrix::<f64>::zeros(0, 0);
-        mlr_beta(&empty_data);
+        let err = Err(UaError::EmptyInput("data".to_string()));
+        assert_eq!(mlr_beta(&empty_data), err);
+        assert_eq!(mlr_beta_robust(&empty_data), err);
+
+        let values = DMatrix::from_vec(2, 2, vec![1.0, 2.0, 3.0, 4.0]);
+        let mut empty = DMatrix::<f64>::zeros(0, 2);
+        assert_eq!(
+            adj_for_beta(&mut empty, &values),
+            Err(UaError::EmptyInput("values1".to_string()))
+        );
+        assert_eq!(
+            adj_for_beta_robust(&mut values.clone(), &empty),
+            Err(UaError::EmptyInput("values2".to_string()))
+        );
     }
 }
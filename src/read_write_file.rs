@@ -1,31 +1,326 @@
-use nalgebra::DMatrix;
-use serde_json::Value;
+use crate::check_nobs;
+use crate::error::UaError;
+use crate::stats;
+use crate::utils;
+use crate::{MutationConfig, MutationDebug};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use nalgebra::{DMatrix, DVectorView};
+use serde_json::{Number, Value};
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::BufReader;
+use std::io::Read;
 use std::io::Write;
+use std::path::PathBuf;
 
 // Define columns to standardise on reading:
 const COLS_TO_STD: [&str; 1] = ["social_index"];
 
-/// Reads a JSON file and returns a tuple of two vectors: one for the indices and one for the
-/// values.
+/// Largest magnitude of values which may be read. Sums of squares of values up to this magnitude
+/// remain finite for any realistic number of observations, so that standardisation can not
+/// overflow to infinity.
+pub const MAX_ABS_VALUE: f64 = 1e150;
+
+/// Largest integer for which all smaller integers are exactly representable as `f64`.
+const MAX_EXACT_INTEGER: f64 = 9_007_199_254_740_992.0;
+
+/// A source of JSON data, from which all `readfile` functions may read.
+///
+/// Sources may be constructed directly, or converted from file paths, byte vectors, or buffered
+/// file readers.
+pub enum Source {
+    /// Path to a local file.
+    Path(PathBuf),
+    /// In-memory contents of a file.
+    Bytes(Vec<u8>),
+    /// Any reader, such as an open file or a decompressing reader.
+    Reader(Box<dyn Read>),
+}
+
+impl From<&str> for Source {
+    fn from(path: &str) -> Self {
+        Source::Path(PathBuf::from(path))
+    }
+}
+
+impl From<PathBuf> for Source {
+    fn from(path: PathBuf) -> Self {
+        Source::Path(path)
+    }
+}
+
+impl From<Vec<u8>> for Source {
+    fn from(bytes: Vec<u8>) -> Self {
+        Source::Bytes(bytes)
+    }
+}
+
+impl From<BufReader<File>> for Source {
+    fn from(reader: BufReader<File>) -> Self {
+        Source::Reader(Box::new(reader))
+    }
+}
+
+impl Source {
+    /// Read the full contents of the source as UTF-8 text.
+    fn read_to_string(self) -> Result<String, UaError> {
+        let read_err = |e: std::io::Error| {
+            if e.kind() == std::io::ErrorKind::InvalidData {
+                UaError::Parse("JSON input is not valid UTF-8".to_string())
+            } else {
+                UaError::Parse(format!("Unable to read JSON input: {}", e))
+            }
+        };
+        let mut contents = String::new();
+        match self {
+            Source::Path(path) => {
+                let file = File::open(&path)
+                    .map_err(|e| UaError::Io(format!("{}: {}", path.display(), e)))?;
+                BufReader::new(file)
+                    .read_to_string(&mut contents)
+                    .map_err(read_err)?;
+            }
+            Source::Bytes(bytes) => {
+                contents = String::from_utf8(bytes)
+                    .map_err(|_| UaError::Parse("JSON input is not valid UTF-8".to_string()))?;
+            }
+            Source::Reader(mut reader) => {
+                reader.read_to_string(&mut contents).map_err(read_err)?;
+            }
+        }
+        Ok(contents)
+    }
+}
+
+/// Data for one city, holding values of named variables, along with the groups and optional
+/// identifiers of each observation.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CityMatrix {
+    /// Matrix of [observations, variables].
+    pub values: DMatrix<f64>,
+    /// Names of the variables in each column of `values`.
+    pub varnames: Vec<String>,
+    /// Group number of each observation (row) of `values`.
+    pub groups: Vec<usize>,
+    /// Identifier of each observation (row) of `values`, or empty if identifiers were not read.
+    pub ids: Vec<i64>,
+}
+
+impl CityMatrix {
+    /// Values of a named variable, or `None` if that variable is not present.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::fs::File;
+    /// use std::io::BufReader;
+    /// use uamutations::read_write_file::readfile;
+    /// let reader = BufReader::new(File::open("./test_resources/dat1.json").unwrap());
+    /// let varnames = vec!["transport".to_string(), "bike_index".to_string()];
+    /// let city = readfile(reader, &varnames, 10).unwrap();
+    /// assert_eq!(city.column_for("bike_index"), Some(city.values.column(1)));
+    /// assert!(city.column_for("natural").is_none());
+    /// ```
+    pub fn column_for(&self, name: &str) -> Option<DVectorView<'_, f64>> {
+        self.varnames
+            .iter()
+            .position(|v| v == name)
+            .map(|i| self.values.column(i))
+    }
+
+    /// Number of observations. This is the number of records actually read, which may be less
+    /// than the number requested from `readfile` if the input has fewer records.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use uamutations::read_write_file::readfile;
+    /// let varnames = vec!["transport".to_string()];
+    /// let city = readfile("./test_resources/dat1.json", &varnames, 10000).unwrap();
+    /// assert_eq!(city.nrows(), 1000);
+    /// ```
+    pub fn nrows(&self) -> usize {
+        self.values.nrows()
+    }
+
+    /// Replace group numbers with consecutive numbers from 1, in increasing order of the original
+    /// numbers. Group 0, the entries of which are discarded in aggregation, is left unchanged.
+    ///
+    /// This allows sparse group numbers, such as census identifiers, to be aggregated without
+    /// allocating one row for every number up to the largest.
+    ///
+    /// # Returns
+    ///
+    /// The original number of each new group, so that new group `g` had original number
+    /// `labels[g - 1]`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use uamutations::read_write_file::readfile;
+    /// let json = br#"[
+    ///     {"transport": 1.0, "index": 9007199254740993},
+    ///     {"transport": 2.0, "index": 0},
+    ///     {"transport": 3.0, "index": 5}
+    /// ]"#;
+    /// let mut city = readfile(json.to_vec(), &["transport".to_string()], 10).unwrap();
+    /// let labels = city.densify_groups();
+    /// assert_eq!(city.groups, vec![2, 0, 1]);
+    /// assert_eq!(labels, vec![5, 9007199254740993]);
+    /// ```
+    pub fn densify_groups(&mut self) -> Vec<usize> {
+        let mut labels: Vec<usize> = self.groups.iter().cloned().filter(|&g| g != 0).collect();
+        labels.sort_unstable();
+        labels.dedup();
+        let dense: HashMap<usize, usize> = labels
+            .iter()
+            .enumerate()
+            .map(|(i, &g)| (g, i + 1))
+            .collect();
+        for g in self.groups.iter_mut().filter(|g| **g != 0) {
+            *g = dense[g];
+        }
+        labels
+    }
+}
+
+/// An input of city data in a particular format, from which the mutation algorithm reads values.
+///
+/// Implementors may be passed to `uamutate`, so that inputs in formats other than
+/// JSON can be supported without changes to the algorithm. Use `reader_for_path` to select a reader
+/// from a file extension.
+pub trait CityReader {
+    /// Read values of `varnames` from up to `nentries` records, as for `readfile`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `UaError::EmptyInput` if the input contains no records, or `UaError::Parse` if it
+    /// can not be read.
+    fn read(&self, varnames: &[String], nentries: usize) -> Result<CityMatrix, UaError>;
+
+    /// Names of all numeric variables present in the input, as for `read_varnames`.
+    ///
+    /// # Errors
+    ///
+    /// Returns errors under the same conditions as `read`.
+    fn varnames(&self) -> Result<Vec<String>, UaError>;
+}
+
+/// A `CityReader` for JSON arrays of records, as read by `readfile`, from a file or from bytes
+/// held in memory. Inputs may be read any number of times.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum JsonReader {
+    /// Path to a local JSON file.
+    Path(PathBuf),
+    /// Contents of a JSON file.
+    Bytes(Vec<u8>),
+}
+
+impl JsonReader {
+    /// Read the full contents of `reader` into a `JsonReader::Bytes`, so that they may be read
+    /// more than once.
+    ///
+    /// # Errors
+    ///
+    /// Returns `UaError::Io` if `reader` can not be read.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::fs::File;
+    /// use std::io::BufReader;
+    /// use uamutations::read_write_file::{CityReader, JsonReader};
+    /// let file = BufReader::new(File::open("./test_resources/dat1.json").unwrap());
+    /// let reader = JsonReader::from_reader(file).unwrap();
+    /// assert!(reader.varnames().unwrap().contains(&"bike_index".to_string()));
+    /// ```
+    pub fn from_reader(mut reader: impl Read) -> Result<Self, UaError> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        Ok(JsonReader::Bytes(bytes))
+    }
+
+    fn source(&self) -> Source {
+        match self {
+            JsonReader::Path(path) => Source::Path(path.clone()),
+            JsonReader::Bytes(bytes) => Source::Bytes(bytes.clone()),
+        }
+    }
+}
+
+impl CityReader for JsonReader {
+    fn read(&self, varnames: &[String], nentries: usize) -> Result<CityMatrix, UaError> {
+        readfile(self.source(), varnames, nentries)
+    }
+
+    fn varnames(&self) -> Result<Vec<String>, UaError> {
+        Ok(read_varnames(self.source())?.0)
+    }
+}
+
+/// Select a `CityReader` for a local file according to the extension of `path`.
+///
+/// # Errors
+///
+/// Returns `UaError::Parse` if the extension of `path` is not that of a supported format. The only
+/// format currently supported is JSON, with an extension of ".json".
+///
+/// # Example
+///
+/// ```
+/// use uamutations::read_write_file::{reader_for_path, CityReader};
+/// let reader = reader_for_path("./test_resources/dat1.json").unwrap();
+/// let varnames = vec!["transport".to_string()];
+/// assert_eq!(reader.read(&varnames, 10).unwrap().nrows(), 10);
+/// assert!(reader_for_path("./test_resources/dat1.csv").is_err());
+/// ```
+pub fn reader_for_path(path: impl Into<PathBuf>) -> Result<Box<dyn CityReader>, UaError> {
+    let path = path.into();
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("json") => Ok(Box::new(JsonReader::Path(path))),
+        _ => Err(UaError::Parse(format!(
+            "Unsupported input format: {}",
+            path.display()
+        ))),
+    }
+}
+
+/// Reads a JSON file and returns a `CityMatrix` holding values of the requested variables, and the
+/// group of each observation.
 ///
 /// # Arguments
 ///
-/// * `filename` - The path to the JSON file to be read.
+/// * `source` - The JSON file to be read, as a `Source` or anything convertible to one, such as a
+///   path or a `BufReader`.
 /// * `varnames` - The names of the variables to be read from the JSON file.
 /// * `nentries` - The number of entries to be read from the JSON file.
 ///
 /// # Panics
 ///
-/// This function will panic if `nentries` is less than or equal to zero, or if the file cannot be
-/// read.
+/// This function will panic if `nentries` is zero.
+///
+/// # Errors
+///
+/// Returns `UaError::Io` if a file given as a path can not be opened, and `UaError::Parse` if the
+/// file can not be read, is not valid UTF-8, or can not be parsed as a JSON array. A leading UTF-8 byte-order mark, as commonly written by Windows tools, is
+/// removed prior to parsing. Returns `UaError::EmptyInput` if the array contains no records, and
+/// `UaError::MissingVariable` if any of `varnames` has no numeric value in any record. Also
+/// returns `UaError::Parse` if any value has a magnitude greater than `MAX_ABS_VALUE`, or if any
+/// record which is read is not a JSON object, or has no "index" value, or a group index which is
+/// not a non-negative integer.
 ///
 /// # Returns
 ///
-/// A tuple of two vectors:
-/// * The first vector contains the indices of the sorted values.
-/// * The second vector contains the sorted values.
+/// A `CityMatrix` with one column of values for each of `varnames`, and no identifiers. Values
+/// which are `null` in the JSON file, or which are absent from a record, are returned as `NaN`, so
+/// that each row holds the values of one record. Subnormal values and negative zero are returned
+/// as zero. Inputs with fewer than `nentries` records are read in full, and
+/// `CityMatrix::nrows` gives the number of records actually read.
 ///
 /// # Example
 ///
@@ -38,119 +333,1481 @@ const COLS_TO_STD: [&str; 1] = ["social_index"];
 /// let reader = BufReader::new(file);
 /// let varnames = vec!["transport".to_string()];
 /// let nentries = 10;
-/// let (values, groups) = readfile(reader, &varnames, nentries);
+/// let city = readfile(reader, &varnames, nentries).unwrap();
+/// assert_eq!(city.values.nrows(), city.groups.len());
+///
+/// // Paths and in-memory bytes may also be read directly:
+/// let city_path = readfile(filename, &varnames, nentries).unwrap();
+/// assert_eq!(city_path, city);
+/// let bytes = br#"[{"transport": 1.5, "index": 1}]"#.to_vec();
+/// let city_bytes = readfile(bytes, &varnames, nentries).unwrap();
+/// assert_eq!(city_bytes.values[(0, 0)], 1.5);
 /// ```
-
 pub fn readfile(
-    reader: BufReader<File>,
+    source: impl Into<Source>,
     varnames: &[String],
     nentries: usize,
-) -> (DMatrix<f64>, Vec<usize>) {
+) -> Result<CityMatrix, UaError> {
+    readfile_with_ids(source, varnames, nentries, None, None)
+}
+
+/// Reads a JSON file as for `readfile`, converting values equal to per-variable sentinels to
+/// `NaN`.
+///
+/// Some data encode missing values as numbers such as `-999` rather than as `null`. Sentinels are
+/// converted at the time of reading, and so before any log-transformation or standardisation,
+/// after which the resultant `NaN` values are handled by `MutationConfig::nan_policy`.
+///
+/// # Arguments
+///
+/// * `source` - The JSON file to be read, as for `readfile`.
+/// * `varnames` - The names of the variables to be read from the JSON file.
+/// * `nentries` - The number of entries to be read from the JSON file.
+/// * `sentinels` - Map of variable names to the value which represents missing values of that
+///   variable. Variables not in `varnames` are ignored.
+///
+/// # Panics
+///
+/// This function will panic under the same conditions as `readfile`.
+///
+/// # Errors
+///
+/// Returns errors under the same conditions as `readfile`.
+///
+/// # Example
+///
+/// ```
+/// use std::collections::HashMap;
+/// use uamutations::read_write_file::readfile_with_sentinels;
+/// let varnames = vec!["transport".to_string(), "bike_index".to_string()];
+/// let sentinels = HashMap::from([("transport".to_string(), -999.0)]);
+/// let city =
+///     readfile_with_sentinels("./test_resources/dat_sentinel.json", &varnames, 10, &sentinels)
+///         .unwrap();
+/// assert!(city.values[(1, 0)].is_nan());
+/// ```
+pub fn readfile_with_sentinels(
+    source: impl Into<Source>,
+    varnames: &[String],
+    nentries: usize,
+    sentinels: &HashMap<String, f64>,
+) -> Result<CityMatrix, UaError> {
+    let mut city = readfile(source, varnames, nentries)?;
+    replace_sentinels(&mut city, sentinels);
+    Ok(city)
+}
+
+/// Replace all values of `city` which equal the sentinel of their variable with `NaN`.
+pub(crate) fn replace_sentinels(city: &mut CityMatrix, sentinels: &HashMap<String, f64>) {
+    for (j, varname) in city.varnames.iter().enumerate() {
+        if let Some(&sentinel) = sentinels.get(varname) {
+            city.values
+                .column_mut(j)
+                .iter_mut()
+                .filter(|x| **x == sentinel)
+                .for_each(|x| *x = f64::NAN);
+        }
+    }
+}
+
+/// Reads all records from a JSON file, and returns a `CityMatrix` as for `readfile`.
+///
+/// # Arguments
+///
+/// * `source` - The JSON file to be read, as for `readfile`.
+/// * `varnames` - The names of the variables to be read from the JSON file.
+///
+/// # Errors
+///
+/// Returns errors under the same conditions as `readfile`.
+///
+/// # Example
+///
+/// ```
+/// use std::fs::File;
+/// use std::io::BufReader;
+/// use uamutations::read_write_file::readfile_all;
+/// let reader = BufReader::new(File::open("./test_resources/dat1.json").unwrap());
+/// let varnames = vec!["transport".to_string()];
+/// let city = readfile_all(reader, &varnames).unwrap();
+/// assert_eq!(city.values.nrows(), 1000);
+/// ```
+pub fn readfile_all(source: impl Into<Source>, varnames: &[String]) -> Result<CityMatrix, UaError> {
+    readfile_with_ids(source, varnames, usize::MAX, None, None)
+}
+
+/// Reads a JSON file as for `readfile`, and also returns identifiers of each observation.
+///
+/// Observation identifiers allow distances for each row of the returned values to be joined back
+/// on to individual records, rather than only on to groups. Records may also be read from an
+/// array nested within a top-level JSON object, such as `{"data": [ ... ]}`.
+///
+/// # Arguments
+///
+/// * `source` - The JSON file to be read, as for `readfile`.
+/// * `varnames` - The names of the variables to be read from the JSON file.
+/// * `nentries` - The number of entries to be read from the JSON file.
+/// * `id_col` - Optional name of an integer-valued column identifying each observation.
+/// * `records_path` - Optional dotted path to the array of records within a top-level JSON
+///   object, such as "data" or "results.data". If `None`, the top level must be an array.
+///
+/// # Panics
+///
+/// This function will panic under any of the conditions described for `readfile`.
+///
+/// # Errors
+///
+/// Returns errors under the same conditions as `readfile`, along with `UaError::Parse` if
+/// `records_path` does not resolve to an array, or if `id_col` is specified and any record which
+/// is read has no identifier, or an identifier which is not an integer.
+/// Identifiers may be written either as integers or as integral floating point values such as
+/// `3.0`, but only integers are read exactly above 2^53.
+///
+/// # Returns
+///
+/// A `CityMatrix` as for `readfile`, with identifiers for each observation. These are empty if
+/// `id_col` is `None`.
+///
+/// # Example
+///
+/// ```
+/// use std::fs::File;
+/// use std::io::BufReader;
+/// use uamutations::read_write_file::readfile_with_ids;
+/// let reader = BufReader::new(File::open("./test_resources/dat_ids.json").unwrap());
+/// let varnames = vec!["transport".to_string()];
+/// let city = readfile_with_ids(reader, &varnames, 10, Some("cell_id"), None).unwrap();
+/// assert_eq!(city.ids.len(), city.values.nrows());
+/// ```
+pub fn readfile_with_ids(
+    source: impl Into<Source>,
+    varnames: &[String],
+    nentries: usize,
+    id_col: Option<&str>,
+    records_path: Option<&str>,
+) -> Result<CityMatrix, UaError> {
+    assert!(nentries > 0, "nentries must be greater than zero");
+
+    let array = read_json_array(source.into(), records_path)?;
+    records_to_city(&array, varnames, nentries, id_col, None)
+}
+
+/// Reads a JSON file as for `readfile`, and calculates the mean and standard deviation of each
+/// variable while values are read.
+///
+/// Statistics are accumulated with `stats::MeanVar` as each value is read, so that the values do
+/// not need to be traversed a second time, as they are by `Scaling::from_values`. Missing values
+/// are excluded from the statistics. The returned `Scaling` may be passed to
+/// `apply_fixed_scaling`, or used as `MutationConfig::fixed_scaling`.
+///
+/// # Arguments
+///
+/// * `source` - The JSON file to be read, as for `readfile`.
+/// * `varnames` - The names of the variables to be read from the JSON file.
+/// * `nentries` - The number of entries to be read from the JSON file.
+///
+/// # Panics
+///
+/// This function will panic if `nentries` is zero.
+///
+/// # Errors
+///
+/// Returns errors under the same conditions as `readfile`, along with
+/// `UaError::InsufficientData` if fewer than `MIN_NOBS` records are read.
+///
+/// # Example
+///
+/// ```
+/// use uamutations::read_write_file::{readfile_with_scaling, Scaling};
+/// let varnames = vec!["transport".to_string()];
+/// let (city, scaling) =
+///     readfile_with_scaling("./test_resources/dat1.json", &varnames, 100).unwrap();
+/// let batch = Scaling::from_values(&city.values).unwrap();
+/// assert!((scaling.means[0] - batch.means[0]).abs() < 1e-10);
+/// assert!((scaling.sds[0] - batch.sds[0]).abs() < 1e-10);
+/// ```
+pub fn readfile_with_scaling(
+    source: impl Into<Source>,
+    varnames: &[String],
+    nentries: usize,
+) -> Result<(CityMatrix, Scaling), UaError> {
     assert!(nentries > 0, "nentries must be greater than zero");
 
-    let json: Value = serde_json::from_reader(reader).unwrap();
-    let actual_nentries = json.as_array().unwrap().len().min(nentries);
+    let array = read_json_array(source.into(), None)?;
+    let mut accumulators = vec![stats::MeanVar::new(); varnames.len()];
+    let city = records_to_city(&array, varnames, nentries, None, Some(&mut accumulators))?;
+    check_nobs("JSON input", city.nrows())?;
+    let (means, sds) = accumulators
+        .iter()
+        .map(|acc| (acc.mean(), acc.sd()))
+        .unzip();
+    Ok((city, Scaling { means, sds }))
+}
+
+/// Reads a JSON file as for `readfile`, skipping any malformed records rather than failing.
+///
+/// Records are malformed if they can not be parsed as JSON objects, or if they do not have numeric
+/// values for the "index" column, and either numeric or null values for all of `varnames`. Null
+/// values are read as `NaN`, as for `readfile`. A record which is missing its closing brace ends
+/// at the start of the next record, so that only that one record is skipped. Valid records are
+/// read up to `nentries`.
+///
+/// # Arguments
+///
+/// * `source` - The JSON file to be read, as for `readfile`.
+/// * `varnames` - The names of the variables to be read from the JSON file.
+/// * `nentries` - The number of valid entries to be read from the JSON file.
+///
+/// # Panics
+///
+/// This function will panic if `nentries` is zero.
+///
+/// # Errors
+///
+/// Returns `UaError::Parse` if the file is not valid UTF-8, or is not a JSON array, and
+/// `UaError::EmptyInput` if the array contains no valid records.
+///
+/// # Returns
+///
+/// A `CityMatrix` as for `readfile`, along with the number of malformed records which were
+/// skipped.
+///
+/// # Example
+///
+/// ```
+/// use std::fs::File;
+/// use std::io::BufReader;
+/// use uamutations::read_write_file::readfile_skip_malformed;
+/// let reader = BufReader::new(File::open("./test_resources/dat_malformed.json").unwrap());
+/// let varnames = vec!["transport".to_string()];
+/// let (city, nskipped) = readfile_skip_malformed(reader, &varnames, 10).unwrap();
+/// assert_eq!(city.values.nrows(), 4);
+/// assert_eq!(nskipped, 2);
+/// ```
+pub fn readfile_skip_malformed(
+    source: impl Into<Source>,
+    varnames: &[String],
+    nentries: usize,
+) -> Result<(CityMatrix, usize), UaError> {
+    assert!(nentries > 0, "nentries must be greater than zero");
+
+    let contents = read_json_text(source.into())?;
+    let mut records: Vec<Value> = Vec::new();
+    let mut nskipped = 0;
+    for record in split_json_array(&contents)? {
+        let is_valid = |map: &serde_json::Map<String, Value>| {
+            map.get("index").is_some_and(Value::is_number)
+                && varnames.iter().all(|v| {
+                    map.get(v.as_str())
+                        .is_some_and(|x| x.is_number() || x.is_null())
+                })
+        };
+        match serde_json::from_str::<Value>(record) {
+            Ok(Value::Object(map)) if is_valid(&map) => records.push(Value::Object(map)),
+            _ => nskipped += 1,
+        }
+    }
+    if records.is_empty() {
+        return Err(UaError::EmptyInput("JSON input".to_string()));
+    }
+
+    Ok((
+        records_to_city(&records, varnames, nentries, None, None)?,
+        nskipped,
+    ))
+}
+
+/// Reads values of `varnames`, along with groups and optional identifiers, from up to `nentries`
+/// JSON records. If `accumulators` are given, each non-missing value read is also added to the
+/// accumulator for its variable.
+fn records_to_city(
+    array: &[Value],
+    varnames: &[String],
+    nentries: usize,
+    id_col: Option<&str>,
+    mut accumulators: Option<&mut Vec<stats::MeanVar>>,
+) -> Result<CityMatrix, UaError> {
+    let nrecords = array.len();
+    let actual_nentries = nrecords.min(nentries);
 
     let mut values = DMatrix::<f64>::zeros(actual_nentries, varnames.len());
     let mut city_group = Vec::new();
     let city_group_col = "index";
+    let mut ids: Vec<i64> = Vec::new();
 
     let mut var_exists = vec![false; varnames.len()];
-    let mut current_positions = vec![0; varnames.len()];
 
     let mut std_index: Vec<usize> = vec![];
-    if let Value::Array(array) = &json {
-        for item in array {
-            if let Value::Object(map) = item {
-                for (i, var) in varnames.iter().enumerate() {
-                    if let Some(Value::Number(number)) = map.get(var.as_str()) {
-                        var_exists[i] = true;
-                        if current_positions[i] == 0 && COLS_TO_STD.contains(&var.as_str()) {
-                            std_index.push(i);
-                        }
-                        if let Some(number) = number.as_f64() {
-                            if current_positions[i] < actual_nentries {
-                                values[(current_positions[i], i)] = number;
-                                current_positions[i] += 1;
-                            }
-                        }
+    for (row, item) in array.iter().enumerate() {
+        let in_range = row < actual_nentries;
+        let map = match item {
+            Value::Object(map) => map,
+            _ if in_range => {
+                return Err(UaError::Parse(format!(
+                    "Record {} is not a JSON object",
+                    row
+                )));
+            }
+            _ => continue,
+        };
+        for (i, var) in varnames.iter().enumerate() {
+            match map.get(var.as_str()) {
+                Some(Value::Number(number)) => {
+                    if !var_exists[i] && COLS_TO_STD.contains(&var.as_str()) {
+                        std_index.push(i);
                     }
-                }
-                if let Some(Value::Number(number)) = map.get(city_group_col) {
-                    if let Some(number) = number.as_f64() {
-                        if city_group.len() < actual_nentries {
-                            city_group.push(number as usize);
+                    var_exists[i] = true;
+                    if in_range {
+                        let value = number_to_value(number, var)?;
+                        values[(row, i)] = value;
+                        if let Some(acc) = accumulators.as_deref_mut() {
+                            acc[i].push(value);
                         }
                     }
                 }
+                // Missing values, whether null or absent, are read as NaN:
+                _ if in_range => values[(row, i)] = f64::NAN,
+                _ => {}
+            }
+        }
+        if !in_range {
+            continue;
+        }
+        match map.get(city_group_col) {
+            Some(Value::Number(number)) => city_group.push(number_to_group(number)?),
+            _ => {
+                return Err(UaError::Parse(format!(
+                    "Record {} has no numeric '{}' value",
+                    row, city_group_col
+                )));
+            }
+        }
+        if let Some(col) = id_col {
+            match map.get(col) {
+                Some(Value::Number(number)) => ids.push(number_to_id(number)?),
+                _ => {
+                    return Err(UaError::Parse(format!(
+                        "Record {} has no numeric '{}' value",
+                        row, col
+                    )));
+                }
             }
         }
     }
 
-    for (i, exists) in var_exists.iter().enumerate() {
-        assert!(
-            *exists,
-            "Variable {} does not exist in the JSON file",
-            varnames[i]
+    if let Some(i) = var_exists.iter().position(|exists| !exists) {
+        return Err(UaError::MissingVariable {
+            name: "JSON input".to_string(),
+            varname: varnames[i].clone(),
+        });
+    }
+
+    Ok(CityMatrix {
+        values,
+        varnames: varnames.to_vec(),
+        groups: city_group,
+        ids,
+    })
+}
+
+/// Converts a JSON number to a value of variable `var`. Subnormal values and negative zero are
+/// read as zero, so that neither can produce infinite values when used as divisors.
+fn number_to_value(number: &Number, var: &str) -> Result<f64, UaError> {
+    let x = number.as_f64().unwrap_or(f64::NAN);
+    if x.abs() > MAX_ABS_VALUE {
+        return Err(UaError::Parse(format!(
+            "Value {} of variable '{}' exceeds the maximum magnitude of {:e}",
+            number, var, MAX_ABS_VALUE
+        )));
+    }
+    Ok(if x == 0.0 || x.is_subnormal() { 0.0 } else { x })
+}
+
+/// Converts a JSON number to a group index, which must be a non-negative integer. Integers are
+/// read directly to avoid loss of precision above 2^53, while floating point values must be
+/// integral and no greater than 2^53.
+fn number_to_group(number: &Number) -> Result<usize, UaError> {
+    number
+        .as_u64()
+        .and_then(|x| usize::try_from(x).ok())
+        .or_else(|| {
+            number
+                .as_f64()
+                .filter(|x| x.fract() == 0.0 && (0.0..=MAX_EXACT_INTEGER).contains(x))
+                .map(|x| x as usize)
+        })
+        .ok_or_else(|| {
+            UaError::Parse(format!(
+                "Group index {} is not a non-negative integer",
+                number
+            ))
+        })
+}
+
+/// Converts a JSON number to an identifier, which must be an integer. As for groups, integers are
+/// read directly, while floating point values must be integral and no greater than 2^53 in
+/// magnitude.
+fn number_to_id(number: &Number) -> Result<i64, UaError> {
+    number
+        .as_i64()
+        .or_else(|| {
+            number
+                .as_f64()
+                .filter(|x| x.fract() == 0.0 && x.abs() <= MAX_EXACT_INTEGER)
+                .map(|x| x as i64)
+        })
+        .ok_or_else(|| UaError::Parse(format!("Identifier {} is not an integer", number)))
+}
+
+/// Reads the full text of a JSON file, removing any leading UTF-8 byte-order mark.
+fn read_json_text(source: Source) -> Result<String, UaError> {
+    let contents = source.read_to_string()?;
+    match contents.strip_prefix('\u{feff}') {
+        Some(stripped) => Ok(stripped.to_string()),
+        None => Ok(contents),
+    }
+}
+
+/// Splits the text of a JSON array into the unparsed text of each element, without requiring
+/// that any elements be valid JSON.
+///
+/// Within an object, an opening brace may only follow a colon, so an opening brace which follows
+/// a comma within an object is taken to start a new element, and the preceding element to be
+/// missing its closing brace.
+fn split_json_array(contents: &str) -> Result<Vec<&str>, UaError> {
+    let inner = contents
+        .trim()
+        .strip_prefix('[')
+        .and_then(|s| s.strip_suffix(']'))
+        .ok_or_else(|| UaError::Parse("JSON input is not an array".to_string()))?;
+
+    let mut elements = Vec::new();
+    let (mut in_string, mut escaped) = (false, false);
+    // Opening brackets of all containers enclosing the current position, and the previous
+    // character other than whitespace:
+    let mut open: Vec<char> = Vec::new();
+    let mut prev = None;
+    let (mut start, mut last_comma) = (0, 0);
+    for (i, c) in inner.char_indices() {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match c {
+            '"' => in_string = true,
+            '{' if open.last() == Some(&'{') && prev == Some(',') => {
+                elements.push(inner[start..last_comma].trim());
+                start = last_comma + 1;
+                open = vec!['{'];
+            }
+            '{' | '[' => open.push(c),
+            '}' | ']' => {
+                open.pop();
+            }
+            ',' if open.is_empty() => {
+                elements.push(inner[start..i].trim());
+                start = i + 1;
+            }
+            ',' => last_comma = i,
+            _ => {}
+        }
+        if !c.is_whitespace() {
+            prev = Some(c);
+        }
+    }
+    elements.push(inner[start..].trim());
+    elements.retain(|e| !e.is_empty());
+
+    Ok(elements)
+}
+
+/// Reads the contents of a JSON file, which must be a non-empty array of records, either at the top
+/// level or at the dotted `records_path` within a top-level object.
+fn read_json_array(source: Source, records_path: Option<&str>) -> Result<Vec<Value>, UaError> {
+    let contents = read_json_text(source)?;
+    let mut json: Value = serde_json::from_str(&contents)
+        .map_err(|e| UaError::Parse(format!("Unable to parse JSON input: {}", e)))?;
+    let array = match records_path {
+        None => match json {
+            Value::Array(array) => array,
+            _ => return Err(UaError::Parse("JSON input is not an array".to_string())),
+        },
+        Some(path) => {
+            let pointer = format!("/{}", path.replace('.', "/"));
+            match json.pointer_mut(&pointer).map(Value::take) {
+                Some(Value::Array(array)) => array,
+                _ => {
+                    return Err(UaError::Parse(format!(
+                        "JSON path '{}' does not resolve to an array",
+                        path
+                    )))
+                }
+            }
+        }
+    };
+    if array.is_empty() {
+        return Err(UaError::EmptyInput("JSON input".to_string()));
+    }
+    Ok(array)
+}
+
+/// Reads a JSON file and returns the names of all numeric variables in the first record, along
+/// with the total number of records, without reading any values into a matrix.
+///
+/// # Arguments
+///
+/// * `source` - The JSON file to be read, as for `readfile`.
+///
+/// # Errors
+///
+/// Returns errors under the same conditions as `readfile`.
+///
+/// # Example
+///
+/// ```
+/// use std::fs::File;
+/// use std::io::BufReader;
+/// use uamutations::read_write_file::read_varnames;
+/// let reader = BufReader::new(File::open("./test_resources/dat1.json").unwrap());
+/// let (varnames, nrecords) = read_varnames(reader).unwrap();
+/// assert!(varnames.contains(&"bike_index".to_string()));
+/// assert_eq!(nrecords, 1000);
+/// ```
+pub fn read_varnames(source: impl Into<Source>) -> Result<(Vec<String>, usize), UaError> {
+    let array = read_json_array(source.into(), None)?;
+    let varnames = match &array[0] {
+        Value::Object(map) => map
+            .iter()
+            .filter(|(_, v)| v.is_number())
+            .map(|(k, _)| k.to_string())
+            .collect(),
+        _ => vec![],
+    };
+    Ok((varnames, array.len()))
+}
+
+/// Summary of the records of a JSON file, as returned from `scan_records`.
+pub(crate) struct RecordScan {
+    /// Names of all numeric variables in the first record.
+    pub varnames: Vec<String>,
+    /// Total number of records.
+    pub nrecords: usize,
+    /// Number of the first `nentries` records with missing values of any requested variable.
+    pub nmissing: usize,
+}
+
+/// Reads a JSON file in full, and checks the values of `varnames` in each of the first `nentries`
+/// records, which are those read by `readfile`. Values which are null or absent are counted as
+/// missing.
+///
+/// # Errors
+///
+/// Returns `UaError::MissingVariable` for the first of `varnames` which has no numeric values in
+/// any of those records, or otherwise errors under the same conditions as `read_varnames`.
+pub(crate) fn scan_records(
+    source: impl Into<Source>,
+    varnames: &[String],
+    nentries: usize,
+) -> Result<RecordScan, UaError> {
+    let array = read_json_array(source.into(), None)?;
+    let mut present = vec![false; varnames.len()];
+    let mut nmissing = 0;
+    for record in array.iter().take(nentries) {
+        let mut has_missing = false;
+        for (i, var) in varnames.iter().enumerate() {
+            match record.get(var.as_str()) {
+                Some(Value::Number(_)) => present[i] = true,
+                _ => has_missing = true,
+            }
+        }
+        nmissing += has_missing as usize;
+    }
+    if let Some(i) = present.iter().position(|p| !p) {
+        return Err(UaError::MissingVariable {
+            name: "JSON input".to_string(),
+            varname: varnames[i].clone(),
+        });
+    }
+    let varnames = match &array[0] {
+        Value::Object(map) => map
+            .iter()
+            .filter(|(_, v)| v.is_number())
+            .map(|(k, _)| k.to_string())
+            .collect(),
+        _ => vec![],
+    };
+
+    Ok(RecordScan {
+        varnames,
+        nrecords: array.len(),
+        nmissing,
+    })
+}
+
+/// Reads several JSON files as if they were a single file, and returns a `CityMatrix` as for
+/// `readfile`.
+///
+/// Records are read from each file in turn until `nentries` have been collected. Group indices are
+/// taken directly from each file, so files split from one original file retain the groups of that
+/// original.
+///
+/// # Arguments
+///
+/// * `sources` - Sources for each of the JSON files, in the order in which they are to be read.
+/// * `varnames` - The names of the variables to be read from the JSON files.
+/// * `nentries` - The total number of entries to be read from all JSON files.
+///
+/// # Panics
+///
+/// This function will panic under any of the conditions described for `readfile`.
+///
+/// # Errors
+///
+/// Returns any error returned from `readfile` for any of the files.
+///
+/// # Example
+///
+/// ```
+/// use std::fs::File;
+/// use std::io::BufReader;
+/// use uamutations::read_write_file::readfile_many;
+/// let readers = vec![
+///     BufReader::new(File::open("./test_resources/dat1.json").unwrap()),
+///     BufReader::new(File::open("./test_resources/dat2.json").unwrap()),
+/// ];
+/// let varnames = vec!["transport".to_string()];
+/// let city = readfile_many(readers, &varnames, 1500).unwrap();
+/// assert_eq!(city.values.nrows(), 1500);
+/// ```
+pub fn readfile_many<S: Into<Source>>(
+    sources: Vec<S>,
+    varnames: &[String],
+    nentries: usize,
+) -> Result<CityMatrix, UaError> {
+    assert!(nentries > 0, "nentries must be greater than zero");
+
+    let mut values_all: Vec<DMatrix<f64>> = Vec::new();
+    let mut groups_all: Vec<usize> = Vec::new();
+    for source in sources {
+        let remaining = nentries - groups_all.len();
+        if remaining == 0 {
+            break;
+        }
+        let city = readfile(source, varnames, remaining)?;
+        values_all.push(city.values);
+        groups_all.extend(city.groups);
+    }
+
+    let mut values = DMatrix::<f64>::zeros(groups_all.len(), varnames.len());
+    let mut row = 0;
+    for v in &values_all {
+        values.rows_mut(row, v.nrows()).copy_from(v);
+        row += v.nrows();
+    }
+
+    Ok(CityMatrix {
+        values,
+        varnames: varnames.to_vec(),
+        groups: groups_all,
+        ids: vec![],
+    })
+}
+
+/// Merge the observations of several cities into a single `CityMatrix`, such as to match against
+/// a combined reference city.
+///
+/// Variables are aligned by name, so may be in different orders in each city, and are returned in
+/// the order of the first city. Groups of each city are offset by the largest group number of all
+/// preceding cities, so that groups of different cities remain distinct, except for entries in
+/// group 0, which remain in group 0 to be discarded in aggregation. Identifiers are retained
+/// only if all cities have identifiers.
+///
+/// # Arguments
+///
+/// * `cities` - The cities to be merged, all of which must have the same variables.
+///
+/// # Errors
+///
+/// Returns `UaError::EmptyInput` if `cities` is empty, or `UaError::MissingVariable` naming any
+/// city which lacks one of the variables of the first city, or has a variable which the first
+/// city does not.
+///
+/// # Example
+///
+/// ```
+/// use uamutations::read_write_file::{merge_cities, readfile};
+/// let varnames = vec!["transport".to_string()];
+/// let city1 = readfile("./test_resources/dat1.json", &varnames, 10).unwrap();
+/// let city2 = readfile("./test_resources/dat2.json", &varnames, 20).unwrap();
+/// let merged = merge_cities(&[city1, city2]).unwrap();
+/// assert_eq!(merged.nrows(), 30);
+/// ```
+pub fn merge_cities(cities: &[CityMatrix]) -> Result<CityMatrix, UaError> {
+    let first = cities
+        .first()
+        .ok_or_else(|| UaError::EmptyInput("cities".to_string()))?;
+    let varnames = &first.varnames;
+
+    // Column of each city holding each variable of the first city:
+    let mut columns: Vec<Vec<usize>> = Vec::with_capacity(cities.len());
+    for (i, city) in cities.iter().enumerate() {
+        if let Some(extra) = city.varnames.iter().find(|v| !varnames.contains(v)) {
+            return Err(UaError::MissingVariable {
+                name: format!("city {}", i),
+                varname: extra.clone(),
+            });
+        }
+        let cols = varnames
+            .iter()
+            .map(|v| {
+                city.varnames.iter().position(|cv| cv == v).ok_or_else(|| {
+                    UaError::MissingVariable {
+                        name: format!("city {}", i),
+                        varname: v.clone(),
+                    }
+                })
+            })
+            .collect::<Result<Vec<usize>, UaError>>()?;
+        columns.push(cols);
+    }
+
+    let nrows: usize = cities.iter().map(|c| c.nrows()).sum();
+    let mut values = DMatrix::<f64>::zeros(nrows, varnames.len());
+    let mut groups: Vec<usize> = Vec::with_capacity(nrows);
+    let keep_ids = cities.iter().all(|c| !c.ids.is_empty());
+    let mut ids: Vec<i64> = Vec::new();
+    let mut row = 0;
+    let mut group_offset = 0;
+    for (city, cols) in cities.iter().zip(&columns) {
+        for (j, &col) in cols.iter().enumerate() {
+            values
+                .view_mut((row, j), (city.nrows(), 1))
+                .copy_from(&city.values.column(col));
+        }
+        // Group 0 is discarded in aggregation, so remains 0 rather than being offset:
+        groups.extend(
+            city.groups
+                .iter()
+                .map(|&g| if g == 0 { 0 } else { g + group_offset }),
         );
+        if keep_ids {
+            ids.extend(&city.ids);
+        }
+        row += city.nrows();
+        group_offset += city.groups.iter().cloned().max().unwrap_or(0);
+    }
+
+    Ok(CityMatrix {
+        values,
+        varnames: varnames.clone(),
+        groups,
+        ids,
+    })
+}
+
+/// Standarise one column of an array to z-scores. Column in standardised in-place.
+///
+/// This is used for social variables, which need to be standardised in order to have comparable
+/// beta coefficients for the MLR routines.
+///
+/// # Arguments
+/// * `values` - The array to be standarised.
+/// * `i` - The index of the column to be standarised.
+///
+/// # Returns
+/// The standarised array.
+pub fn standardise_array(values: &mut DMatrix<f64>, i: usize) {
+    let sum_values: f64 = values.column(i).sum();
+
+    let sum_values_sq: f64 = values.column(i).iter().map(|&x| x.powi(2)).sum();
+
+    // Calculate standard deviations:
+    let nobs = values.nrows() as f64;
+    let mean_val: f64 = sum_values / nobs;
+    let std_dev: f64 =
+        ((sum_values_sq / nobs - (sum_values / nobs).powi(2)) * nobs / (nobs - 1.0)).sqrt();
+
+    // Transform values:
+    for val in &mut values.column_mut(i) {
+        *val = (*val - mean_val) / std_dev;
+    }
+}
+
+/// Method used to pool statistics when standardising two arrays together.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScalePooling {
+    /// Both arrays are standardised by the mean and standard deviation of their combined
+    /// observations, so differences in level between the two arrays are retained.
+    Pooled,
+    /// Each array is standardised by its own mean and standard deviation, so only differences in
+    /// the shapes of the two distributions are retained.
+    Separate,
+    /// Both arrays are standardised by the same mean and standard deviation, as for `Pooled`, but
+    /// with each array contributing equally to those statistics regardless of its number of
+    /// observations. The mean is the average of the means of each array, and the variance is that
+    /// of an equal mixture of both arrays. This prevents statistics from being dominated by the
+    /// larger of two arrays of very different sizes.
+    Balanced,
+}
+
+/// Standardise all columns of two arrays to z-scores. Arrays are standardised in-place.
+///
+/// With `ScalePooling::Pooled`, each column of both arrays is transformed with the same mean and
+/// standard deviation, calculated from all observations in both arrays. Mutations then reflect
+/// both differences in overall levels and differences in the shapes of the two distributions.
+///
+/// With `ScalePooling::Separate`, each array is transformed with its own statistics, so both end
+/// up with zero mean and unit variance in every column. This removes any difference in levels
+/// between the two cities, and mutations reflect differences in shape only. Note that
+/// `mlr::adj_for_beta` rescales the first column of `values1` back to its own mean and standard
+/// deviation, so separate standardisation also removes any level differences in the residual
+/// effects of extra variables.
+///
+/// # Arguments
+/// * `values1` - The first array to be standardised.
+/// * `values2` - The second array to be standardised, with the same number of columns as
+///   `values1`.
+/// * `pooling` - Whether statistics are pooled across both arrays, or calculated separately.
+///
+/// # Errors
+///
+/// Returns `UaError::InsufficientData` if either array has fewer than `MIN_NOBS` rows, for which
+/// standard deviations can not be calculated.
+///
+/// # Panics
+///
+/// This function will panic if `values1` and `values2` have different numbers of columns.
+pub fn standardise_arrays(
+    values1: &mut DMatrix<f64>,
+    values2: &mut DMatrix<f64>,
+    pooling: ScalePooling,
+) -> Result<(), UaError> {
+    check_nobs("values1", values1.nrows())?;
+    check_nobs("values2", values2.nrows())?;
+    assert_eq!(
+        values1.ncols(),
+        values2.ncols(),
+        "values1 and values2 must have the same number of columns."
+    );
+
+    match pooling {
+        ScalePooling::Pooled => {
+            standardise_arrays_reported(values1, values2)?;
+        }
+        ScalePooling::Separate => {
+            for i in 0..values1.ncols() {
+                standardise_array(values1, i);
+                standardise_array(values2, i);
+            }
+        }
+        ScalePooling::Balanced => {
+            for i in 0..values1.ncols() {
+                let (mean1, sd1) = utils::mean_sd_column(&values1.column(i).clone_owned())?;
+                let (mean2, sd2) = utils::mean_sd_column(&values2.column(i).clone_owned())?;
+                let mean_val = (mean1 + mean2) / 2.0;
+                let std_dev =
+                    ((sd1.powi(2) + sd2.powi(2)) / 2.0 + ((mean1 - mean2) / 2.0).powi(2)).sqrt();
+
+                for val in values1.column_mut(i).iter_mut() {
+                    *val = (*val - mean_val) / std_dev;
+                }
+                for val in values2.column_mut(i).iter_mut() {
+                    *val = (*val - mean_val) / std_dev;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Method used to transform variables onto common scales prior to matching.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ScaleMode {
+    /// Leave values in their original units. This is only appropriate where both inputs are
+    /// already on identical and meaningful scales.
+    #[default]
+    None,
+    /// Standardise to z-scores, using pooled means and standard deviations.
+    ZScore,
+    /// Centre on pooled medians, and scale by pooled interquartile ranges. This is less sensitive
+    /// to outliers than z-scores.
+    Iqr,
+}
+
+/// Transform all columns of two arrays onto common scales. Arrays are transformed in-place.
+///
+/// Statistics are always pooled across both arrays. With `ScaleMode::Iqr`, any columns with an
+/// interquartile range of zero are centred on their medians, but not scaled.
+///
+/// # Arguments
+/// * `values1` - The first array to be scaled.
+/// * `values2` - The second array to be scaled, with the same number of columns as `values1`.
+/// * `mode` - The method used to scale the arrays.
+///
+/// # Errors
+///
+/// Returns `UaError::InsufficientData` if either array has fewer than `MIN_NOBS` rows, except with
+/// `ScaleMode::None`, for which arrays are returned unchanged.
+///
+/// # Panics
+///
+/// This function will panic if `values1` and `values2` have different numbers of columns.
+pub fn scale_arrays(
+    values1: &mut DMatrix<f64>,
+    values2: &mut DMatrix<f64>,
+    mode: ScaleMode,
+) -> Result<(), UaError> {
+    match mode {
+        ScaleMode::None => Ok(()),
+        ScaleMode::ZScore => standardise_arrays(values1, values2, ScalePooling::Pooled),
+        ScaleMode::Iqr => {
+            check_nobs("values1", values1.nrows())?;
+            check_nobs("values2", values2.nrows())?;
+            assert_eq!(
+                values1.ncols(),
+                values2.ncols(),
+                "values1 and values2 must have the same number of columns."
+            );
+
+            for i in 0..values1.ncols() {
+                let pooled: Vec<f64> = values1
+                    .column(i)
+                    .iter()
+                    .chain(values2.column(i).iter())
+                    .cloned()
+                    .collect();
+                let median = stats::quantile(&pooled, 0.5);
+                let iqr = stats::quantile(&pooled, 0.75) - stats::quantile(&pooled, 0.25);
+                let iqr = if iqr > crate::EPS { iqr } else { 1.0 };
+
+                for val in values1.column_mut(i).iter_mut() {
+                    *val = (*val - median) / iqr;
+                }
+                for val in values2.column_mut(i).iter_mut() {
+                    *val = (*val - median) / iqr;
+                }
+            }
+            Ok(())
+        }
     }
+}
+
+/// Fixed means and standard deviations used to standardise arrays, such as those of a single
+/// baseline city against which many other cities are compared.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Scaling {
+    /// Mean of each variable (column).
+    pub means: Vec<f64>,
+    /// Standard deviation of each variable (column).
+    pub sds: Vec<f64>,
+}
+
+impl Scaling {
+    /// Calculate the mean and standard deviation of each column of `values`.
+    ///
+    /// This requires values to have already been read in full, and traverses them a second time.
+    /// Use `readfile_with_scaling` to calculate the same statistics in a single pass while values
+    /// are read.
+    ///
+    /// # Errors
+    ///
+    /// Returns `UaError::InsufficientData` if `values` has fewer than `MIN_NOBS` rows.
+    pub fn from_values(values: &DMatrix<f64>) -> Result<Self, UaError> {
+        check_nobs("values", values.nrows())?;
+        let (means, sds) = values
+            .column_iter()
+            .map(|col| {
+                let mut acc = stats::MeanVar::new();
+                col.iter().for_each(|&x| acc.push(x));
+                (acc.mean(), acc.sd())
+            })
+            .unzip();
+        Ok(Scaling { means, sds })
+    }
+}
+
+/// Standardise all columns of an array to z-scores using fixed statistics. The array is
+/// transformed in-place.
+///
+/// Unlike `standardise_arrays`, the result for each observation depends only on `scaling`, and not
+/// on any other observations, so arrays for different cities standardised with the same
+/// `scaling` remain directly comparable. This is the asymmetric counterpart of the pooled
+/// `standardise_arrays`: a baseline city standardised with `Scaling::from_values` of its own values
+/// has zero means, while any other city standardised against the same `scaling` has means relative
+/// to that baseline.
+///
+/// # Arguments
+/// * `values` - The array to be standardised.
+/// * `scaling` - The means and standard deviations of each column.
+///
+/// # Panics
+///
+/// This function will panic if `scaling` does not have one mean and standard deviation for each
+/// column of `values`.
+///
+/// # Example
+///
+/// ```
+/// use nalgebra::DMatrix;
+/// use uamutations::read_write_file::{apply_fixed_scaling, Scaling};
+/// let scaling = Scaling { means: vec![1.0, 10.0], sds: vec![2.0, 5.0] };
+/// let mut values = DMatrix::from_vec(2, 2, vec![1.0, 5.0, 0.0, 20.0]);
+/// apply_fixed_scaling(&mut values, &scaling);
+/// assert_eq!(values, DMatrix::from_vec(2, 2, vec![0.0, 2.0, -2.0, 2.0]));
+/// ```
+pub fn apply_fixed_scaling(values: &mut DMatrix<f64>, scaling: &Scaling) {
     assert!(
-        city_group.len() == values.nrows(),
-        "The length of city_group does not match the number of rows in values"
+        scaling.means.len() == values.ncols() && scaling.sds.len() == values.ncols(),
+        "scaling must have one mean and standard deviation for each column of values"
     );
+    for (i, mut col) in values.column_iter_mut().enumerate() {
+        for val in col.iter_mut() {
+            *val = (*val - scaling.means[i]) / scaling.sds[i];
+        }
+    }
+}
+
+/// Statistics used to standardise two arrays with pooled statistics, as returned from
+/// `standardise_arrays_reported`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScalingReport {
+    /// Pooled mean of each variable (column).
+    pub means: Vec<f64>,
+    /// Pooled standard deviation of each variable (column).
+    pub sds: Vec<f64>,
+    /// Number of observations from the first array.
+    pub nobs1: usize,
+    /// Number of observations from the second array.
+    pub nobs2: usize,
+}
+
+/// Standardise all columns of two arrays to z-scores using pooled statistics, as for
+/// `standardise_arrays` with `ScalePooling::Pooled`, and report the statistics used.
+///
+/// # Arguments
+/// * `values1` - The first array to be standardised.
+/// * `values2` - The second array to be standardised, with the same number of columns as
+///   `values1`.
+///
+/// # Errors
+///
+/// Returns `UaError::InsufficientData` if either array has fewer than `MIN_NOBS` rows.
+///
+/// # Panics
+///
+/// This function will panic if `values1` and `values2` have different numbers of columns.
+///
+/// # Returns
+/// A `ScalingReport` with the pooled mean and standard deviation used for each variable.
+pub fn standardise_arrays_reported(
+    values1: &mut DMatrix<f64>,
+    values2: &mut DMatrix<f64>,
+) -> Result<ScalingReport, UaError> {
+    check_nobs("values1", values1.nrows())?;
+    check_nobs("values2", values2.nrows())?;
+    assert_eq!(
+        values1.ncols(),
+        values2.ncols(),
+        "values1 and values2 must have the same number of columns."
+    );
+
+    let mut means = Vec::with_capacity(values1.ncols());
+    let mut sds = Vec::with_capacity(values1.ncols());
+    for i in 0..values1.ncols() {
+        let col1 = values1.column(i);
+        let col2 = values2.column(i);
+        let sum_values: f64 = col1.sum() + col2.sum();
+        let sum_values_sq: f64 = col1.iter().chain(col2.iter()).map(|&x| x.powi(2)).sum();
+
+        let nobs = (values1.nrows() + values2.nrows()) as f64;
+        let mean_val: f64 = sum_values / nobs;
+        let std_dev: f64 =
+            ((sum_values_sq / nobs - (sum_values / nobs).powi(2)) * nobs / (nobs - 1.0)).sqrt();
+
+        for val in values1.column_mut(i).iter_mut() {
+            *val = (*val - mean_val) / std_dev;
+        }
+        for val in values2.column_mut(i).iter_mut() {
+            *val = (*val - mean_val) / std_dev;
+        }
+        means.push(mean_val);
+        sds.push(std_dev);
+    }
+
+    Ok(ScalingReport {
+        means,
+        sds,
+        nobs1: values1.nrows(),
+        nobs2: values2.nrows(),
+    })
+}
+
+/// Standardise all columns of two arrays to z-scores using pooled statistics, as for
+/// `standardise_arrays` with `ScalePooling::Pooled`, with observations of `values1` weighted.
+///
+/// Weights are rescaled to a mean of one, so that `values1` retains the same total weight
+/// relative to `values2`, each observation of which has a weight of one. Uniform weights then
+/// give the same statistics as unweighted standardisation.
+///
+/// # Arguments
+/// * `values1` - The first array to be standardised.
+/// * `values2` - The second array to be standardised, with the same number of columns as
+///   `values1`.
+/// * `weights1` - Non-negative weight of each observation (row) of `values1`.
+///
+/// # Errors
+///
+/// Returns `UaError::InsufficientData` if either array has fewer than `MIN_NOBS` rows.
+///
+/// # Panics
+///
+/// This function will panic if `values1` and `values2` have different numbers of columns, if
+/// `weights1` has a different length to the number of rows of `values1`, or if any weights are
+/// negative, or all weights are zero.
+///
+/// # Returns
+/// A `ScalingReport` with the weighted pooled mean and standard deviation used for each variable.
+pub fn standardise_arrays_weighted(
+    values1: &mut DMatrix<f64>,
+    values2: &mut DMatrix<f64>,
+    weights1: &[f64],
+) -> Result<ScalingReport, UaError> {
+    check_nobs("values1", values1.nrows())?;
+    check_nobs("values2", values2.nrows())?;
+    assert_eq!(
+        values1.ncols(),
+        values2.ncols(),
+        "values1 and values2 must have the same number of columns."
+    );
+    assert_eq!(
+        weights1.len(),
+        values1.nrows(),
+        "weights1 must have same length as number of rows of values1"
+    );
+    assert!(
+        weights1.iter().all(|&w| w >= 0.0),
+        "weights must not be negative"
+    );
+    let sum_weights: f64 = weights1.iter().sum();
+    assert!(sum_weights > 0.0, "weights must not all be zero");
+    let nobs1 = values1.nrows() as f64;
+    let weights1: Vec<f64> = weights1.iter().map(|w| w * nobs1 / sum_weights).collect();
+
+    let mut means = Vec::with_capacity(values1.ncols());
+    let mut sds = Vec::with_capacity(values1.ncols());
+    for i in 0..values1.ncols() {
+        let col1 = values1.column(i);
+        let col2 = values2.column(i);
+        let sum_values: f64 =
+            col1.iter().zip(&weights1).map(|(x, w)| w * x).sum::<f64>() + col2.sum();
+        let sum_values_sq: f64 = col1
+            .iter()
+            .zip(&weights1)
+            .map(|(x, w)| w * x.powi(2))
+            .sum::<f64>()
+            + col2.iter().map(|&x| x.powi(2)).sum::<f64>();
+
+        let nobs = nobs1 + values2.nrows() as f64;
+        let mean_val: f64 = sum_values / nobs;
+        let std_dev: f64 =
+            ((sum_values_sq / nobs - (sum_values / nobs).powi(2)) * nobs / (nobs - 1.0)).sqrt();
+
+        for val in values1.column_mut(i).iter_mut() {
+            *val = (*val - mean_val) / std_dev;
+        }
+        for val in values2.column_mut(i).iter_mut() {
+            *val = (*val - mean_val) / std_dev;
+        }
+        means.push(mean_val);
+        sds.push(std_dev);
+    }
+
+    Ok(ScalingReport {
+        means,
+        sds,
+        nobs1: values1.nrows(),
+        nobs2: values2.nrows(),
+    })
+}
+
+/// Inputs to a run of the mutation algorithm which are not part of `MutationConfig`, recorded
+/// along with the configuration by `write_metadata`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Provenance {
+    /// Path to the JSON file with data which were mutated.
+    pub path1: String,
+    /// Path to the JSON file with data of the mutation target.
+    pub path2: String,
+    /// Names of variables, the first of which was mutated.
+    pub varnames: Vec<String>,
+    /// The number of entries read from each file.
+    pub nentries: usize,
+    /// Name of the `Matcher` used, such as "GreedyMatcher".
+    pub matcher: String,
+}
+
+/// Writes a JSON sidecar file describing how the results written to `filename` were produced.
+///
+/// The sidecar is written to `<filename>.meta.json`, and records the version of this crate, all
+/// fields of `provenance`, the main options of `config`, the number of aggregated groups in
+/// `result`, and descriptions of all warnings. The algorithm has no random components, so these
+/// suffice to reproduce the results from the same input files.
+///
+/// # Arguments
+///
+/// * `provenance` - The input files, variables, and matcher used.
+/// * `config` - The `MutationConfig` used.
+/// * `result` - The result of `uamutate_debug`.
+/// * `filename` - Name of the output file to which the sidecar belongs.
+///
+/// # Errors
+///
+/// Returns `UaError::Io` if the sidecar file can not be created or written.
+///
+/// # Returns
+///
+/// The name of the sidecar file.
+///
+/// # Example
+///
+/// ```
+/// use uamutations::calculate_dists::GreedyMatcher;
+/// use uamutations::read_write_file::{write_metadata, JsonReader, Provenance};
+/// use uamutations::{uamutate_debug, MutationConfig};
+/// let provenance = Provenance {
+///     path1: "./test_resources/dat1.json".to_string(),
+///     path2: "./test_resources/dat2.json".to_string(),
+///     varnames: vec!["bike_index".to_string()],
+///     nentries: 100,
+///     matcher: "GreedyMatcher".to_string(),
+/// };
+/// let config = MutationConfig::default();
+/// let result = uamutate_debug(
+///     &JsonReader::Path(provenance.path1.clone().into()),
+///     &JsonReader::Path(provenance.path2.clone().into()),
+///     &provenance.varnames,
+///     provenance.nentries,
+///     &GreedyMatcher,
+///     &config,
+/// )
+/// .unwrap();
+/// let sidecar = write_metadata(&provenance, &config, &result, "/tmp/doc_output.txt").unwrap();
+/// assert_eq!(sidecar, "/tmp/doc_output.txt.meta.json");
+/// ```
+pub fn write_metadata(
+    provenance: &Provenance,
+    config: &MutationConfig,
+    result: &MutationDebug,
+    filename: &str,
+) -> Result<String, UaError> {
+    let metadata = serde_json::json!({
+        "crate_version": env!("CARGO_PKG_VERSION"),
+        "path1": provenance.path1,
+        "path2": provenance.path2,
+        "varnames": provenance.varnames,
+        "nentries": provenance.nentries,
+        "matcher": provenance.matcher,
+        "config": {
+            "on_exhaustion": format!("{:?}", config.on_exhaustion),
+            "log_vars": config.log_vars,
+            "output": format!("{:?}", config.output),
+            "scale": format!("{:?}", config.scale),
+            "fixed_scaling": config.fixed_scaling.is_some(),
+            "nan_policy": format!("{:?}", config.nan_policy),
+            "relative_base": format!("{:?}", config.relative_base),
+            "dedup_epsilon": config.dedup_epsilon,
+            "skip_covariate_adjustment": config.skip_covariate_adjustment,
+            "weighted": config.weights.is_some(),
+            "mode": format!("{:?}", config.mode),
+        },
+        "ngroups": result.sums.nrows(),
+        "warnings": result.warnings.iter().map(|w| w.to_string()).collect::<Vec<String>>(),
+    });
+
+    let sidecar = format!("{}.meta.json", filename);
+    let text = serde_json::to_string_pretty(&metadata).map_err(|e| UaError::Io(e.to_string()))?;
+    std::fs::write(&sidecar, text)?;
+
+    Ok(sidecar)
+}
+
+/// Writes the mean mutation values to a file.
+///
+/// Files with names ending in `.gz` are gzip-compressed, while all other files are written as
+/// plain text. The uncompressed contents are identical in both cases.
+///
+/// # Arguments
+///
+/// * `sums` - Mutation values aggregated into city polygons.
+/// * `filename` - The name of the file to which the data will be written.
+///
+/// # Errors
+///
+/// Returns `UaError::Io` if the file can not be created or written. Lines are written as they are
+/// generated, so the file may be left partially written if an error occurs part way through.
+pub fn write_file(sums: &DMatrix<f64>, filename: &str) -> Result<(), UaError> {
+    write_file_with_header(sums, filename, None)
+}
+
+/// Writes the mean mutation values to a file as for `write_file`, with the name of the mutated
+/// variable included in the header.
+///
+/// Each column name in the header is prefixed with `name`, so that output files for different
+/// variables can be distinguished, and combined without ambiguity.
+///
+/// # Arguments
+///
+/// * `sums` - Mutation values aggregated into city polygons.
+/// * `filename` - The name of the file to which the data will be written.
+/// * `name` - Name of the mutated variable.
+///
+/// # Errors
+///
+/// Returns errors under the same conditions as `write_file`.
+///
+/// # Example
+///
+/// ```
+/// use nalgebra::DMatrix;
+/// use uamutations::read_write_file::write_file_named;
+/// let sums = DMatrix::from_vec(1, 4, vec![1.0, 2.0, 1.0, 1.0]);
+/// let filename = "/tmp/write_file_named_doctest.txt";
+/// write_file_named(&sums, filename, "bike_index").unwrap();
+/// let contents = std::fs::read_to_string(filename).unwrap();
+/// assert!(contents.starts_with("bike_index_original, bike_index_transformed,"));
+/// ```
+pub fn write_file_named(sums: &DMatrix<f64>, filename: &str, name: &str) -> Result<(), UaError> {
+    write_file_with_header(sums, filename, Some(name))
+}
 
-    (values, city_group)
+fn write_file_with_header(
+    sums: &DMatrix<f64>,
+    filename: &str,
+    name: Option<&str>,
+) -> Result<(), UaError> {
+    let file = File::create(filename)?;
+    if filename.ends_with(".gz") {
+        let mut encoder = GzEncoder::new(file, Compression::default());
+        write_to_with_header(&mut encoder, sums, name)?;
+        encoder.finish()?;
+    } else {
+        write_to_with_header(file, sums, name)?;
+    }
+    Ok(())
 }
 
-/// Standarise one column of an array to z-scores. Column in standardised in-place.
-///
-/// This is used for social variables, which need to be standardised in order to have comparable
-/// beta coefficients for the MLR routines.
+/// Writes several named columns of values side by side to a single file, such as results for
+/// different variables from several runs of the mutation algorithm.
 ///
 /// # Arguments
-/// * `values` - The array to be standarised.
-/// * `i` - The index of the column to be standarised.
 ///
-/// # Returns
-/// The standarised array.
-pub fn standardise_array(values: &mut DMatrix<f64>, i: usize) {
-    let sum_values: f64 = values.column(i).sum();
-
-    let sum_values_sq: f64 = values.column(i).iter().map(|&x| x.powi(2)).sum();
-
-    // Calculate standard deviations:
-    let nobs = values.nrows() as f64;
-    let mean_val: f64 = sum_values / nobs;
-    let std_dev: f64 =
-        ((sum_values_sq / nobs - (sum_values / nobs).powi(2)) * nobs / (nobs - 1.0)).sqrt();
+/// * `columns` - Pairs of column names and values. Names are written as a comma-separated header
+///   line, followed by one line for each row of values.
+/// * `filename` - The name of the file to which the data will be written.
+///
+/// # Errors
+///
+/// Returns `UaError::LengthMismatch` if any columns have different lengths to the first column, in
+/// which case no file is written, or `UaError::Io` if the file can not be created or written.
+///
+/// # Example
+///
+/// ```
+/// use uamutations::read_write_file::write_file_wide;
+/// let columns = vec![
+///     ("bike_index".to_string(), vec![0.1, 0.2]),
+///     ("natural".to_string(), vec![1.5, -0.5]),
+/// ];
+/// let filename = "/tmp/write_file_wide_doctest.txt";
+/// write_file_wide(&columns, filename).unwrap();
+/// let contents = std::fs::read_to_string(filename).unwrap();
+/// assert_eq!(contents, "bike_index, natural\n0.1,1.5\n0.2,-0.5\n");
+/// ```
+pub fn write_file_wide(columns: &[(String, Vec<f64>)], filename: &str) -> Result<(), UaError> {
+    let nrows = columns.first().map_or(0, |(_, v)| v.len());
+    if let Some((name, v)) = columns.iter().find(|(_, v)| v.len() != nrows) {
+        return Err(UaError::LengthMismatch {
+            name: name.clone(),
+            len: v.len(),
+            expected: nrows,
+        });
+    }
 
-    // Transform values:
-    for val in &mut values.column_mut(i) {
-        *val = (*val - mean_val) / std_dev;
+    let mut file = File::create(filename)?;
+    let names: Vec<&str> = columns.iter().map(|(name, _)| name.as_str()).collect();
+    writeln!(file, "{}", names.join(", "))?;
+    for i in 0..nrows {
+        let row: Vec<f64> = columns.iter().map(|(_, v)| v[i]).collect();
+        write_row(&mut file, &row)?;
     }
+    Ok(())
 }
 
-/// Writes the mean mutation values to a file.
+/// Writes the mean mutation values to any `Write` sink, such as an in-memory buffer or stdout.
 ///
 /// # Arguments
 ///
+/// * `writer` - The sink to which the data will be written.
 /// * `sums` - Mutation values aggregated into city polygons.
-/// * `filename` - The name of the file to which the data will be written.
 ///
-/// # Panics
+/// # Errors
+///
+/// Returns any error encountered while writing to `writer`.
+///
+/// # Example
 ///
-/// This function will panic if it fails to create or write to the file.
-pub fn write_file(sums: &DMatrix<f64>, filename: &str) {
-    let mut file = File::create(filename).expect("Unable to create file");
+/// ```
+/// use nalgebra::DMatrix;
+/// use uamutations::read_write_file::write_to;
+/// let sums = DMatrix::from_vec(1, 4, vec![1.0, 2.0, 1.0, 1.0]);
+/// let mut buffer: Vec<u8> = Vec::new();
+/// write_to(&mut buffer, &sums).unwrap();
+/// assert!(String::from_utf8(buffer).unwrap().ends_with("1,2,1,1\n"));
+/// ```
+pub fn write_to<W: Write>(writer: W, sums: &DMatrix<f64>) -> std::io::Result<()> {
+    write_to_with_header(writer, sums, None)
+}
 
-    // Write the header line
-    writeln!(file, "original, transformed, absolute, relative").expect("Unable to write to file");
+fn write_to_with_header<W: Write>(
+    mut writer: W,
+    sums: &DMatrix<f64>,
+    name: Option<&str>,
+) -> std::io::Result<()> {
+    write_header_named(&mut writer, name)?;
 
     for i in 0..sums.nrows() {
         let row: Vec<f64> = sums.row(i).iter().cloned().collect();
-        let row_str: Vec<String> = row.iter().map(|&x| format!("{}", x)).collect();
-        writeln!(file, "{}", row_str.join(",")).expect("Unable to write to file");
+        write_row(&mut writer, &row)?;
     }
+
+    Ok(())
+}
+
+/// Write the header line of an output file.
+pub(crate) fn write_header<W: Write>(writer: &mut W) -> std::io::Result<()> {
+    write_header_named(writer, None)
+}
+
+/// Write the header line of an output file, with column names prefixed by `name`, if given.
+fn write_header_named<W: Write>(writer: &mut W, name: Option<&str>) -> std::io::Result<()> {
+    let cols = ["original", "transformed", "absolute", "relative"];
+    let cols: Vec<String> = match name {
+        Some(name) => cols.iter().map(|c| format!("{}_{}", name, c)).collect(),
+        None => cols.iter().map(|c| c.to_string()).collect(),
+    };
+    writeln!(writer, "{}", cols.join(", "))
+}
+
+/// Write one row of an output file.
+pub(crate) fn write_row<W: Write>(writer: &mut W, row: &[f64]) -> std::io::Result<()> {
+    let row_str: Vec<String> = row.iter().map(|&x| format!("{}", x)).collect();
+    writeln!(writer, "{}", row_str.join(","))
 }
 
 #[cfg(test)]
@@ -158,6 +1815,20 @@ mod tests {
     use super::*;
     use approx::assert_abs_diff_eq;
 
+    /// Open a JSON test fixture.
+    fn open(path: &str) -> BufReader<File> {
+        BufReader::new(File::open(path).unwrap())
+    }
+
+    /// Read values of `varnames` from a JSON test fixture, excluding entries with missing values.
+    fn read_complete(path: &str, varnames: &[String], nentries: usize) -> DMatrix<f64> {
+        let values = readfile(path, varnames, nentries).unwrap().values;
+        let complete: Vec<usize> = (0..values.nrows())
+            .filter(|&i| values.row(i).iter().all(|x| !x.is_nan()))
+            .collect();
+        values.select_rows(&complete)
+    }
+
     #[test]
     fn test_readfile() {
         let filename1 = "./test_resources/dat1.json";
@@ -170,26 +1841,27 @@ mod tests {
         let file1a = File::open(filename1).unwrap();
         let reader1a = BufReader::new(file1a);
         let result = std::panic::catch_unwind(|| {
-            readfile(reader1a, &varnames, nentries);
+            let _ = readfile(reader1a, &varnames, nentries);
         });
         assert!(result.is_err(), "Expected an error when nentries <= 0");
 
         // Test error when variables do not exist in JSON file
         let file1b = File::open(filename1).unwrap();
         let reader1b = BufReader::new(file1b);
-        let result = std::panic::catch_unwind(|| {
-            readfile(reader1b, &["nonexistent_var".to_string()], nentries);
-        });
-        assert!(
-            result.is_err(),
-            "Expected an error when varname does not exist"
+        let result = readfile(reader1b, &["nonexistent_var".to_string()], 10);
+        assert_eq!(
+            result.unwrap_err(),
+            UaError::MissingVariable {
+                name: "JSON input".to_string(),
+                varname: "nonexistent_var".to_string(),
+            }
         );
 
         // Test error when nentries == 0:
         let file1c = File::open(filename1).unwrap();
         let reader1c = BufReader::new(file1c);
         let result = std::panic::catch_unwind(|| {
-            readfile(reader1c, &varnames, 0);
+            let _ = readfile(reader1c, &varnames, 0);
         });
         assert!(result.is_err(), "Expected an error when nentries <= 0");
 
@@ -200,8 +1872,10 @@ mod tests {
         let reader1d = BufReader::new(file1d);
         let file2a = File::open(filename2).unwrap();
         let reader2a = BufReader::new(file2a);
-        let (values1, groups1) = readfile(reader1d, &varnames, nentries);
-        let (values2, groups2) = readfile(reader2a, &varnames, nentries);
+        let city1 = readfile(reader1d, &varnames, nentries).unwrap();
+        let city2 = readfile(reader2a, &varnames, nentries).unwrap();
+        let (values1, groups1) = (city1.values, city1.groups);
+        let (values2, groups2) = (city2.values, city2.groups);
 
         assert_eq!(
             values1.nrows(),
@@ -225,6 +1899,312 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_city_matrix_column_for() {
+        let filename = "./test_resources/dat1.json";
+        let varnames = vec!["transport".to_string(), "bike_index".to_string()];
+        let city = readfile(BufReader::new(File::open(filename).unwrap()), &varnames, 10).unwrap();
+
+        let bike_index = city.column_for("bike_index").unwrap();
+        assert_eq!(bike_index.len(), 10);
+        // First record of 'dat1' has "bike_index":0.817:
+        assert_eq!(bike_index[0], 0.817);
+        assert_eq!(city.column_for("transport").unwrap()[0], 27.1006);
+        assert!(city.column_for("index").is_none());
+    }
+
+    #[test]
+    fn test_readfile_all() {
+        let varnames = vec!["transport".to_string(), "bike_index".to_string()];
+        let (_, nrecords) = read_varnames(open("./test_resources/dat2.json")).unwrap();
+        let city = readfile_all(open("./test_resources/dat2.json"), &varnames).unwrap();
+        assert_eq!(city.nrows(), nrecords);
+        assert_eq!(city.groups.len(), nrecords);
+
+        let city = readfile_all(open("./test_resources/dat_ids.json"), &varnames).unwrap();
+        assert_eq!(city.nrows(), 4);
+    }
+
+    #[test]
+    fn test_readfile_records_path() {
+        let varnames = vec!["transport".to_string(), "bike_index".to_string()];
+        let wrapped = "./test_resources/dat_wrapped.json";
+        let city =
+            readfile_with_ids(open(wrapped), &varnames, 10, None, Some("results.data")).unwrap();
+        let city_ids = readfile(open("./test_resources/dat_ids.json"), &varnames, 3).unwrap();
+        assert_eq!(city.values, city_ids.values);
+        assert_eq!(city.groups, vec![1, 1, 2]);
+
+        let result = readfile_with_ids(open(wrapped), &varnames, 10, None, Some("results.meta"));
+        assert_eq!(
+            result.unwrap_err(),
+            UaError::Parse("JSON path 'results.meta' does not resolve to an array".to_string())
+        );
+        let result = readfile(open(wrapped), &varnames, 10);
+        assert_eq!(
+            result.unwrap_err(),
+            UaError::Parse("JSON input is not an array".to_string())
+        );
+    }
+
+    #[test]
+    fn test_readfile_skip_malformed() {
+        let varnames = vec!["transport".to_string(), "bike_index".to_string()];
+        let filename = "./test_resources/dat_malformed.json";
+
+        // Strict reading fails on the broken record:
+        let result = readfile(open(filename), &varnames, 10);
+        assert!(matches!(result, Err(UaError::Parse(_))));
+
+        let (city, nskipped) = readfile_skip_malformed(open(filename), &varnames, 10).unwrap();
+        assert_eq!(nskipped, 2);
+        assert_eq!(city.nrows(), 4);
+        assert_eq!(city.groups, vec![1, 1, 3, 4]);
+        assert_eq!(
+            city.values.column(0).as_slice(),
+            &[27.1006, 27.6308, 27.5993, 26.8713]
+        );
+
+        let (city, _) = readfile_skip_malformed(open(filename), &varnames, 3).unwrap();
+        assert_eq!(city.groups, vec![1, 1, 3]);
+
+        // A record missing its closing brace is skipped alone, and null values are read as NaN:
+        let filename = "./test_resources/dat_truncated.json";
+        let (city, nskipped) = readfile_skip_malformed(open(filename), &varnames, 10).unwrap();
+        assert_eq!(nskipped, 1);
+        assert_eq!(city.groups, vec![1, 2, 3, 3]);
+        assert_eq!(
+            city.values.column(0).as_slice(),
+            &[27.1006, 28.6907, 27.5993, 28.1162]
+        );
+        assert!(city.values[(1, 1)].is_nan());
+
+        let result = readfile_skip_malformed(open("./test_resources/empty.json"), &varnames, 3);
+        assert_eq!(
+            result.unwrap_err(),
+            UaError::EmptyInput("JSON input".to_string())
+        );
+    }
+
+    #[test]
+    fn test_readfile_with_sentinels() {
+        let varnames = vec!["transport".to_string(), "bike_index".to_string()];
+        let filename = "./test_resources/dat_sentinel.json";
+
+        // Without sentinels, values are read as real numbers:
+        let city = readfile(filename, &varnames, 10).unwrap();
+        assert_eq!(city.values[(1, 0)], -999.0);
+        assert!(city.values.iter().all(|x| !x.is_nan()));
+
+        let sentinels = HashMap::from([
+            ("transport".to_string(), -999.0),
+            ("bike_index".to_string(), 0.0),
+            ("natural".to_string(), -1.0),
+        ]);
+        let city = readfile_with_sentinels(filename, &varnames, 10, &sentinels).unwrap();
+        assert_eq!(city.nrows(), 6);
+        let missing: Vec<(usize, usize)> = (0..city.values.nrows())
+            .flat_map(|i| (0..city.values.ncols()).map(move |j| (i, j)))
+            .filter(|&(i, j)| city.values[(i, j)].is_nan())
+            .collect();
+        assert_eq!(missing, vec![(1, 0), (2, 1), (4, 0), (4, 1)]);
+        assert_eq!(city.values[(0, 0)], 27.1006);
+
+        // Sentinels apply only to their own variables:
+        let sentinels = HashMap::from([("bike_index".to_string(), -999.0)]);
+        let city = readfile_with_sentinels(filename, &varnames, 10, &sentinels).unwrap();
+        assert!(city.values.iter().all(|x| !x.is_nan()));
+    }
+
+    #[test]
+    fn test_readfile_extreme_values() {
+        let varnames = vec!["x".to_string()];
+        let read = |x: &str, group: &str| {
+            let json = format!(r#"[{{"x": {}, "index": {}}}]"#, x, group);
+            readfile(json.into_bytes(), &varnames, 10)
+        };
+
+        let city = read("4.9e-324", "1").unwrap();
+        assert_eq!(city.values[(0, 0)], 0.0);
+        let city = read("-0.0", "1").unwrap();
+        assert!(city.values[(0, 0)].is_sign_positive());
+        let city = read("-1e150", "1.0").unwrap();
+        assert_eq!(city.values[(0, 0)], -MAX_ABS_VALUE);
+        assert_eq!(city.groups, vec![1]);
+
+        // Values beyond `f64` range, and those which would overflow when squared:
+        for x in ["1e400", "-1e400", "1e200", "-1.7e308"] {
+            assert!(matches!(read(x, "1"), Err(UaError::Parse(_))), "{}", x);
+        }
+        for group in ["-1", "1.5", "1e20"] {
+            assert!(
+                matches!(read("1", group), Err(UaError::Parse(_))),
+                "{}",
+                group
+            );
+        }
+
+        // Values up to the maximum magnitude standardise to finite values:
+        let json = r#"[{"x": 1e150, "index": 1}, {"x": -1e150, "index": 2}, {"x": 0, "index": 3}]"#;
+        let mut city = readfile(json.as_bytes().to_vec(), &varnames, 10).unwrap();
+        standardise_array(&mut city.values, 0);
+        assert!(city.values.iter().all(|x| x.is_finite()));
+    }
+
+    #[test]
+    fn test_readfile_mixed_numbers() {
+        // Values, groups, and identifiers written both as integers such as `3` and as floating
+        // point values such as `3.0`:
+        let varnames = vec!["transport".to_string()];
+        let city = readfile_with_ids(
+            "./test_resources/dat_mixed.json",
+            &varnames,
+            10,
+            Some("cell_id"),
+            None,
+        )
+        .unwrap();
+        assert_eq!(city.values.as_slice(), &[3.0, 3.0, 2.5, 3.0]);
+        assert_eq!(city.groups, vec![1, 1, 2, 2]);
+        assert_eq!(city.ids, vec![9_007_199_254_740_993, -104, -104, 1000]);
+
+        let read_id = |id: &str| {
+            let json = format!(r#"[{{"transport": 1, "index": 1, "cell_id": {}}}]"#, id);
+            readfile_with_ids(json.into_bytes(), &varnames, 10, Some("cell_id"), None)
+        };
+        for id in ["1.5", "1e19", "-1e19"] {
+            assert!(matches!(read_id(id), Err(UaError::Parse(_))), "{}", id);
+        }
+    }
+
+    #[test]
+    fn test_readfile_sources() {
+        let filename = "./test_resources/dat_ids.json";
+        let varnames = vec!["transport".to_string(), "bike_index".to_string()];
+        let city = readfile(BufReader::new(File::open(filename).unwrap()), &varnames, 10).unwrap();
+
+        let city_path = readfile(filename, &varnames, 10).unwrap();
+        assert_eq!(city_path, city);
+        let city_pathbuf = readfile(std::path::PathBuf::from(filename), &varnames, 10).unwrap();
+        assert_eq!(city_pathbuf, city);
+        let city_bytes = readfile(std::fs::read(filename).unwrap(), &varnames, 10).unwrap();
+        assert_eq!(city_bytes, city);
+
+        let result = readfile("./test_resources/nonexistent.json", &varnames, 10);
+        assert!(matches!(result, Err(UaError::Io(_))));
+        let result = readfile(vec![0xff, 0xfe], &varnames, 10);
+        assert_eq!(
+            result.unwrap_err(),
+            UaError::Parse("JSON input is not valid UTF-8".to_string())
+        );
+    }
+
+    #[test]
+    fn test_readfile_large_group_ids() {
+        let varnames = vec!["transport".to_string()];
+        let json = br#"[
+            {"transport": 1.0, "index": 9007199254740993},
+            {"transport": 2.0, "index": 18446744073709551615},
+            {"transport": 3.0, "index": 4.0}
+        ]"#;
+        let city = readfile(json.to_vec(), &varnames, 10).unwrap();
+        assert_eq!(city.groups, vec![9007199254740993, 18446744073709551615, 4]);
+    }
+
+    #[test]
+    fn test_readfile_many() {
+        let filename = "./test_resources/dat1.json";
+        let varnames = vec!["transport".to_string(), "bike_index".to_string()];
+
+        // Split the fixture into two halves:
+        let json: Value =
+            serde_json::from_reader(BufReader::new(File::open(filename).unwrap())).unwrap();
+        let array = json.as_array().unwrap();
+        let (half1, half2) = array.split_at(array.len() / 2);
+        let fname1 = "/tmp/test_readfile_many_1.json";
+        let fname2 = "/tmp/test_readfile_many_2.json";
+        std::fs::write(fname1, serde_json::to_string(half1).unwrap()).unwrap();
+        std::fs::write(fname2, serde_json::to_string(half2).unwrap()).unwrap();
+
+        let nentries = array.len() - 10;
+        let city = readfile(
+            BufReader::new(File::open(filename).unwrap()),
+            &varnames,
+            nentries,
+        )
+        .unwrap();
+        let readers = vec![
+            BufReader::new(File::open(fname1).unwrap()),
+            BufReader::new(File::open(fname2).unwrap()),
+        ];
+        let city_many = readfile_many(readers, &varnames, nentries).unwrap();
+
+        assert_eq!(city_many.nrows(), nentries);
+        assert_eq!(city_many, city);
+    }
+
+    #[test]
+    fn test_readfile_with_ids() {
+        let varnames = vec!["transport".to_string(), "bike_index".to_string()];
+        let city = readfile_with_ids(
+            open("./test_resources/dat_ids.json"),
+            &varnames,
+            10,
+            Some("cell_id"),
+            None,
+        )
+        .unwrap();
+        assert_eq!(city.nrows(), 4);
+        assert_eq!(city.groups, vec![1, 1, 2, 3]);
+        assert_eq!(city.ids, vec![9007199254740993, 102, 103, -104]);
+
+        let city = readfile_with_ids(
+            open("./test_resources/dat_ids.json"),
+            &varnames,
+            10,
+            None,
+            None,
+        )
+        .unwrap();
+        assert!(city.ids.is_empty());
+    }
+
+    #[test]
+    fn test_readfile_empty() {
+        let file = File::open("./test_resources/empty.json").unwrap();
+        let result = readfile(BufReader::new(file), &["transport".to_string()], 10);
+        assert_eq!(
+            result.unwrap_err(),
+            UaError::EmptyInput("JSON input".to_string())
+        );
+    }
+
+    #[test]
+    fn test_readfile_bom() {
+        let filename = "./test_resources/dat1.json";
+        let varnames = vec!["transport".to_string(), "bike_index".to_string()];
+        let contents = std::fs::read(filename).unwrap();
+        let fname_bom = "/tmp/test_readfile_bom.json";
+        std::fs::write(fname_bom, [&[0xEF, 0xBB, 0xBF], &contents[..]].concat()).unwrap();
+
+        let city = readfile(open(filename), &varnames, 10).unwrap();
+        let city_bom = readfile(open(fname_bom), &varnames, 10).unwrap();
+        assert_eq!(city_bom, city);
+
+        // Non-UTF-8 input errors with a clear message:
+        let fname_latin1 = "/tmp/test_readfile_latin1.json";
+        std::fs::write(
+            fname_latin1,
+            b"[{\"transport\": 1.0, \"name\": \"M\xfcnchen\"}]",
+        )
+        .unwrap();
+        let result = readfile(open(fname_latin1), &varnames, 10);
+        assert_eq!(
+            result.unwrap_err(),
+            UaError::Parse("JSON input is not valid UTF-8".to_string())
+        );
+    }
+
     #[test]
     fn test_standardise_array() {
         // The rows and columns are:
@@ -255,6 +2235,310 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_standardise_arrays_pooling() {
+        let v1 = DMatrix::from_vec(5, 1, vec![1.0, 2.0, 3.0, 4.0, 5.0]);
+        let v2 = DMatrix::from_vec(5, 1, vec![11.0, 12.0, 13.0, 14.0, 15.0]);
+
+        let (mut p1, mut p2) = (v1.clone(), v2.clone());
+        standardise_arrays(&mut p1, &mut p2, ScalePooling::Pooled).unwrap();
+        // Pooled means are equal and opposite, retaining the difference in levels:
+        assert_abs_diff_eq!(p1.mean() + p2.mean(), 0.0, epsilon = 1e-10);
+        assert!(p1.mean() < -0.5 && p2.mean() > 0.5);
+
+        let (mut s1, mut s2) = (v1.clone(), v2.clone());
+        standardise_arrays(&mut s1, &mut s2, ScalePooling::Separate).unwrap();
+        // Separate standardisation removes the difference in levels entirely:
+        assert_abs_diff_eq!(s1.mean(), 0.0, epsilon = 1e-10);
+        assert_abs_diff_eq!(s2.mean(), 0.0, epsilon = 1e-10);
+        for (a, b) in s1.iter().zip(s2.iter()) {
+            assert_abs_diff_eq!(a, b, epsilon = 1e-10);
+        }
+    }
+
+    #[test]
+    fn test_standardise_arrays_reported() {
+        let varnames = vec!["transport".to_string(), "bike_index".to_string()];
+        let mut values1 = readfile(open("./test_resources/dat1.json"), &varnames, 20)
+            .unwrap()
+            .values;
+        let mut values2 = read_complete("./test_resources/dat2.json", &varnames, 30);
+        let nobs2 = values2.nrows();
+        let pooled_means: Vec<f64> = (0..2)
+            .map(|i| (values1.column(i).sum() + values2.column(i).sum()) / (20 + nobs2) as f64)
+            .collect();
+
+        let report = standardise_arrays_reported(&mut values1, &mut values2).unwrap();
+        assert_eq!(report.nobs1, 20);
+        assert_eq!(report.nobs2, nobs2);
+        for (mean, pooled_mean) in report.means.iter().zip(pooled_means.iter()) {
+            assert_abs_diff_eq!(mean, pooled_mean, epsilon = 1e-10);
+        }
+        assert!(report.sds.iter().all(|&sd| sd > 0.0));
+        // Standardised values then have zero pooled mean:
+        let sum0 = values1.column(0).sum() + values2.column(0).sum();
+        assert_abs_diff_eq!(sum0, 0.0, epsilon = 1e-8);
+    }
+
+    #[test]
+    fn test_standardise_arrays_weighted() {
+        let varnames = vec!["transport".to_string(), "bike_index".to_string()];
+        let values1 = readfile("./test_resources/dat1.json", &varnames, 20)
+            .unwrap()
+            .values;
+        let values2 = read_complete("./test_resources/dat2.json", &varnames, 30);
+
+        // Uniform weights reproduce unweighted standardisation:
+        let (mut a1, mut a2) = (values1.clone(), values2.clone());
+        let (mut b1, mut b2) = (values1.clone(), values2.clone());
+        let report = standardise_arrays_reported(&mut a1, &mut a2).unwrap();
+        let report_w = standardise_arrays_weighted(&mut b1, &mut b2, &[3.0; 20]).unwrap();
+        for (a, b) in report.means.iter().zip(&report_w.means) {
+            assert_abs_diff_eq!(a, b, epsilon = 1e-10);
+        }
+        assert_abs_diff_eq!(a1, b1, epsilon = 1e-10);
+
+        // Weights rescaled to a mean of one count the first 10 rows of `values1` twice:
+        let weights: Vec<f64> = (0..20).map(|i| if i < 10 { 1.0 } else { 0.0 }).collect();
+        let (mut b1, mut b2) = (values1.clone(), values2.clone());
+        let report_w = standardise_arrays_weighted(&mut b1, &mut b2, &weights).unwrap();
+        for i in 0..2 {
+            let expected = (2.0 * values1.column(i).rows(0, 10).sum() + values2.column(i).sum())
+                / (20 + values2.nrows()) as f64;
+            assert_abs_diff_eq!(report_w.means[i], expected, epsilon = 1e-10);
+        }
+    }
+
+    #[test]
+    fn test_scale_arrays_iqr() {
+        let v1 = DMatrix::from_vec(5, 1, vec![1.0, 2.0, 3.0, 4.0, 5.0]);
+        let v2 = DMatrix::from_vec(5, 1, vec![2.0, 3.0, 4.0, 5.0, 6.0]);
+        let mut v2_outlier = v2.clone();
+        v2_outlier[(4, 0)] = 100.0;
+
+        // Change in scaled value of first entry of `v1` caused by the outlier:
+        let outlier_effect = |mode: ScaleMode| {
+            let (mut a1, mut a2) = (v1.clone(), v2.clone());
+            scale_arrays(&mut a1, &mut a2, mode).unwrap();
+            let (mut b1, mut b2) = (v1.clone(), v2_outlier.clone());
+            scale_arrays(&mut b1, &mut b2, mode).unwrap();
+            (a1[(0, 0)] - b1[(0, 0)]).abs()
+        };
+        assert!(outlier_effect(ScaleMode::Iqr) < outlier_effect(ScaleMode::ZScore));
+
+        // Pooled median is 3.5 and IQR is (4.75 - 2.25):
+        let (mut a1, mut a2) = (v1.clone(), v2.clone());
+        scale_arrays(&mut a1, &mut a2, ScaleMode::Iqr).unwrap();
+        assert_abs_diff_eq!(a1[(0, 0)], -1.0, epsilon = 1e-10);
+
+        // Zero-IQR columns are centred but not scaled:
+        let mut c1 = DMatrix::from_vec(3, 1, vec![2.0, 2.0, 2.0]);
+        let mut c2 = DMatrix::from_vec(3, 1, vec![2.0, 2.0, 5.0]);
+        scale_arrays(&mut c1, &mut c2, ScaleMode::Iqr).unwrap();
+        assert_eq!(c2.as_slice(), &[0.0, 0.0, 3.0]);
+    }
+
+    #[test]
+    fn test_apply_fixed_scaling() {
+        let varnames = vec!["transport".to_string(), "bike_index".to_string()];
+        let baseline = readfile(open("./test_resources/dat1.json"), &varnames, 100).unwrap();
+        let scaling = Scaling::from_values(&baseline.values).unwrap();
+
+        // Two target cities sharing their first 50 observations:
+        let mut target1 = read_complete("./test_resources/dat2.json", &varnames, 50);
+        let mut target2 = read_complete("./test_resources/dat2.json", &varnames, 200);
+        apply_fixed_scaling(&mut target1, &scaling);
+        apply_fixed_scaling(&mut target2, &scaling);
+        assert_eq!(target1, target2.rows(0, target1.nrows()));
+
+        // The baseline itself is standardised to zero mean and unit variance:
+        let mut base = baseline.values.clone();
+        apply_fixed_scaling(&mut base, &scaling);
+        let (mean, sd) = utils::mean_sd_dmat(&base).unwrap();
+        assert_abs_diff_eq!(mean, 0.0, epsilon = 1e-10);
+        assert_abs_diff_eq!(sd, 1.0, epsilon = 1e-10);
+
+        // Other cities are placed in the coordinate system of the baseline, so have means relative
+        // to the baseline rather than means of zero:
+        let city = read_complete("./test_resources/dat2.json", &varnames, 100);
+        let mut city_scaled = city.clone();
+        apply_fixed_scaling(&mut city_scaled, &scaling);
+        for j in 0..city.ncols() {
+            let expected = (city.column(j).mean() - scaling.means[j]) / scaling.sds[j];
+            assert_abs_diff_eq!(city_scaled.column(j).mean(), expected, epsilon = 1e-10);
+            assert!(expected.abs() > 1e-3);
+        }
+
+        let short = Scaling::from_values(&DMatrix::from_vec(1, 1, vec![1.0]));
+        assert!(matches!(short, Err(UaError::InsufficientData { .. })));
+    }
+
+    #[test]
+    fn test_readfile_with_scaling() {
+        let varnames = vec!["transport".to_string(), "bike_index".to_string()];
+        let (city, scaling) =
+            readfile_with_scaling("./test_resources/dat1.json", &varnames, 100).unwrap();
+        assert_eq!(
+            city,
+            readfile("./test_resources/dat1.json", &varnames, 100).unwrap()
+        );
+        let batch = Scaling::from_values(&city.values).unwrap();
+        for j in 0..varnames.len() {
+            assert_abs_diff_eq!(scaling.means[j], batch.means[j], epsilon = 1e-10);
+            assert_abs_diff_eq!(scaling.sds[j], batch.sds[j], epsilon = 1e-10);
+        }
+
+        // Missing values are excluded from the statistics:
+        let (city, scaling) =
+            readfile_with_scaling("./test_resources/dat_nan.json", &varnames, 100).unwrap();
+        for j in 0..varnames.len() {
+            let col: Vec<f64> = city
+                .values
+                .column(j)
+                .iter()
+                .cloned()
+                .filter(|x| !x.is_nan())
+                .collect();
+            let n = col.len() as f64;
+            let mean = col.iter().sum::<f64>() / n;
+            let var = col.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (n - 1.0);
+            assert_abs_diff_eq!(scaling.means[j], mean, epsilon = 1e-10);
+            assert_abs_diff_eq!(scaling.sds[j], var.sqrt(), epsilon = 1e-10);
+        }
+    }
+
+    #[test]
+    fn test_standardise_arrays_balanced() {
+        // Large array centred on 0, and small array centred on 10:
+        let large: Vec<f64> = (0..100).map(|i| (i % 5) as f64 - 2.0).collect();
+        let small = vec![9.0, 10.0, 10.0, 11.0];
+        let arrays = || {
+            (
+                DMatrix::from_vec(100, 1, large.clone()),
+                DMatrix::from_vec(4, 1, small.clone()),
+            )
+        };
+
+        let (mut p1, mut p2) = arrays();
+        standardise_arrays(&mut p1, &mut p2, ScalePooling::Pooled).unwrap();
+        let (mut b1, mut b2) = arrays();
+        standardise_arrays(&mut b1, &mut b2, ScalePooling::Balanced).unwrap();
+
+        // Pooled statistics are dominated by the large array, which stays close to zero:
+        assert!(p1.mean().abs() < 0.5 && p2.mean() > 3.0);
+        // Balanced statistics place both arrays symmetrically about zero:
+        assert_abs_diff_eq!(b1.mean(), -b2.mean(), epsilon = 1e-10);
+        assert!(b1.mean() < -0.5);
+        // With unit variance of an equal mixture of both arrays:
+        let var = |m: &DMatrix<f64>| utils::mean_sd_dmat(m).unwrap().1.powi(2);
+        let mixture_var = (var(&b1) + var(&b2)) / 2.0 + b1.mean().powi(2);
+        assert_abs_diff_eq!(mixture_var, 1.0, epsilon = 1e-10);
+    }
+
+    #[test]
+    fn test_standardise_arrays_single_observation() {
+        let mut v1 = DMatrix::from_vec(1, 1, vec![1.0]);
+        let mut v2 = DMatrix::from_vec(3, 1, vec![1.0, 2.0, 3.0]);
+        let result = standardise_arrays(&mut v1, &mut v2, ScalePooling::Pooled);
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "values1 has 1 observations, but at least 2 are needed"
+        );
+    }
+
+    #[test]
+    fn test_merge_cities() {
+        let city1 = readfile(
+            "./test_resources/dat1.json",
+            &["natural".to_string(), "bike_index".to_string()],
+            50,
+        )
+        .unwrap();
+        let city2 = readfile(
+            "./test_resources/dat2.json",
+            &["bike_index".to_string(), "natural".to_string()],
+            30,
+        )
+        .unwrap();
+
+        let merged = merge_cities(&[city1.clone(), city2.clone()]).unwrap();
+        assert_eq!(merged.nrows(), 80);
+        assert_eq!(merged.varnames, city1.varnames);
+        assert_eq!(merged.values.rows(0, 50), city1.values);
+        // Variables of the second city are aligned by name:
+        assert_eq!(merged.values.view((50, 0), (30, 1)), city2.values.column(1));
+        assert_eq!(merged.values.view((50, 1), (30, 1)), city2.values.column(0));
+        // Groups of the second city follow those of the first:
+        let max1 = *city1.groups.iter().max().unwrap();
+        assert_eq!(&merged.groups[..50], &city1.groups[..]);
+        assert!(merged.groups[50..].iter().all(|&g| g > max1));
+
+        // Entries of the second city in group 0 remain in group 0:
+        let city4 = readfile("./test_resources/dat_group0.json", &city1.varnames, 10).unwrap();
+        let merged = merge_cities(&[city1.clone(), city4.clone()]).unwrap();
+        for (&g, &g4) in merged.groups[50..].iter().zip(&city4.groups) {
+            assert_eq!(g, if g4 == 0 { 0 } else { g4 + max1 });
+        }
+        assert_eq!(merged.groups[50], 0);
+
+        let city3 = readfile("./test_resources/dat2.json", &["natural".to_string()], 10).unwrap();
+        assert_eq!(
+            merge_cities(&[city1.clone(), city3]),
+            Err(UaError::MissingVariable {
+                name: "city 1".to_string(),
+                varname: "bike_index".to_string()
+            })
+        );
+        // Extra variables are reported for the city which has them:
+        let mut varnames5 = city1.varnames.clone();
+        varnames5.push("social_index".to_string());
+        let city5 = readfile("./test_resources/dat2.json", &varnames5, 10).unwrap();
+        assert_eq!(
+            merge_cities(&[city1, city2, city5]),
+            Err(UaError::MissingVariable {
+                name: "city 2".to_string(),
+                varname: "social_index".to_string()
+            })
+        );
+        assert!(matches!(merge_cities(&[]), Err(UaError::EmptyInput(_))));
+    }
+
+    #[test]
+    fn test_readfile_absent_values() {
+        // Absent variables are read as `NaN`, leaving values of other records in their own rows:
+        let json = br#"[{"a":1,"b":10,"index":1},{"a":2,"index":2},{"a":3,"b":30,"index":3}]"#;
+        let varnames = vec!["a".to_string(), "b".to_string()];
+        let city = readfile(json.to_vec(), &varnames, 10).unwrap();
+        assert_eq!(city.values.column(0).as_slice(), &[1.0, 2.0, 3.0]);
+        assert_eq!(city.values[(0, 1)], 10.0);
+        assert!(city.values[(1, 1)].is_nan());
+        assert_eq!(city.values[(2, 1)], 30.0);
+        assert_eq!(city.groups, vec![1, 2, 3]);
+
+        // Records without a group index are errors:
+        let json = br#"[{"a":1,"b":10,"index":1},{"a":2,"b":20}]"#;
+        let result = readfile(json.to_vec(), &varnames, 10);
+        assert!(matches!(result, Err(UaError::Parse(_))));
+        // Unless they are beyond the records which are read:
+        let city = readfile(json.to_vec(), &varnames, 1).unwrap();
+        assert_eq!(city.groups, vec![1]);
+
+        let json = br#"[{"a":1,"index":1,"id":7},{"a":2,"index":2}]"#;
+        let result = readfile_with_ids(json.to_vec(), &varnames[..1], 10, Some("id"), None);
+        assert!(matches!(result, Err(UaError::Parse(_))));
+    }
+
+    #[test]
+    fn test_readfile_short_input() {
+        let varnames = vec!["transport".to_string(), "bike_index".to_string()];
+        let city = readfile("./test_resources/dat_ids.json", &varnames, 10).unwrap();
+        assert_eq!(city.nrows(), 4);
+        assert_eq!(city.groups.len(), city.nrows());
+
+        let city = readfile("./test_resources/dat_ids.json", &varnames, 3).unwrap();
+        assert_eq!(city.nrows(), 3);
+    }
+
     #[test]
     fn test_write_file() {
         use std::fs;
@@ -263,7 +2547,7 @@ mod tests {
         let sums = DMatrix::from_vec(4, 1, vec![1.0, 4.5, 3.0, 2.0]);
         let filename = "/tmp/test_write_file.txt";
 
-        write_file(&sums, filename);
+        write_file(&sums, filename).unwrap();
 
         let mut file = fs::File::open(filename).expect("Unable to open file");
         let mut contents = String::new();
@@ -278,4 +2562,167 @@ mod tests {
             2\n";
         assert_eq!(contents, expected_contents);
     }
+
+    #[test]
+    fn test_write_file_named() {
+        let sums = DMatrix::from_vec(2, 4, vec![1.0, 2.0, 1.5, 2.5, 0.5, 0.5, 0.5, 0.25]);
+        let plain = "/tmp/test_write_file_named_plain.txt";
+        let named = "/tmp/test_write_file_named.txt";
+        write_file(&sums, plain).unwrap();
+        write_file_named(&sums, named, "social_index").unwrap();
+
+        let plain = std::fs::read_to_string(plain).unwrap();
+        let named = std::fs::read_to_string(named).unwrap();
+        let header = named.lines().next().unwrap();
+        assert_eq!(
+            header,
+            "social_index_original, social_index_transformed, social_index_absolute, \
+             social_index_relative"
+        );
+        // Only the header differs:
+        assert!(named.lines().skip(1).eq(plain.lines().skip(1)));
+    }
+
+    #[test]
+    fn test_write_metadata() {
+        let provenance = Provenance {
+            path1: "./test_resources/dat1.json".to_string(),
+            path2: "./test_resources/dat2.json".to_string(),
+            varnames: vec!["bike_index".to_string(), "bike_index".to_string()],
+            nentries: 50,
+            matcher: "GreedyMatcher".to_string(),
+        };
+        let config = MutationConfig {
+            scale: ScaleMode::ZScore,
+            ..Default::default()
+        };
+        let result = crate::uamutate_debug(
+            &JsonReader::Path(provenance.path1.clone().into()),
+            &JsonReader::Path(provenance.path2.clone().into()),
+            &provenance.varnames,
+            provenance.nentries,
+            &crate::calculate_dists::GreedyMatcher,
+            &config,
+        )
+        .unwrap();
+
+        let sidecar = write_metadata(
+            &provenance,
+            &config,
+            &result,
+            "/tmp/test_write_metadata.txt",
+        )
+        .unwrap();
+        assert_eq!(sidecar, "/tmp/test_write_metadata.txt.meta.json");
+        let json: Value = serde_json::from_str(&std::fs::read_to_string(sidecar).unwrap()).unwrap();
+        assert_eq!(json["varnames"][0], "bike_index");
+        assert_eq!(json["nentries"], 50);
+        assert_eq!(json["config"]["scale"], "ZScore");
+        assert_eq!(json["crate_version"], env!("CARGO_PKG_VERSION"));
+        // The duplicated variable is recorded as a warning:
+        let warnings = json["warnings"].as_array().unwrap();
+        assert_eq!(warnings.len(), result.warnings.len());
+        assert!(warnings[0].as_str().unwrap().contains("bike_index"));
+    }
+
+    #[test]
+    fn test_write_file_gz() {
+        use flate2::read::GzDecoder;
+
+        let sums = DMatrix::from_vec(2, 4, vec![1.0, 2.0, 1.5, 2.5, 0.5, 0.5, 0.5, 0.25]);
+        let plain = "/tmp/test_write_file_gz.txt";
+        let gz = "/tmp/test_write_file_gz.txt.gz";
+        write_file(&sums, plain).unwrap();
+        write_file(&sums, gz).unwrap();
+
+        let bytes = std::fs::read(gz).unwrap();
+        assert_eq!(
+            &bytes[..2],
+            &[0x1f, 0x8b],
+            "output should be gzip-compressed"
+        );
+        let mut decompressed = String::new();
+        GzDecoder::new(&bytes[..])
+            .read_to_string(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, std::fs::read_to_string(plain).unwrap());
+    }
+
+    #[test]
+    fn test_write_file_wide() {
+        let filename = "/tmp/test_write_file_wide.txt";
+        let columns = vec![
+            ("bike_index".to_string(), vec![0.25, -1.0, 3.0]),
+            ("social_index".to_string(), vec![2.0, 0.5, f64::NAN]),
+        ];
+        write_file_wide(&columns, filename).unwrap();
+
+        let contents = std::fs::read_to_string(filename).unwrap();
+        let mut lines = contents.lines();
+        let header: Vec<&str> = lines.next().unwrap().split(',').map(|x| x.trim()).collect();
+        assert_eq!(header, vec!["bike_index", "social_index"]);
+        let rows: Vec<&str> = lines.collect();
+        assert_eq!(rows, vec!["0.25,2", "-1,0.5", "3,NaN"]);
+
+        let bad = vec![
+            ("a".to_string(), vec![1.0, 2.0]),
+            ("b".to_string(), vec![1.0]),
+        ];
+        let result = write_file_wide(&bad, "/tmp/test_write_file_wide_bad.txt");
+        assert_eq!(
+            result,
+            Err(UaError::LengthMismatch {
+                name: "b".to_string(),
+                len: 1,
+                expected: 2
+            })
+        );
+        assert!(!std::path::Path::new("/tmp/test_write_file_wide_bad.txt").exists());
+    }
+
+    // Writer which fails once a given number of lines have been written.
+    struct FailingWriter {
+        lines_left: usize,
+    }
+
+    impl Write for FailingWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            if self.lines_left == 0 {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::WriteZero,
+                    "no space left",
+                ));
+            }
+            self.lines_left -= buf.iter().filter(|&&b| b == b'\n').count();
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_write_errors() {
+        let sums = DMatrix::from_vec(4, 1, vec![1.0, 4.5, 3.0, 2.0]);
+        let writer = FailingWriter { lines_left: 2 };
+        let err = UaError::from(write_to(writer, &sums).unwrap_err());
+        assert_eq!(err, UaError::Io("no space left".to_string()));
+
+        let result = write_file(&sums, "/nonexistent_directory/out.txt");
+        assert!(matches!(result, Err(UaError::Io(_))));
+    }
+
+    #[test]
+    fn test_write_to() {
+        let sums = DMatrix::from_vec(2, 4, vec![1.0, 2.0, 1.5, 2.5, 0.5, 0.5, 0.5, 0.25]);
+        let mut buffer: Vec<u8> = Vec::new();
+        write_to(&mut buffer, &sums).unwrap();
+
+        let expected_contents = "\
+            original, transformed, absolute, relative\n\
+            1,1.5,0.5,0.5\n\
+            2,2.5,0.5,0.25\n";
+        assert_eq!(String::from_utf8(buffer).unwrap(), expected_contents);
+    }
 }
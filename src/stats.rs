@@ -0,0 +1,1069 @@
+use nalgebra::{DMatrix, DVector, RowDVector};
+
+use crate::calculate_dists::{get_ordering_index, multidim_distance, Metric};
+use crate::error::UaError;
+use crate::utils;
+
+/// Statistics comparing the distributions of one variable in two cities, as returned from
+/// `compare_distributions`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DistComparison {
+    /// Spearman rank correlation between paired values of the two cities.
+    pub spearman: f64,
+    /// Two-sample Kolmogorov-Smirnov statistic, as the maximal difference between the empirical
+    /// cumulative distributions of the two cities.
+    pub ks: f64,
+    /// Mean of the values of the first city.
+    pub mean1: f64,
+    /// Standard deviation of the values of the first city.
+    pub sd1: f64,
+    /// Mean of the values of the second city.
+    pub mean2: f64,
+    /// Standard deviation of the values of the second city.
+    pub sd2: f64,
+}
+
+/// Statistics quantifying differences between two mutation outputs, as returned from
+/// `diff_outputs`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OutputDiff {
+    /// Maximal absolute difference between paired values.
+    pub max_abs_diff: f64,
+    /// Mean absolute difference between paired values.
+    pub mean_abs_diff: f64,
+    /// Pearson correlation between paired values, which is `NaN` if either output is constant.
+    pub correlation: f64,
+    /// Zero-based index of the values with the maximal absolute difference. For columns of
+    /// `uamutate` results, this is one less than the group number.
+    pub max_index: usize,
+}
+
+/// Online accumulator of the mean and variance of a stream of values, using Welford's algorithm.
+///
+/// Values are added one at a time, so statistics can be calculated in a single pass without
+/// holding all values in memory, and without the loss of precision of accumulating sums of
+/// squares.
+///
+/// # Example
+///
+/// ```
+/// use uamutations::stats::MeanVar;
+/// let mut acc = MeanVar::new();
+/// for x in [1.0, 2.0, 3.0, 4.0, 5.0] {
+///     acc.push(x);
+/// }
+/// assert_eq!(acc.count(), 5);
+/// assert_eq!(acc.mean(), 3.0);
+/// assert_eq!(acc.variance(), 2.5);
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MeanVar {
+    count: usize,
+    mean: f64,
+    m2: f64,
+}
+
+impl MeanVar {
+    /// Create an empty accumulator.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add one value.
+    pub fn push(&mut self, x: f64) {
+        self.count += 1;
+        let delta = x - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (x - self.mean);
+    }
+
+    /// Number of values added.
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Mean of all values added, or `NaN` if none have been added.
+    pub fn mean(&self) -> f64 {
+        if self.count == 0 {
+            f64::NAN
+        } else {
+            self.mean
+        }
+    }
+
+    /// Sample variance (with denominator `n - 1`) of all values added, or `NaN` if fewer than two
+    /// have been added.
+    pub fn variance(&self) -> f64 {
+        if self.count < 2 {
+            f64::NAN
+        } else {
+            self.m2 / (self.count - 1) as f64
+        }
+    }
+
+    /// Sample standard deviation of all values added, or `NaN` if fewer than two have been added.
+    pub fn sd(&self) -> f64 {
+        self.variance().sqrt()
+    }
+}
+
+/// Distribution of matching distances, as returned from `matching_coverage`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MatchingCoverage {
+    /// Mean absolute matching distance.
+    pub mean: f64,
+    /// Maximal absolute matching distance.
+    pub max: f64,
+    /// 95th percentile of absolute matching distances.
+    pub p95: f64,
+    /// Median distance from each distinct target point to its nearest distinct neighbouring
+    /// target point, or `NaN` if there are fewer than two distinct target points.
+    pub nn_distance: f64,
+    /// True if `mean` exceeds the specified multiple of `nn_distance`, indicating that the values
+    /// to be mutated are poorly covered by the target distribution, and so that mutations may be
+    /// unreliable.
+    pub warning: bool,
+}
+
+/// Summarise the distribution of matching distances, and whether they indicate that values to be
+/// mutated are poorly covered by the target distribution.
+///
+/// Where the distributions of values and targets barely overlap, matching still produces
+/// results, but with distances far greater than the spacing between target values. That spacing
+/// is measured between distinct target points only, so that targets with many tied values, such
+/// as commonly arise from discretisation, do not have a spacing of zero. Distances between target
+/// points are calculated with `calculate_dists::multidim_distance`, so that coverage may be
+/// assessed either for the single variable on which entries are matched, or in the full space of
+/// all variables.
+///
+/// # Arguments
+///
+/// * `dists` - Absolute matching distances, either in one dimension, such as the first column
+///   returned from `calculate_dists::calculate_dists`, or between whole rows of matched entries,
+///   as returned from `calculate_dists::multidim_distance`. `NaN` values are ignored.
+/// * `targets` - Target points against which values were matched, with one row for each point,
+///   and one column for each dimension in which `dists` were calculated, on the same scale as
+///   `dists`. Points with any `NaN` or infinite values are ignored.
+/// * `multiple` - Multiple of the typical nearest-neighbour distance between target points above
+///   which the mean matching distance triggers a warning.
+///
+/// # Panics
+///
+/// This function will panic if `dists` is empty, or if `targets` has fewer than two rows.
+///
+/// # Example
+///
+/// ```
+/// use nalgebra::DMatrix;
+/// use uamutations::stats::matching_coverage;
+/// let targets = DMatrix::from_vec(6, 1, vec![10.0, 10.0, 11.0, 12.0, 12.0, 13.0]);
+/// let coverage = matching_coverage(&[9.0, 9.0, 9.0, 9.0], &targets, 5.0);
+/// assert_eq!(coverage.nn_distance, 1.0);
+/// assert!(coverage.warning);
+/// ```
+pub fn matching_coverage(dists: &[f64], targets: &DMatrix<f64>, multiple: f64) -> MatchingCoverage {
+    assert!(!dists.is_empty(), "dists must not be empty");
+    assert!(targets.nrows() >= 2, "targets must have at least two rows");
+
+    let abs_dists: Vec<f64> = dists.iter().map(|d| d.abs()).collect();
+    let finite_dists: Vec<f64> = abs_dists.iter().cloned().filter(|d| !d.is_nan()).collect();
+    let mean = finite_dists.iter().sum::<f64>() / finite_dists.len() as f64;
+    let max = finite_dists.iter().cloned().fold(f64::NAN, f64::max);
+    let p95 = quantile(&abs_dists, 0.95);
+
+    // Distinct target points, in lexicographic order:
+    let mut points: Vec<Vec<f64>> = targets
+        .row_iter()
+        .filter(|row| row.iter().all(|x| x.is_finite()))
+        .map(|row| row.iter().cloned().collect())
+        .collect();
+    points.sort_by(|a, b| {
+        a.iter()
+            .zip(b.iter())
+            .map(|(x, y)| x.total_cmp(y))
+            .find(|o| o.is_ne())
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+    points.dedup();
+
+    let nn: Vec<f64> = if targets.ncols() == 1 {
+        // In one dimension, nearest neighbours are adjacent values once sorted:
+        let gaps: Vec<f64> = points.windows(2).map(|w| w[1][0] - w[0][0]).collect();
+        (0..points.len())
+            .map(|i| {
+                let lo = if i > 0 { gaps[i - 1] } else { f64::INFINITY };
+                let hi = gaps.get(i).cloned().unwrap_or(f64::INFINITY);
+                lo.min(hi)
+            })
+            .collect()
+    } else {
+        let rows: Vec<RowDVector<f64>> = points
+            .iter()
+            .map(|p| RowDVector::from_row_slice(p))
+            .collect();
+        rows.iter()
+            .enumerate()
+            .map(|(i, a)| {
+                rows.iter()
+                    .enumerate()
+                    .filter(|&(j, _)| j != i)
+                    .map(|(_, b)| multidim_distance(a, b, Metric::Euclidean, None))
+                    .fold(f64::INFINITY, f64::min)
+            })
+            .collect()
+    };
+    let nn: Vec<f64> = nn.into_iter().filter(|d| d.is_finite()).collect();
+    let nn_distance = if nn.is_empty() {
+        f64::NAN
+    } else {
+        quantile(&nn, 0.5)
+    };
+
+    MatchingCoverage {
+        mean,
+        max,
+        p95,
+        nn_distance,
+        warning: mean > multiple * nn_distance,
+    }
+}
+
+/// Measure how sensitive greedy matching is to the extreme from which it starts, by comparing
+/// distances from matching upwards from the lowest values with those from matching downwards from
+/// the highest values.
+///
+/// The default `GreedyMatcher` pairs values rank by rank, for which both directions give
+/// identical pairs except among tied values, which may be matched to different targets depending
+/// on direction. Results are then zero for data without ties, and increase with the extent to
+/// which ties make matching arbitrary.
+///
+/// # Arguments
+///
+/// * `values1` - Values to be mutated.
+/// * `values2` - Target values, with the same length as `values1`.
+///
+/// # Errors
+///
+/// Returns `UaError::EmptyInput` if `values1` is empty.
+///
+/// # Panics
+///
+/// This function will panic if `values1` and `values2` have different lengths.
+///
+/// # Returns
+///
+/// The sum of absolute differences between the distances from each direction, divided by the sum
+/// of absolute distances from both directions. This lies between 0, for identical distances, and
+/// 1, and is 0 if all distances are zero.
+///
+/// # Example
+///
+/// ```
+/// use uamutations::stats::matching_stability;
+/// assert_eq!(matching_stability(&[1.0, 2.0, 3.0], &[2.0, 5.0, 3.0]).unwrap(), 0.0);
+/// // Tied values of 1 are matched to (2, 4) upwards, and to (4, 2) downwards:
+/// let stability = matching_stability(&[1.0, 1.0], &[2.0, 4.0]).unwrap();
+/// assert_eq!(stability, 0.5);
+/// ```
+pub fn matching_stability(values1: &[f64], values2: &[f64]) -> Result<f64, UaError> {
+    if values1.is_empty() {
+        return Err(UaError::EmptyInput("values1".to_string()));
+    }
+    assert_eq!(
+        values1.len(),
+        values2.len(),
+        "values1 and values2 must have the same length."
+    );
+
+    let dists_from = |desc: bool| -> Vec<f64> {
+        let order1 = get_ordering_index(values1, desc, false);
+        let order2 = get_ordering_index(values2, desc, false);
+        order1
+            .index_reorder
+            .iter()
+            .enumerate()
+            .map(|(i, &rank)| values2[order2.index_sort[rank]] - values1[i])
+            .collect()
+    };
+    let lowest = dists_from(false);
+    let highest = dists_from(true);
+
+    let diff: f64 = lowest
+        .iter()
+        .zip(highest.iter())
+        .map(|(a, b)| (a - b).abs())
+        .sum();
+    let total: f64 = lowest.iter().chain(highest.iter()).map(|d| d.abs()).sum();
+
+    Ok(if total > 0.0 { diff / total } else { 0.0 })
+}
+
+/// Jensen-Shannon divergence between the distributions of two sets of values, as a measure of how
+/// different they are which is independent of any matching.
+///
+/// Values are binned into histograms with `nbins` bins of equal width spanning the combined range
+/// of both sets of values. Every bin has `EPS` added to its proportion of values, so that empty
+/// bins do not give infinite logarithms, and proportions are then renormalised.
+///
+/// # Arguments
+///
+/// * `values1` - The first set of values.
+/// * `values2` - The second set of values, which need not have the same length as `values1`.
+/// * `nbins` - Number of histogram bins.
+///
+/// # Panics
+///
+/// This function will panic if either `values1` or `values2` is empty, or if `nbins` is zero.
+///
+/// # Returns
+///
+/// The divergence in bits, between 0 for identical histograms and 1 for histograms with no
+/// overlap.
+///
+/// # Example
+///
+/// ```
+/// use uamutations::stats::js_divergence;
+/// let values = vec![1.0, 2.0, 3.0, 4.0];
+/// assert_eq!(js_divergence(&values, &values, 4), 0.0);
+/// let disjoint = js_divergence(&[1.0, 1.5], &[3.5, 4.0], 2);
+/// assert!((disjoint - 1.0).abs() < 1e-6);
+/// ```
+pub fn js_divergence(values1: &[f64], values2: &[f64], nbins: usize) -> f64 {
+    assert!(!values1.is_empty(), "values1 must not be empty");
+    assert!(!values2.is_empty(), "values2 must not be empty");
+    assert!(nbins > 0, "nbins must be greater than zero");
+
+    let all = values1.iter().chain(values2.iter());
+    let min = all.clone().cloned().fold(f64::INFINITY, f64::min);
+    let max = all.cloned().fold(f64::NEG_INFINITY, f64::max);
+    let width = (max - min) / nbins as f64;
+
+    let histogram = |values: &[f64]| -> Vec<f64> {
+        let mut counts = vec![0.0; nbins];
+        for &v in values {
+            let bin = if width > 0.0 {
+                (((v - min) / width) as usize).min(nbins - 1)
+            } else {
+                0
+            };
+            counts[bin] += 1.0;
+        }
+        let n = values.len() as f64;
+        let smoothed: Vec<f64> = counts.iter().map(|c| c / n + crate::EPS).collect();
+        let total: f64 = smoothed.iter().sum();
+        smoothed.iter().map(|p| p / total).collect()
+    };
+    let p = histogram(values1);
+    let q = histogram(values2);
+
+    let kl_to_mean = |a: &[f64], b: &[f64]| -> f64 {
+        a.iter()
+            .zip(b.iter())
+            .map(|(&x, &y)| x * (2.0 * x / (x + y)).log2())
+            .sum()
+    };
+
+    0.5 * kl_to_mean(&p, &q) + 0.5 * kl_to_mean(&q, &p)
+}
+
+/// Compare the distributions of one variable in two cities, as a quick diagnostic of how similar
+/// they already are prior to mutation.
+///
+/// Pairs in which either value is `NaN` or infinite, such as those read from missing values, are
+/// ignored.
+///
+/// # Arguments
+///
+/// * `values1` - Values of the variable in the first city.
+/// * `values2` - Values of the variable in the second city, with the same length as `values1`.
+///
+/// # Errors
+///
+/// Returns `UaError::EmptyInput` if `values1` is empty, or if no pairs have finite values in
+/// both.
+///
+/// # Panics
+///
+/// This function will panic if `values1` and `values2` have different lengths.
+///
+/// # Returns
+///
+/// A `DistComparison` object.
+///
+/// # Example
+///
+/// ```
+/// use uamutations::stats::compare_distributions;
+/// let values1 = vec![1.0, 2.0, 4.0, 5.0];
+/// let values2 = vec![7.0, 9.0, 3.0, 2.0];
+/// let result = compare_distributions(&values1, &values2).unwrap();
+/// assert!((result.spearman + 0.8).abs() < 1e-10);
+/// assert_eq!(result.ks, 0.5);
+/// ```
+pub fn compare_distributions(values1: &[f64], values2: &[f64]) -> Result<DistComparison, UaError> {
+    if values1.is_empty() {
+        return Err(UaError::EmptyInput("values1".to_string()));
+    }
+    assert_eq!(
+        values1.len(),
+        values2.len(),
+        "values1 and values2 must have the same length."
+    );
+
+    let (values1, values2): (Vec<f64>, Vec<f64>) = values1
+        .iter()
+        .zip(values2.iter())
+        .filter(|(a, b)| a.is_finite() && b.is_finite())
+        .unzip();
+    if values1.is_empty() {
+        return Err(UaError::EmptyInput("values1".to_string()));
+    }
+    let (values1, values2) = (values1.as_slice(), values2.as_slice());
+
+    let ranks1 = ranks(values1);
+    let ranks2 = ranks(values2);
+
+    let (mean1, sd1) = utils::mean_sd_column(&DVector::from_column_slice(values1))?;
+    let (mean2, sd2) = utils::mean_sd_column(&DVector::from_column_slice(values2))?;
+
+    Ok(DistComparison {
+        spearman: pearson(&ranks1, &ranks2),
+        ks: ks_statistic(values1, values2),
+        mean1,
+        sd1,
+        mean2,
+        sd2,
+    })
+}
+
+/// Quantify the differences between two mutation outputs, such as the same column of `uamutate`
+/// results calculated with different parameters.
+///
+/// Pairs in which either value is `NaN`, such as groups with no complete entries, are ignored.
+///
+/// # Arguments
+///
+/// * `a` - Values of the first output.
+/// * `b` - Values of the second output, with the same length as `a`.
+///
+/// # Errors
+///
+/// Returns `UaError::EmptyInput` if there are no pairs of values which are both not `NaN`.
+///
+/// # Panics
+///
+/// This function will panic if `a` and `b` have different lengths.
+///
+/// # Returns
+///
+/// An `OutputDiff` object.
+///
+/// # Example
+///
+/// ```
+/// use uamutations::stats::diff_outputs;
+/// let a = vec![1.0, 2.0, 3.0, 4.0];
+/// let b = vec![1.1, 2.0, 2.8, 4.4];
+/// let diff = diff_outputs(&a, &b).unwrap();
+/// assert_eq!(diff.max_index, 3);
+/// assert!((diff.mean_abs_diff - 0.175).abs() < 1e-10);
+/// ```
+pub fn diff_outputs(a: &[f64], b: &[f64]) -> Result<OutputDiff, UaError> {
+    assert_eq!(a.len(), b.len(), "a and b must have the same length.");
+
+    let pairs: Vec<(usize, f64, f64)> = a
+        .iter()
+        .zip(b)
+        .enumerate()
+        .filter(|(_, (x, y))| !x.is_nan() && !y.is_nan())
+        .map(|(i, (&x, &y))| (i, x, y))
+        .collect();
+    if pairs.is_empty() {
+        return Err(UaError::EmptyInput("a".to_string()));
+    }
+
+    let (max_index, max_abs_diff) =
+        pairs
+            .iter()
+            .map(|&(i, x, y)| (i, (y - x).abs()))
+            .fold(
+                (0, f64::NEG_INFINITY),
+                |max, d| if d.1 > max.1 { d } else { max },
+            );
+    let mean_abs_diff =
+        pairs.iter().map(|&(_, x, y)| (y - x).abs()).sum::<f64>() / pairs.len() as f64;
+    let (x, y): (Vec<f64>, Vec<f64>) = pairs.iter().map(|&(_, x, y)| (x, y)).unzip();
+
+    Ok(OutputDiff {
+        max_abs_diff,
+        mean_abs_diff,
+        correlation: pearson(&x, &y),
+        max_index,
+    })
+}
+
+/// Calculate a quantile of a vector of values, using linear interpolation between order statistics.
+///
+/// # Arguments
+///
+/// * `values` - Values from which quantile is to be calculated. These need not be sorted. `NaN`
+///   values are ignored.
+/// * `p` - Probability of the quantile, between 0 and 1.
+///
+/// # Returns
+///
+/// The quantile of the non-`NaN` entries of `values`, or `NaN` if all entries are `NaN`.
+///
+/// # Panics
+///
+/// This function will panic if `values` is empty, or if `p` is not between 0 and 1.
+///
+/// # Example
+///
+/// ```
+/// use uamutations::stats::quantile;
+/// let values = vec![4.0, 1.0, 3.0, 2.0];
+/// assert_eq!(quantile(&values, 0.5), 2.5);
+/// assert_eq!(quantile(&values, 0.25), 1.75);
+/// // NaN values are ignored:
+/// assert_eq!(quantile(&[4.0, f64::NAN, 1.0, 3.0, 2.0], 0.5), 2.5);
+/// ```
+pub fn quantile(values: &[f64], p: f64) -> f64 {
+    assert!(!values.is_empty(), "values must not be empty");
+    assert!((0.0..=1.0).contains(&p), "p must be between 0 and 1");
+
+    let mut sorted: Vec<f64> = values.iter().cloned().filter(|v| !v.is_nan()).collect();
+    if sorted.is_empty() {
+        return f64::NAN;
+    }
+    sorted.sort_by(|a, b| a.total_cmp(b));
+
+    let pos = p * (sorted.len() - 1) as f64;
+    let lo = pos.floor() as usize;
+    let hi = pos.ceil() as usize;
+    sorted[lo] + (pos - lo as f64) * (sorted[hi] - sorted[lo])
+}
+
+/// Calculate the ranks of a vector of values, with tied values all given the average of the ranks
+/// they would otherwise occupy.
+///
+/// # Arguments
+///
+/// * `vals` - Values to be ranked. These need not be sorted, and may contain `NaN`.
+///
+/// # Returns
+///
+/// A vector of the same length as `vals`, with the one-based rank of each value, so that the
+/// lowest value has rank 1 and the highest rank equal to the number of non-`NaN` values. `NaN`
+/// values are excluded from ranking, and have ranks of `NaN`.
+///
+/// # Example
+///
+/// ```
+/// use uamutations::stats::ranks;
+/// let vals = vec![3.0, 1.0, 4.0, 1.0, 5.0];
+/// // The two values of 1.0 share ranks 1 and 2:
+/// assert_eq!(ranks(&vals), vec![3.0, 1.5, 4.0, 1.5, 5.0]);
+/// let r = ranks(&[3.0, f64::NAN, 1.0]);
+/// assert_eq!((r[0], r[2]), (2.0, 1.0));
+/// assert!(r[1].is_nan());
+/// ```
+pub fn ranks(vals: &[f64]) -> Vec<f64> {
+    let mut index_sort: Vec<usize> = (0..vals.len()).filter(|&i| !vals[i].is_nan()).collect();
+    index_sort.sort_by(|&a, &b| vals[a].total_cmp(&vals[b]));
+
+    let mut result = vec![f64::NAN; vals.len()];
+    let mut start = 0;
+    while start < index_sort.len() {
+        let mut end = start + 1;
+        while end < index_sort.len() && vals[index_sort[end]] == vals[index_sort[start]] {
+            end += 1;
+        }
+        // Positions `start..end` hold tied values, with one-based ranks of `start + 1` to `end`:
+        let rank = (start + 1 + end) as f64 / 2.0;
+        for &i in &index_sort[start..end] {
+            result[i] = rank;
+        }
+        start = end;
+    }
+
+    result
+}
+
+/// Quantile function (inverse cumulative distribution function) of the standard normal
+/// distribution.
+///
+/// Uses the rational approximation of Acklam, with a relative error of less than 1.2e-9.
+///
+/// # Arguments
+///
+/// * `p` - Probability, strictly between 0 and 1.
+///
+/// # Panics
+///
+/// This function will panic if `p` is not strictly between 0 and 1.
+///
+/// # Example
+///
+/// ```
+/// use uamutations::stats::normal_quantile;
+/// assert_eq!(normal_quantile(0.5), 0.0);
+/// assert!((normal_quantile(0.975) - 1.959964).abs() < 1e-6);
+/// assert!((normal_quantile(0.025) + 1.959964).abs() < 1e-6);
+/// ```
+pub fn normal_quantile(p: f64) -> f64 {
+    assert!(p > 0.0 && p < 1.0, "p must be strictly between 0 and 1");
+
+    const A: [f64; 6] = [
+        -3.969683028665376e1,
+        2.209460984245205e2,
+        -2.759285104469687e2,
+        1.38357751867269e2,
+        -3.066479806614716e1,
+        2.506628277459239,
+    ];
+    const B: [f64; 5] = [
+        -5.447609879822406e1,
+        1.615858368580409e2,
+        -1.556989798598866e2,
+        6.680131188771972e1,
+        -1.328068155288572e1,
+    ];
+    const C: [f64; 6] = [
+        -7.784894002430293e-3,
+        -3.223964580411365e-1,
+        -2.400758277161838,
+        -2.549732539343734,
+        4.374664141464968,
+        2.938163982698783,
+    ];
+    const D: [f64; 4] = [
+        7.784695709041462e-3,
+        3.224671290700398e-1,
+        2.445134137142996,
+        3.754408661907416,
+    ];
+    const P_LOW: f64 = 0.02425;
+
+    // Tails are symmetric, so upper tail is calculated as the negative of the lower tail:
+    let tail = |p: f64| {
+        let q = (-2.0 * p.ln()).sqrt();
+        (((((C[0] * q + C[1]) * q + C[2]) * q + C[3]) * q + C[4]) * q + C[5])
+            / ((((D[0] * q + D[1]) * q + D[2]) * q + D[3]) * q + 1.0)
+    };
+
+    if p < P_LOW {
+        tail(p)
+    } else if p > 1.0 - P_LOW {
+        -tail(1.0 - p)
+    } else {
+        let q = p - 0.5;
+        let r = q * q;
+        (((((A[0] * r + A[1]) * r + A[2]) * r + A[3]) * r + A[4]) * r + A[5]) * q
+            / (((((B[0] * r + B[1]) * r + B[2]) * r + B[3]) * r + B[4]) * r + 1.0)
+    }
+}
+
+/// Calculate the covariance matrix of the observations of two arrays combined.
+///
+/// Both arrays are treated as one set of observations of the same variables, so that the result
+/// is the sample covariance (with denominator `n - 1`) of all rows of `values1` and `values2`
+/// together, about the means of those combined rows.
+///
+/// # Arguments
+///
+/// * `values1` - Matrix of [observations, variables].
+/// * `values2` - Matrix of [observations, variables], with the same variables as `values1`.
+///
+/// # Panics
+///
+/// This function will panic if `values1` and `values2` have different numbers of columns, or if
+/// they have fewer than two observations in total.
+///
+/// # Returns
+///
+/// A symmetric matrix of [variables, variables].
+///
+/// # Example
+///
+/// ```
+/// use nalgebra::DMatrix;
+/// use uamutations::stats::pooled_covariance;
+/// let values1 = DMatrix::from_vec(2, 1, vec![1.0, 2.0]);
+/// let values2 = DMatrix::from_vec(1, 1, vec![3.0]);
+/// assert_eq!(pooled_covariance(&values1, &values2)[(0, 0)], 1.0);
+/// ```
+pub fn pooled_covariance(values1: &DMatrix<f64>, values2: &DMatrix<f64>) -> DMatrix<f64> {
+    assert_eq!(
+        values1.ncols(),
+        values2.ncols(),
+        "values1 and values2 must have the same number of columns."
+    );
+    let nobs = values1.nrows() + values2.nrows();
+    assert!(
+        nobs >= 2,
+        "values1 and values2 must have at least two observations in total"
+    );
+
+    let ncols = values1.ncols();
+    let means: Vec<f64> = (0..ncols)
+        .map(|j| (values1.column(j).sum() + values2.column(j).sum()) / nobs as f64)
+        .collect();
+    let centred = DMatrix::from_fn(nobs, ncols, |i, j| {
+        if i < values1.nrows() {
+            values1[(i, j)] - means[j]
+        } else {
+            values2[(i - values1.nrows(), j)] - means[j]
+        }
+    });
+
+    centred.transpose() * centred / (nobs - 1) as f64
+}
+
+/// Pearson correlation coefficient between two vectors of equal length.
+fn pearson(x: &[f64], y: &[f64]) -> f64 {
+    let n = x.len() as f64;
+    let mean_x: f64 = x.iter().sum::<f64>() / n;
+    let mean_y: f64 = y.iter().sum::<f64>() / n;
+
+    let mut cov = 0.0;
+    let mut var_x = 0.0;
+    let mut var_y = 0.0;
+    for (&a, &b) in x.iter().zip(y.iter()) {
+        cov += (a - mean_x) * (b - mean_y);
+        var_x += (a - mean_x).powi(2);
+        var_y += (b - mean_y).powi(2);
+    }
+
+    cov / (var_x * var_y).sqrt()
+}
+
+/// Two-sample Kolmogorov-Smirnov statistic between two vectors.
+fn ks_statistic(values1: &[f64], values2: &[f64]) -> f64 {
+    let mut sorted1 = values1.to_vec();
+    sorted1.sort_by(|a, b| a.total_cmp(b));
+    let mut sorted2 = values2.to_vec();
+    sorted2.sort_by(|a, b| a.total_cmp(b));
+
+    let n1 = sorted1.len() as f64;
+    let n2 = sorted2.len() as f64;
+    let (mut i, mut j) = (0, 0);
+    let mut ks: f64 = 0.0;
+    while i < sorted1.len() && j < sorted2.len() {
+        let x = sorted1[i].min(sorted2[j]);
+        while i < sorted1.len() && sorted1[i] <= x {
+            i += 1;
+        }
+        while j < sorted2.len() && sorted2[j] <= x {
+            j += 1;
+        }
+        ks = ks.max((i as f64 / n1 - j as f64 / n2).abs());
+    }
+
+    ks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_abs_diff_eq;
+
+    #[test]
+    fn test_matching_stability() {
+        let varnames = vec!["bike_index".to_string()];
+        let read = |f: &str| {
+            crate::read_write_file::readfile(f, &varnames, 1000)
+                .unwrap()
+                .values
+                .column(0)
+                .iter()
+                .cloned()
+                .collect::<Vec<f64>>()
+        };
+        let values1 = read("./test_resources/dat1.json");
+        let values2 = read("./test_resources/dat2.json");
+
+        let stability = matching_stability(&values1, &values2).unwrap();
+        assert!((0.0..=1.0).contains(&stability));
+
+        // Breaking all ties removes any dependence on direction:
+        let untied: Vec<f64> = values1
+            .iter()
+            .enumerate()
+            .map(|(i, v)| v + i as f64 * 1e-9)
+            .collect();
+        assert_eq!(matching_stability(&untied, &values2).unwrap(), 0.0);
+
+        assert_eq!(
+            matching_stability(&[], &[]),
+            Err(UaError::EmptyInput("values1".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_js_divergence() {
+        let values1: Vec<f64> = (0..100).map(|i| (i as f64 / 10.0).sin()).collect();
+        assert_eq!(js_divergence(&values1, &values1, 10), 0.0);
+        // Order of values does not matter:
+        let mut reversed = values1.clone();
+        reversed.reverse();
+        assert_eq!(js_divergence(&values1, &reversed, 10), 0.0);
+
+        // Divergence is symmetric, and increases as distributions separate:
+        let shifted = |d: f64| -> Vec<f64> { values1.iter().map(|v| v + d).collect() };
+        let near = js_divergence(&values1, &shifted(0.5), 20);
+        assert_abs_diff_eq!(
+            near,
+            js_divergence(&shifted(0.5), &values1, 20),
+            epsilon = 1e-12
+        );
+        let far = js_divergence(&values1, &shifted(1.5), 20);
+        assert!(0.0 < near && near < far && far < 1.0);
+
+        // Disjoint inputs approach the upper bound of 1:
+        let disjoint = js_divergence(&values1, &shifted(10.0), 20);
+        assert!(disjoint < 1.0);
+        assert_abs_diff_eq!(disjoint, 1.0, epsilon = 1e-6);
+    }
+
+    #[test]
+    fn test_compare_distributions() {
+        let values1 = vec![1.0, 2.0, 4.0, 5.0];
+        let values2 = vec![7.0, 9.0, 3.0, 2.0];
+        let result = compare_distributions(&values1, &values2).unwrap();
+
+        // Ranks are (0, 1, 2, 3) and (2, 3, 1, 0), so rho = 1 - 6 * 18 / (4 * 15):
+        assert_abs_diff_eq!(result.spearman, -0.8, epsilon = 1e-10);
+        // Maximal difference in ECDFs is at x = 5, with (1.0 - 0.5):
+        assert_abs_diff_eq!(result.ks, 0.5, epsilon = 1e-10);
+        assert_abs_diff_eq!(result.mean1, 3.0, epsilon = 1e-10);
+        assert_abs_diff_eq!(result.mean2, 5.25, epsilon = 1e-10);
+        assert_abs_diff_eq!(result.sd1, (10.0f64 / 3.0).sqrt(), epsilon = 1e-10);
+    }
+
+    #[test]
+    fn test_compare_distributions_nan() {
+        let values1 = vec![1.0, f64::NAN, 2.0, 4.0, 5.0];
+        let values2 = vec![7.0, 1.0, 9.0, f64::INFINITY, 3.0];
+        let result = compare_distributions(&values1, &values2).unwrap();
+        let expected = compare_distributions(&[1.0, 2.0, 5.0], &[7.0, 9.0, 3.0]).unwrap();
+        assert_eq!(result, expected);
+
+        assert_eq!(
+            compare_distributions(&[f64::NAN, 1.0], &[1.0, f64::NAN]),
+            Err(UaError::EmptyInput("values1".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_diff_outputs() {
+        let a = vec![1.0, 2.0, 3.0, f64::NAN, 4.0];
+        let b = vec![1.1, 2.0, 2.8, 1.0, 4.4];
+        let diff = diff_outputs(&a, &b).unwrap();
+
+        // Absolute differences are (0.1, 0, 0.2, -, 0.4), with the `NaN` pair ignored:
+        assert_abs_diff_eq!(diff.max_abs_diff, 0.4, epsilon = 1e-10);
+        assert_eq!(diff.max_index, 4);
+        assert_abs_diff_eq!(diff.mean_abs_diff, 0.175, epsilon = 1e-10);
+        // Deviations from means of 2.5 and 2.575 give a covariance sum of 5.35, and sums of
+        // squares of 5 and 5.8875:
+        let expected = 5.35 / (5.0f64 * 5.8875).sqrt();
+        assert_abs_diff_eq!(diff.correlation, expected, epsilon = 1e-10);
+
+        let same = diff_outputs(&b, &b).unwrap();
+        assert_eq!(same.max_abs_diff, 0.0);
+        assert_abs_diff_eq!(same.correlation, 1.0, epsilon = 1e-10);
+        assert_eq!(
+            diff_outputs(&[f64::NAN], &[1.0]),
+            Err(UaError::EmptyInput("a".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_compare_distributions_identical() {
+        let values = vec![3.0, 1.0, 2.0, 5.0];
+        let result = compare_distributions(&values, &values).unwrap();
+        assert_abs_diff_eq!(result.spearman, 1.0, epsilon = 1e-10);
+        assert_eq!(result.ks, 0.0);
+    }
+
+    #[test]
+    fn test_pooled_covariance() {
+        // Combined observations are (1, 2), (2, 1), (3, 6), (6, 3), with means of (3, 3):
+        let values1 = DMatrix::from_row_slice(2, 2, &[1.0, 2.0, 2.0, 1.0]);
+        let values2 = DMatrix::from_row_slice(2, 2, &[3.0, 6.0, 6.0, 3.0]);
+        let cov = pooled_covariance(&values1, &values2);
+
+        // Deviations are (-2, -1), (-1, -2), (0, 3), (3, 0), giving sums of squares of 14 and
+        // cross-products of 2 + 2 + 0 + 0 = 4, all divided by n - 1 = 3:
+        let expected =
+            DMatrix::from_row_slice(2, 2, &[14.0 / 3.0, 4.0 / 3.0, 4.0 / 3.0, 14.0 / 3.0]);
+        for (a, b) in cov.iter().zip(expected.iter()) {
+            assert_abs_diff_eq!(a, b, epsilon = 1e-10);
+        }
+
+        // Diagonal holds pooled variances, as for a single column of all observations:
+        let all = DVector::from_vec(vec![1.0, 2.0, 3.0, 6.0]);
+        let (_, sd) = utils::mean_sd_column(&all).unwrap();
+        assert_abs_diff_eq!(cov[(0, 0)], sd.powi(2), epsilon = 1e-10);
+    }
+
+    #[test]
+    fn test_ranks_no_ties() {
+        let vals = vec![0.5, -2.0, 3.0, 1.0, -4.0];
+        assert_eq!(ranks(&vals), vec![3.0, 2.0, 5.0, 4.0, 1.0]);
+        assert!(ranks(&[]).is_empty());
+        assert_eq!(ranks(&[7.0]), vec![1.0]);
+    }
+
+    #[test]
+    fn test_ranks_ties() {
+        // All values tied share the average rank of (1 + n) / 2:
+        assert_eq!(ranks(&[2.0; 4]), vec![2.5; 4]);
+
+        let vals = vec![2.0, 1.0, 2.0, 3.0, 2.0, 1.0];
+        let result = ranks(&vals);
+        assert_eq!(result, vec![4.0, 1.5, 4.0, 6.0, 4.0, 1.5]);
+        // Sum of ranks is unchanged by ties:
+        assert_eq!(result.iter().sum::<f64>(), 21.0);
+    }
+
+    #[test]
+    fn test_ranks_quantile_nan() {
+        let vals = vec![2.0, f64::NAN, 1.0, 2.0, f64::NAN];
+        let result = ranks(&vals);
+        assert_eq!((result[0], result[2], result[3]), (2.5, 1.0, 2.5));
+        assert!(result[1].is_nan() && result[4].is_nan());
+        assert!(ranks(&[f64::NAN; 3]).iter().all(|r| r.is_nan()));
+
+        assert_eq!(quantile(&vals, 0.0), 1.0);
+        assert_eq!(quantile(&vals, 1.0), 2.0);
+        assert!(quantile(&[f64::NAN; 2], 0.5).is_nan());
+    }
+
+    #[test]
+    fn test_normal_quantile() {
+        assert_eq!(normal_quantile(0.5), 0.0);
+        // Reference values from R's `qnorm`, in both tails and the central region:
+        let refs = [
+            (0.001, -3.090232306167813),
+            (0.01, -2.326347874040841),
+            (0.1, -1.281551565544601),
+            (0.3, -0.524400512708041),
+        ];
+        for (p, x) in refs {
+            assert_abs_diff_eq!(normal_quantile(p), x, epsilon = 1e-8);
+            assert_abs_diff_eq!(normal_quantile(1.0 - p), -x, epsilon = 1e-8);
+        }
+    }
+
+    #[test]
+    fn test_matching_coverage() {
+        use crate::calculate_dists::calculate_dists;
+
+        let n = 50;
+        let values: Vec<f64> = (0..n).map(|i| i as f64 * 0.1).collect();
+        let overlapping: Vec<f64> = (0..n).map(|i| i as f64 * 0.1 + 0.05).collect();
+        let disjoint: Vec<f64> = (0..n).map(|i| 100.0 + i as f64 * 0.1).collect();
+        let values1 = DMatrix::from_vec(n, 1, values);
+
+        let coverage = |targets: &[f64]| {
+            let values2 = DMatrix::from_vec(n, 1, targets.to_vec());
+            let dists = calculate_dists(&values1, &values2, &false, 0).unwrap();
+            let dists: Vec<f64> = dists.column(0).iter().cloned().collect();
+            matching_coverage(&dists, &values2, 10.0)
+        };
+
+        let good = coverage(&overlapping);
+        assert_abs_diff_eq!(good.mean, 0.05, epsilon = 1e-10);
+        assert_abs_diff_eq!(good.nn_distance, 0.1, epsilon = 1e-10);
+        assert!(!good.warning);
+
+        let poor = coverage(&disjoint);
+        assert_abs_diff_eq!(poor.mean, 100.0, epsilon = 1e-8);
+        assert_abs_diff_eq!(poor.max, 100.0, epsilon = 1e-8);
+        assert_abs_diff_eq!(poor.p95, 100.0, epsilon = 1e-8);
+        assert!(poor.warning);
+    }
+
+    #[test]
+    fn test_matching_coverage_ties() {
+        use crate::calculate_dists::calculate_dists;
+        use crate::read_write_file::readfile;
+
+        // Over half of these values are tied, so the median gap between adjacent sorted values is
+        // zero, but the spacing between distinct values is not:
+        let varnames = vec!["bike_index".to_string()];
+        let targets = readfile("./test_resources/dat1.json", &varnames, 1000)
+            .unwrap()
+            .values;
+        let coverage = matching_coverage(&[0.0], &targets, 10.0);
+        assert!(coverage.nn_distance > 0.0);
+
+        // Targets of 0, ..., 9, each repeated 10 times, matched at distances well below their
+        // spacing, give no warning:
+        let n = 100;
+        let values2 = DMatrix::from_fn(n, 1, |i, _| (i / 10) as f64);
+        let values1 = values2.map(|x| x + 0.3);
+        let dists = calculate_dists(&values1, &values2, &false, 0).unwrap();
+        let dists: Vec<f64> = dists.column(0).iter().cloned().collect();
+        let coverage = matching_coverage(&dists, &values2, 10.0);
+        assert_eq!(coverage.nn_distance, 1.0);
+        assert_abs_diff_eq!(coverage.mean, 0.3, epsilon = 1e-10);
+        assert!(!coverage.warning);
+
+        // Targets with only one distinct value have no spacing, and give no warning:
+        let constant = DMatrix::from_element(10, 1, 1.0);
+        let coverage = matching_coverage(&[0.5; 10], &constant, 10.0);
+        assert!(coverage.nn_distance.is_nan());
+        assert!(!coverage.warning);
+
+        // Missing values are ignored:
+        let targets = DMatrix::from_vec(5, 1, vec![1.0, f64::NAN, 2.0, 2.0, 4.0]);
+        let coverage = matching_coverage(&[0.5, f64::NAN, 1.5], &targets, 10.0);
+        assert_eq!(coverage.mean, 1.0);
+        assert_eq!(coverage.max, 1.5);
+        assert_eq!(coverage.nn_distance, 1.0);
+
+        // Targets in several dimensions use distances between whole rows:
+        let targets = DMatrix::from_row_slice(4, 2, &[0.0, 0.0, 3.0, 4.0, 3.0, 4.0, 6.0, 8.0]);
+        let coverage = matching_coverage(&[100.0], &targets, 10.0);
+        assert_eq!(coverage.nn_distance, 5.0);
+        assert!(coverage.warning);
+    }
+
+    #[test]
+    fn test_mean_var() {
+        let values: Vec<f64> = (0..1000)
+            .map(|i| ((i * 7919) % 1000) as f64 * 0.01)
+            .collect();
+        let mut acc = MeanVar::new();
+        values.iter().for_each(|&x| acc.push(x));
+
+        let (mean, sd) = utils::mean_sd_column(&DVector::from_vec(values.clone())).unwrap();
+        assert_eq!(acc.count(), values.len());
+        assert_abs_diff_eq!(acc.mean(), mean, epsilon = 1e-10);
+        assert_abs_diff_eq!(acc.sd(), sd, epsilon = 1e-10);
+
+        // With a large offset, online statistics remain as accurate as a two-pass calculation:
+        let offset: Vec<f64> = values.iter().map(|x| x + 1.0e6).collect();
+        let mut acc_offset = MeanVar::new();
+        offset.iter().for_each(|&x| acc_offset.push(x));
+        let mean_offset = offset.iter().sum::<f64>() / 1000.0;
+        let var_offset: f64 = offset
+            .iter()
+            .map(|x| (x - mean_offset).powi(2))
+            .sum::<f64>()
+            / 999.0;
+        assert_abs_diff_eq!(acc_offset.mean(), mean_offset, epsilon = 1e-8);
+        assert_abs_diff_eq!(acc_offset.variance(), var_offset, epsilon = 1e-8);
+        assert_abs_diff_eq!(acc_offset.sd(), sd, epsilon = 1e-8);
+
+        let mut acc = MeanVar::new();
+        assert!(acc.mean().is_nan());
+        acc.push(1.0);
+        assert_eq!(acc.mean(), 1.0);
+        assert!(acc.variance().is_nan());
+    }
+}
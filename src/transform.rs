@@ -1,8 +1,31 @@
 use nalgebra::{DMatrix, DVector};
 use std::collections::HashMap;
 
-/// Transform input values according to specified schema for each input variable. Variables in the
-/// lookup table are inverted.
+use crate::stats;
+
+/// Transform applied to aggregated mutation values before they are returned.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputTransform {
+    /// Return raw mutation values.
+    #[default]
+    Raw,
+    /// Return mutation values as percentile ranks between 0 and 100 within each run, so that
+    /// values from different cities can be shaded on a common scale. Tied values share the
+    /// average of their ranks.
+    Rank,
+    /// Return mutation values as normal scores (rankits), by mapping the percentile rank of each
+    /// value within each run through the quantile function of the standard normal distribution.
+    /// Results have approximately zero mean and unit variance, regardless of the distribution of
+    /// the raw values.
+    NormalScore,
+}
+
+/// Variables which are inverted by default, along with the constants from which their values are
+/// subtracted.
+pub const INVERT_VARS: [(&str, f64); 2] = [("bike_index", 1.0), ("natural", 1.0)];
+
+/// Transform input values according to specified schema for each input variable. Variables in
+/// `INVERT_VARS` are inverted. Use `transform_invert_values_with` to specify different variables.
 ///
 /// # Arguments
 ///
@@ -31,13 +54,53 @@ use std::collections::HashMap;
 /// assert_eq!(result[(0, 1)], 0.3);
 /// assert_eq!(result[(1, 1)], 0.4);
 pub fn transform_invert_values(values: &mut DMatrix<f64>, varname: &str) {
+    let invert_vars: Vec<(String, f64)> = INVERT_VARS
+        .iter()
+        .map(|&(v, c)| (v.to_string(), c))
+        .collect();
+    transform_invert_values_with(values, varname, &invert_vars);
+}
+
+/// Invert the first column of `values` if `varname` is one of `invert_vars`, as for
+/// `transform_invert_values`.
+///
+/// Inversion replaces each value, `x`, with `c - x`, for the constant, `c`, specified for that
+/// variable. This is used for variables for which higher values are worse, so that after
+/// inversion higher values are always better. Positive mutations then always represent
+/// improvements, and mutations of different variables may be interpreted consistently.
+///
+/// # Arguments
+///
+/// * `values` - Matrix the first column of which is to be transformed.
+/// * `varname` - Name of the variable represented by the first column of `values`.
+/// * `invert_vars` - Names of variables to be inverted, and the constant from which values of
+///   each are subtracted.
+///
+/// # Panics
+///
+/// This function will panic if `values` is empty.
+///
+/// # Example
+///
+/// ```
+/// use nalgebra::DMatrix;
+/// use uamutations::transform::transform_invert_values_with;
+/// let mut values = DMatrix::from_vec(2, 1, vec![10.0, 40.0]);
+/// let invert_vars = vec![("transport".to_string(), 100.0)];
+/// transform_invert_values_with(&mut values, "transport", &invert_vars);
+/// assert_eq!(values.as_slice(), &[90.0, 60.0]);
+/// ```
+pub fn transform_invert_values_with(
+    values: &mut DMatrix<f64>,
+    varname: &str,
+    invert_vars: &[(String, f64)],
+) {
     assert!(!values.is_empty(), "values must not be empty");
 
     let mut values_ref_var: Vec<f64> = values.column(0).iter().cloned().collect();
 
-    let mut lookup_table: HashMap<&str, f64> = HashMap::new();
-    lookup_table.insert("bike_index", 1.0);
-    lookup_table.insert("natural", 1.0);
+    let lookup_table: HashMap<&str, f64> =
+        invert_vars.iter().map(|(v, c)| (v.as_str(), *c)).collect();
 
     if let Some(&value) = lookup_table.get(varname) {
         for val in &mut values_ref_var {
@@ -49,9 +112,63 @@ pub fn transform_invert_values(values: &mut DMatrix<f64>, varname: &str) {
     values.set_column(0, &new_col);
 }
 
+/// Transform aggregated mutation values according to `transform`.
+///
+/// # Arguments
+///
+/// * `sums` - Aggregated mutation values as returned from `uamutate`. Only the absolute and
+///   relative differences in the third and fourth columns are transformed; original and mutated
+///   values are left unchanged.
+/// * `transform` - The `OutputTransform` to apply. Only non-`NaN` values are ranked or scored;
+///   `NaN` values, such as those of groups with missing values, are left as `NaN`.
+///
+/// # Example
+///
+/// ```
+/// use nalgebra::DMatrix;
+/// use uamutations::transform::{transform_output, OutputTransform};
+/// let mut sums = DMatrix::from_column_slice(3, 4, &[
+///     1.0, 2.0, 3.0, 1.0, 2.0, 3.0, 0.5, -0.1, 0.2, 0.5, -0.05, 0.1,
+/// ]);
+/// transform_output(&mut sums, OutputTransform::Rank);
+/// assert_eq!(sums.column(2).as_slice(), &[100.0, 0.0, 50.0]);
+/// ```
+pub fn transform_output(sums: &mut DMatrix<f64>, transform: OutputTransform) {
+    match transform {
+        OutputTransform::Raw => {}
+        OutputTransform::Rank => {
+            for j in 2..sums.ncols() {
+                let col: Vec<f64> = sums.column(j).iter().cloned().collect();
+                let denom = (count_non_nan(&col).max(2) - 1) as f64;
+                for (i, r) in stats::ranks(&col).iter().enumerate() {
+                    sums[(i, j)] = 100.0 * (r - 1.0) / denom;
+                }
+            }
+        }
+        OutputTransform::NormalScore => {
+            for j in 2..sums.ncols() {
+                let col: Vec<f64> = sums.column(j).iter().cloned().collect();
+                let n = count_non_nan(&col) as f64;
+                for (i, r) in stats::ranks(&col).iter().enumerate() {
+                    sums[(i, j)] = if r.is_nan() {
+                        f64::NAN
+                    } else {
+                        stats::normal_quantile((r - 0.5) / n)
+                    };
+                }
+            }
+        }
+    }
+}
+
+fn count_non_nan(vals: &[f64]) -> usize {
+    vals.iter().filter(|v| !v.is_nan()).count()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::utils;
 
     #[test]
     fn test_transform_invert_values() {
@@ -65,4 +182,57 @@ mod tests {
         assert_eq!(values[(0, 1)], 0.3);
         assert_eq!(values[(1, 1)], 0.4);
     }
+
+    #[test]
+    fn test_transform_output_rank() {
+        let n = 7;
+        let dists: Vec<f64> = (0..n).map(|i| ((i * 5) % n) as f64 - 3.0).collect();
+        let mut sums = DMatrix::<f64>::zeros(n, 4);
+        sums.set_column(2, &DVector::from_vec(dists.clone()));
+        sums.set_column(3, &DVector::from_vec(dists));
+        let raw = sums.clone();
+
+        transform_output(&mut sums, OutputTransform::Raw);
+        assert_eq!(sums, raw);
+
+        transform_output(&mut sums, OutputTransform::Rank);
+        for j in 2..4 {
+            let mut ranks: Vec<f64> = sums.column(j).iter().map(|r| r * 6.0 / 100.0).collect();
+            ranks.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            let expected: Vec<f64> = (0..n).map(|i| i as f64).collect();
+            assert_eq!(ranks, expected);
+        }
+        // Lowest raw value has rank of zero, and highest has rank of 100:
+        assert_eq!(sums[(0, 2)], 0.0);
+        assert_eq!(sums[(4, 2)], 100.0);
+        assert_eq!(sums.columns(0, 2), raw.columns(0, 2));
+    }
+
+    #[test]
+    fn test_transform_output_normal_score() {
+        // Highly skewed raw values:
+        let n = 1000;
+        let dists: Vec<f64> = (0..n)
+            .map(|i| ((i * 37) % n) as f64)
+            .map(|x| x.powi(3))
+            .collect();
+        let mut sums = DMatrix::<f64>::zeros(n, 4);
+        sums.set_column(2, &DVector::from_vec(dists.clone()));
+        sums.set_column(3, &DVector::from_vec(dists));
+        let raw = sums.clone();
+
+        transform_output(&mut sums, OutputTransform::NormalScore);
+        for j in 2..4 {
+            let col = DVector::from_iterator(n, sums.column(j).iter().cloned());
+            let (mean, sd) = utils::mean_sd_column(&col).unwrap();
+            assert!(mean.abs() < 1e-10, "mean = {}", mean);
+            assert!((sd - 1.0).abs() < 0.01, "sd = {}", sd);
+        }
+        // Order of values is preserved:
+        let lowest = raw.column(2).imin();
+        let highest = raw.column(2).imax();
+        assert_eq!(sums.column(2).imin(), lowest);
+        assert_eq!(sums.column(2).imax(), highest);
+        assert_eq!(sums.columns(0, 2), raw.columns(0, 2));
+    }
 }
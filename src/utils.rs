@@ -1,18 +1,29 @@
-use nalgebra::{DMatrix, DVector};
+use nalgebra::{DMatrix, DVector, SymmetricEigen};
+
+use crate::calculate_dists::get_ordering_index;
+use crate::error::UaError;
+
+/// Names of variables which are log-transformed by default. These are specified in
+/// uaengine/R/ua-export.R.
+pub const LOG_VARS: [&str; 3] = ["parking", "school_dist", "intervals"];
 
 /// Convert values for selected columns of an input variable to logarithmic scales.
 ///
+/// The variables to be transformed are those named in `LOG_VARS`. Use `log_transform_vars` to
+/// specify different variables.
+///
 /// # Arguments
 ///
 /// * `values` - Matrix of values to be transformed.
+/// * `varnames` - Names of the variables in each column of `values`.
 ///
 /// # Returns
 ///
-/// Matrix of (potentially) transformed values.
+/// Boolean flag indicating whether the first column of `values` was log-transformed.
 ///
-/// # Panics
+/// # Errors
 ///
-/// This function will panic if `values` is empty.
+/// Returns `UaError::EmptyInput` if `values` is empty.
 ///
 /// # Example
 /// ```
@@ -21,18 +32,53 @@ use nalgebra::{DMatrix, DVector};
 /// let values = vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0];
 /// let mut values = DMatrix::from_vec(3, 2, values);
 /// let log_scale = log_transform(&mut values, &vec!["parking".to_string(), "school_dist".to_string()]);
-/// assert_eq!(log_scale, true);
+/// assert_eq!(log_scale, Ok(true));
 /// ```
-pub fn log_transform(values: &mut DMatrix<f64>, varnames: &[String]) -> bool {
-    assert!(!values.is_empty(), "values must not be empty");
+pub fn log_transform(values: &mut DMatrix<f64>, varnames: &[String]) -> Result<bool, UaError> {
+    let log_vars: Vec<String> = LOG_VARS.iter().map(|v| v.to_string()).collect();
+    log_transform_vars(values, varnames, &log_vars)
+}
+
+/// Convert values for specified columns of an input variable to logarithmic scales.
+///
+/// # Arguments
+///
+/// * `values` - Matrix of values to be transformed.
+/// * `varnames` - Names of the variables in each column of `values`.
+/// * `log_vars` - Names of the variables to be log-transformed.
+///
+/// # Returns
+///
+/// Boolean flag indicating whether the first column of `values` was log-transformed.
+///
+/// # Errors
+///
+/// Returns `UaError::EmptyInput` if `values` is empty.
+///
+/// # Example
+/// ```
+/// use nalgebra::DMatrix;
+/// use uamutations::utils::log_transform_vars;
+/// let mut values = DMatrix::from_vec(2, 1, vec![10.0, 100.0]);
+/// let log_vars = vec!["transport".to_string()];
+/// let log_scale = log_transform_vars(&mut values, &["transport".to_string()], &log_vars).unwrap();
+/// assert!(log_scale);
+/// assert_eq!(values[(1, 0)], 2.0);
+/// ```
+pub fn log_transform_vars(
+    values: &mut DMatrix<f64>,
+    varnames: &[String],
+    log_vars: &[String],
+) -> Result<bool, UaError> {
+    if values.is_empty() {
+        return Err(UaError::EmptyInput("values".to_string()));
+    }
 
-    // These selected variables to be log-transformed are specified in uaengine/R/ua-export.R:
-    let log_vars = ["parking", "school_dist", "intervals"];
     let epsilon = -10.; // Small constant of log10(1e-10) to avoid NaN from log(<= 0)
 
     let mut log_scale = false;
     for (i, var) in varnames.iter().enumerate() {
-        if log_vars.contains(&var.as_str()) {
+        if log_vars.contains(var) {
             if i == 0 {
                 log_scale = true;
             }
@@ -43,7 +89,7 @@ pub fn log_transform(values: &mut DMatrix<f64>, varnames: &[String]) -> bool {
         }
     }
 
-    log_scale
+    Ok(log_scale)
 }
 
 /// Calcualte mean and standard deviation of first column of input `values` in DMatrix format.
@@ -51,11 +97,11 @@ pub fn log_transform(values: &mut DMatrix<f64>, varnames: &[String]) -> bool {
 /// # Arguments
 ///
 /// * `values` - An Array2 object from which mean and SD values are calcualted from the first
-/// column.
+///   column.
 ///
-/// # Panics
+/// # Errors
 ///
-/// This function will panic if `values` is empty.
+/// Returns `UaError::EmptyInput` if `values` is empty.
 ///
 /// # Returns
 ///
@@ -66,12 +112,14 @@ pub fn log_transform(values: &mut DMatrix<f64>, varnames: &[String]) -> bool {
 /// use nalgebra::DMatrix;
 /// use uamutations::utils::mean_sd_dmat;
 /// let data = DMatrix::from_vec(5, 1, vec![1.0, 2.0, 3.0, 4.0, 5.0]);
-/// let result = mean_sd_dmat(&data);
+/// let result = mean_sd_dmat(&data).unwrap();
 /// assert_eq!(result.0, 3.0);
 /// assert_eq!(result.1, 1.5811388300841898);
 /// ```
-pub fn mean_sd_dmat(values: &DMatrix<f64>) -> (f64, f64) {
-    assert!(!values.is_empty(), "values must not be empty");
+pub fn mean_sd_dmat(values: &DMatrix<f64>) -> Result<(f64, f64), UaError> {
+    if values.is_empty() {
+        return Err(UaError::EmptyInput("values".to_string()));
+    }
 
     let sum_vals: f64 = values.column(0).sum();
     let sum_vals_sq: f64 = values.column(0).iter().map(|&x| x.powi(2)).sum();
@@ -79,7 +127,7 @@ pub fn mean_sd_dmat(values: &DMatrix<f64>) -> (f64, f64) {
     let mean: f64 = sum_vals / nobs;
     let sd: f64 = ((sum_vals_sq / nobs - (sum_vals / nobs).powi(2)) * nobs / (nobs - 1.0)).sqrt();
 
-    (mean, sd)
+    Ok((mean, sd))
 }
 
 /// Calculate mean and standard deviation of a column vector.
@@ -88,15 +136,17 @@ pub fn mean_sd_dmat(values: &DMatrix<f64>) -> (f64, f64) {
 ///
 /// * `column` - A column vector from which mean and SD values are calculated.
 ///
-/// # Panics
+/// # Errors
 ///
-/// This function will panic if `column` is empty.
+/// Returns `UaError::EmptyInput` if `column` is empty.
 ///
 /// # Returns
 ///
 /// Tuple of mean and standard deviation.
-pub fn mean_sd_column(column: &DVector<f64>) -> (f64, f64) {
-    assert!(!column.is_empty(), "column must not be empty");
+pub fn mean_sd_column(column: &DVector<f64>) -> Result<(f64, f64), UaError> {
+    if column.is_empty() {
+        return Err(UaError::EmptyInput("column".to_string()));
+    }
 
     let sum_vals: f64 = column.sum();
     let sum_vals_sq: f64 = column.iter().map(|&x| x.powi(2)).sum();
@@ -104,7 +154,7 @@ pub fn mean_sd_column(column: &DVector<f64>) -> (f64, f64) {
     let mean: f64 = sum_vals / nobs;
     let sd: f64 = ((sum_vals_sq / nobs - (sum_vals / nobs).powi(2)) * nobs / (nobs - 1.0)).sqrt();
 
-    (mean, sd)
+    Ok((mean, sd))
 }
 
 /// Resizes a `DMatrix` to a specified number of rows, keeping the same number of columns.
@@ -131,6 +181,193 @@ pub fn resize_matrix(matrix: &DMatrix<f64>, new_rows: usize) -> DMatrix<f64> {
     resized_matrix
 }
 
+/// Resample the rows of a `DMatrix` at equally-spaced quantiles of its first column.
+///
+/// This allows a matrix to be expanded to a larger number of rows, with each original row
+/// repeated a similar number of times, and with the distribution of the first column preserved.
+///
+/// # Arguments
+///
+/// * `matrix` - The original `DMatrix` to be resampled.
+/// * `new_rows` - The number of rows of the resampled `DMatrix`.
+///
+/// # Returns
+///
+/// A new `DMatrix` with the specified number of rows, ordered by increasing values of the first
+/// column.
+///
+/// # Example
+/// ```
+/// use nalgebra::DMatrix;
+/// use uamutations::utils::resample_by_rank;
+/// let values = DMatrix::from_vec(2, 1, vec![3.0, 1.0]);
+/// let resampled = resample_by_rank(&values, 4);
+/// assert_eq!(resampled.as_slice(), &[1.0, 1.0, 3.0, 3.0]);
+/// ```
+pub fn resample_by_rank(matrix: &DMatrix<f64>, new_rows: usize) -> DMatrix<f64> {
+    assert!(!matrix.is_empty(), "matrix must not be empty");
+
+    let ref_var: Vec<f64> = matrix.column(0).iter().cloned().collect();
+    let index_sort = get_ordering_index(&ref_var, false, false).index_sort;
+    let nrows = matrix.nrows();
+
+    DMatrix::from_fn(new_rows, matrix.ncols(), |i, j| {
+        matrix[(index_sort[i * nrows / new_rows], j)]
+    })
+}
+
+/// Collapse near-identical values into representative values, each with a multiplicity.
+///
+/// Values are sorted, and grouped into clusters in which all values lie within `epsilon` of the
+/// lowest value of that cluster. Each cluster is represented by the mean of its values, and the
+/// number of values it contains.
+///
+/// # Arguments
+///
+/// * `vals` - The values to be deduplicated.
+/// * `epsilon` - Maximal distance of any value from the lowest value of its cluster.
+///
+/// # Returns
+///
+/// A vector of (representative value, multiplicity) pairs, in increasing order of representative
+/// values. Multiplicities sum to the length of `vals`.
+///
+/// # Example
+/// ```
+/// use uamutations::utils::dedup_with_multiplicity;
+/// let vals = vec![2.0, 1.0, 2.05, 1.02, 5.0];
+/// let dedup = dedup_with_multiplicity(&vals, 0.1);
+/// assert_eq!(dedup.len(), 3);
+/// assert_eq!(dedup[1].1, 2);
+/// assert_eq!(dedup[2], (5.0, 1));
+/// ```
+pub fn dedup_with_multiplicity(vals: &[f64], epsilon: f64) -> Vec<(f64, usize)> {
+    let index_sort = get_ordering_index(vals, false, false).index_sort;
+    near_duplicate_clusters(vals, &index_sort, epsilon)
+        .iter()
+        .map(|cluster| (cluster_mean(vals, cluster), cluster.len()))
+        .collect()
+}
+
+/// Replace near-identical values in the first column of a matrix with their representative
+/// values, as calculated by `dedup_with_multiplicity`.
+///
+/// Matching entries against the modified matrix is equivalent to matching against the
+/// deduplicated representatives, with each representative used as many times as its
+/// multiplicity. This prevents many near-identical target values, such as commonly arise from
+/// discretisation, from being matched to distinct entries as if they differed.
+///
+/// # Arguments
+///
+/// * `matrix` - Matrix whose first column is to be deduplicated in place. Other columns are not
+///   modified.
+/// * `epsilon` - Maximal distance of any value from the lowest value of its cluster.
+///
+/// # Example
+/// ```
+/// use nalgebra::DMatrix;
+/// use uamutations::utils::merge_near_duplicates;
+/// let mut values = DMatrix::from_vec(4, 1, vec![3.0, 1.0, 2.9, 3.1]);
+/// merge_near_duplicates(&mut values, 0.25);
+/// assert_eq!(values.as_slice(), &[3.0, 1.0, 3.0, 3.0]);
+/// ```
+pub fn merge_near_duplicates(matrix: &mut DMatrix<f64>, epsilon: f64) {
+    let vals: Vec<f64> = matrix.column(0).iter().cloned().collect();
+    let index_sort = get_ordering_index(&vals, false, false).index_sort;
+    for cluster in near_duplicate_clusters(&vals, &index_sort, epsilon) {
+        let mean = cluster_mean(&vals, &cluster);
+        for i in cluster {
+            matrix[(i, 0)] = mean;
+        }
+    }
+}
+
+/// Indices of `vals` grouped into clusters of near-identical values, given the indices which sort
+/// `vals` in increasing order.
+fn near_duplicate_clusters(vals: &[f64], index_sort: &[usize], epsilon: f64) -> Vec<Vec<usize>> {
+    let mut clusters: Vec<Vec<usize>> = Vec::new();
+    for &i in index_sort {
+        match clusters.last_mut() {
+            Some(cluster) if vals[i] - vals[cluster[0]] <= epsilon => cluster.push(i),
+            _ => clusters.push(vec![i]),
+        }
+    }
+    clusters
+}
+
+fn cluster_mean(vals: &[f64], cluster: &[usize]) -> f64 {
+    cluster.iter().map(|&i| vals[i]).sum::<f64>() / cluster.len() as f64
+}
+
+/// Calculate principal components of a matrix, with each observation weighted by a specified
+/// weight.
+///
+/// The weighted mean and weighted covariance matrix of all columns are calculated, and the
+/// eigenvectors of that covariance matrix returned in order of decreasing eigenvalues. Equal
+/// weights give standard, unweighted principal components.
+///
+/// # Arguments
+///
+/// * `values` - Matrix of [observations, variables].
+/// * `weights` - Non-negative weight for each observation (row) of `values`.
+/// * `k` - Number of principal components to return.
+///
+/// # Panics
+///
+/// This function will panic if `values` is empty, if `weights` does not have one non-negative
+/// value for each row of `values`, if all weights are zero, or if `k` is greater than the number
+/// of columns of `values`.
+///
+/// # Returns
+///
+/// Matrix of [variables, k], each column of which holds one principal component.
+///
+/// # Example
+/// ```
+/// use nalgebra::DMatrix;
+/// use uamutations::utils::pca_weighted;
+/// let values = DMatrix::from_vec(4, 2, vec![1.0, 2.0, 3.0, 4.0, 1.1, 1.9, 3.2, 3.9]);
+/// let pcs = pca_weighted(&values, &[1.0; 4], 1);
+/// assert_eq!(pcs.shape(), (2, 1));
+/// ```
+pub fn pca_weighted(values: &DMatrix<f64>, weights: &[f64], k: usize) -> DMatrix<f64> {
+    assert!(!values.is_empty(), "values must not be empty");
+    assert!(
+        weights.len() == values.nrows(),
+        "weights must have same length as number of rows of values"
+    );
+    assert!(
+        weights.iter().all(|&w| w >= 0.0),
+        "weights must not be negative"
+    );
+    let sum_weights: f64 = weights.iter().sum();
+    assert!(sum_weights > 0.0, "weights must not all be zero");
+    assert!(
+        k <= values.ncols(),
+        "k must not be greater than the number of columns of values"
+    );
+
+    let w = DVector::from_column_slice(weights);
+    let means: Vec<f64> = values
+        .column_iter()
+        .map(|col| col.dot(&w) / sum_weights)
+        .collect();
+    let centred = DMatrix::from_fn(values.nrows(), values.ncols(), |i, j| {
+        (values[(i, j)] - means[j]) * weights[i].sqrt()
+    });
+    let covariance = centred.transpose() * centred / sum_weights;
+
+    let eigen = SymmetricEigen::new(covariance);
+    let mut order: Vec<usize> = (0..eigen.eigenvalues.len()).collect();
+    order.sort_by(|&a, &b| {
+        eigen.eigenvalues[b]
+            .partial_cmp(&eigen.eigenvalues[a])
+            .unwrap()
+    });
+
+    DMatrix::from_fn(values.ncols(), k, |i, j| eigen.eigenvectors[(i, order[j])])
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -142,7 +379,8 @@ mod tests {
         let log_scale = log_transform(
             &mut values,
             &["parking".to_string(), "school_dist".to_string()],
-        );
+        )
+        .unwrap();
         assert!(log_scale);
 
         assert_eq!(
@@ -155,11 +393,107 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_log_transform_vars() {
+        let mut values = DMatrix::from_vec(2, 2, vec![10.0, 100.0, 10.0, 100.0]);
+        let varnames = vec!["transport".to_string(), "parking".to_string()];
+        // Non-default variable is transformed, while default variable is not:
+        let log_scale =
+            log_transform_vars(&mut values, &varnames, &["transport".to_string()]).unwrap();
+        assert!(log_scale);
+        assert_eq!(values.as_slice(), &[1.0, 2.0, 10.0, 100.0]);
+    }
+
     #[test]
     fn test_mean_sd_dmat() {
         let data = DMatrix::from_vec(5, 1, vec![1.0, 2.0, 3.0, 4.0, 5.0]);
-        let (mean, sd) = mean_sd_dmat(&data);
+        let (mean, sd) = mean_sd_dmat(&data).unwrap();
         assert_eq!(mean, 3.);
         assert_abs_diff_eq!(sd, 1.581138, epsilon = 1e-6);
     }
+
+    #[test]
+    fn test_empty_input_errors() {
+        let mut empty = DMatrix::<f64>::zeros(0, 2);
+        let varnames = vec!["parking".to_string(), "school_dist".to_string()];
+        let err = Err(UaError::EmptyInput("values".to_string()));
+        assert_eq!(log_transform(&mut empty, &varnames), err);
+        assert_eq!(log_transform_vars(&mut empty, &varnames, &varnames), err);
+        assert_eq!(
+            mean_sd_dmat(&empty),
+            Err(UaError::EmptyInput("values".to_string()))
+        );
+        assert_eq!(
+            mean_sd_column(&DVector::zeros(0)),
+            Err(UaError::EmptyInput("column".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_pca_weighted() {
+        // One cluster spread along the first axis, and one along the second:
+        let x = vec![-2.0, -1.0, 1.0, 2.0, 0.0, 0.0, 0.0, 0.0];
+        let y = vec![0.0, 0.0, 0.0, 0.0, -1.0, -0.5, 0.5, 1.0];
+        let values = DMatrix::from_vec(8, 2, [x, y].concat());
+
+        let pcs = pca_weighted(&values, &[1.0; 8], 1);
+        // Unweighted first component aligns with the first axis:
+        assert!(pcs[(0, 0)].abs() > pcs[(1, 0)].abs());
+
+        let weights = vec![1.0, 1.0, 1.0, 1.0, 100.0, 100.0, 100.0, 100.0];
+        let pcs_w = pca_weighted(&values, &weights, 1);
+        // Heavily weighting the second cluster rotates first component towards its spread:
+        assert!(pcs_w[(1, 0)].abs() > pcs[(1, 0)].abs());
+        assert!(pcs_w[(1, 0)].abs() > pcs_w[(0, 0)].abs());
+    }
+
+    #[test]
+    #[should_panic(expected = "weights must not be negative")]
+    fn test_pca_weighted_negative_weights() {
+        let values = DMatrix::from_vec(2, 1, vec![1.0, 2.0]);
+        pca_weighted(&values, &[1.0, -1.0], 1);
+    }
+
+    #[test]
+    fn test_dedup_with_multiplicity() {
+        let vals = vec![1.0, 1.1, 1.2, 1.35, 4.0, 4.0, 7.0];
+        let dedup = dedup_with_multiplicity(&vals, 0.25);
+        assert_eq!(dedup.len(), 4);
+        assert_abs_diff_eq!(dedup[0].0, 1.1, epsilon = 1e-10);
+        assert_eq!(dedup[0].1, 3);
+        assert_eq!(dedup[1], (1.35, 1));
+        assert_eq!(dedup[2], (4.0, 2));
+        assert_eq!(dedup[3], (7.0, 1));
+        assert_eq!(dedup.iter().map(|d| d.1).sum::<usize>(), vals.len());
+
+        // Zero epsilon only merges identical values:
+        assert_eq!(dedup_with_multiplicity(&vals, 0.0).len(), 6);
+    }
+
+    #[test]
+    fn test_merge_near_duplicates_tail() {
+        use crate::calculate_dists::calculate_dists;
+
+        let values1 =
+            DMatrix::from_vec(5, 2, vec![1.0, 2.0, 3.0, 4.0, 5.0, 1.0, 2.0, 3.0, 4.0, 5.0]);
+        let mut values2 = DMatrix::from_vec(
+            5,
+            2,
+            vec![1.0, 2.0, 10.2, 3.0, 9.8, 1.0, 2.0, 3.0, 4.0, 5.0],
+        );
+
+        let dists = calculate_dists(&values1, &values2, &false, 0).unwrap();
+        assert_abs_diff_eq!(dists[(3, 0)], 5.8, epsilon = 1e-10);
+        assert_abs_diff_eq!(dists[(4, 0)], 5.2, epsilon = 1e-10);
+
+        // Both tail targets are merged into one representative, matched to both tail entries:
+        merge_near_duplicates(&mut values2, 0.5);
+        assert_eq!(values2.column(1).as_slice(), &[1.0, 2.0, 3.0, 4.0, 5.0]);
+        let dists_merged = calculate_dists(&values1, &values2, &false, 0).unwrap();
+        assert_abs_diff_eq!(dists_merged[(3, 0)], 6.0, epsilon = 1e-10);
+        assert_abs_diff_eq!(dists_merged[(4, 0)], 5.0, epsilon = 1e-10);
+        for i in 0..3 {
+            assert_eq!(dists_merged[(i, 0)], dists[(i, 0)]);
+        }
+    }
 }
@@ -0,0 +1,108 @@
+//! End-to-end regression tests comparing full output of `uamutate` on the `test_resources` data
+//! against committed golden files.
+//!
+//! Golden files are written with `read_write_file::write_to`. After any intended change to the
+//! algorithm, they can be regenerated by running these tests with `UPDATE_GOLDEN=1`, and the
+//! changes to the golden files then reviewed along with the code.
+
+use uamutations::calculate_dists::GreedyMatcher;
+use uamutations::read_write_file::{write_to, JsonReader};
+use uamutations::{uamutate, MutationConfig};
+
+/// Absolute tolerance for comparisons between computed and golden values.
+const TOLERANCE: f64 = 1e-8;
+
+fn run_uamutate(varnames: &[&str], nentries: usize) -> nalgebra::DMatrix<f64> {
+    let reader1 = JsonReader::Path("./test_resources/dat1.json".into());
+    let reader2 = JsonReader::Path("./test_resources/dat2.json".into());
+    let varnames: Vec<String> = varnames.iter().map(|v| v.to_string()).collect();
+    uamutate(
+        &reader1,
+        &reader2,
+        &varnames,
+        nentries,
+        &GreedyMatcher,
+        &MutationConfig::default(),
+    )
+    .unwrap()
+}
+
+/// Parse text written by `write_to` into rows of values, skipping the header line.
+fn parse_output(text: &str) -> Vec<Vec<f64>> {
+    text.lines()
+        .skip(1)
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            line.split(',')
+                .map(|x| x.trim().parse::<f64>().unwrap())
+                .collect()
+        })
+        .collect()
+}
+
+/// Compare `sums` with the golden file at `path`, with all values required to be within
+/// `tolerance`, and `NaN` values required to be in identical positions. If the `UPDATE_GOLDEN`
+/// environment variable is set, the golden file is instead overwritten with `sums`.
+fn assert_matches_golden(sums: &nalgebra::DMatrix<f64>, path: &str, tolerance: f64) {
+    let mut text = Vec::new();
+    write_to(&mut text, sums).unwrap();
+
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::write(path, &text).unwrap();
+        return;
+    }
+
+    let golden = std::fs::read_to_string(path)
+        .unwrap_or_else(|e| panic!("unable to read golden file {}: {}", path, e));
+    let expected = parse_output(&golden);
+    let actual = parse_output(std::str::from_utf8(&text).unwrap());
+
+    assert_eq!(
+        actual.len(),
+        expected.len(),
+        "number of rows differs from golden file {}",
+        path
+    );
+    for (i, (a_row, e_row)) in actual.iter().zip(&expected).enumerate() {
+        assert_eq!(
+            a_row.len(),
+            e_row.len(),
+            "row {} has wrong number of columns",
+            i
+        );
+        for (j, (&a, &e)) in a_row.iter().zip(e_row).enumerate() {
+            let matches = if e.is_nan() {
+                a.is_nan()
+            } else {
+                (a - e).abs() <= tolerance
+            };
+            assert!(
+                matches,
+                "value at ({}, {}) is {} but golden file {} has {}",
+                i, j, a, path, e
+            );
+        }
+    }
+}
+
+#[test]
+fn test_golden_single_variable() {
+    let sums = run_uamutate(&["bike_index"], 1000);
+    assert_matches_golden(&sums, "./test_resources/golden/bike_index.csv", TOLERANCE);
+}
+
+#[test]
+fn test_golden_extra_variables() {
+    let sums = run_uamutate(&["bike_index", "natural", "social_index"], 1000);
+    assert_matches_golden(
+        &sums,
+        "./test_resources/golden/bike_index_natural_social.csv",
+        TOLERANCE,
+    );
+}
+
+#[test]
+fn test_golden_is_deterministic() {
+    let varnames = ["bike_index", "natural", "social_index"];
+    assert_eq!(run_uamutate(&varnames, 1000), run_uamutate(&varnames, 1000));
+}